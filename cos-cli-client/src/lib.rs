@@ -0,0 +1,378 @@
+//! Async client for the JSON-RPC server of `cos-cli serve`.
+//!
+//! ```no_run
+//! use cos_cli_client::{Client, MoveParams, SubscribeParams};
+//! use futures::StreamExt;
+//!
+//! # async fn example() -> Result<(), cos_cli_client::Error> {
+//! let client = Client::connect_default().await?;
+//! let info = client.info().await?;
+//! println!("{} apps", info.apps.len());
+//!
+//! client
+//!     .move_window(MoveParams {
+//!         app_id: Some("firefox".into()),
+//!         workspace: 1.into(),
+//!         ..Default::default()
+//!     })
+//!     .await?;
+//!
+//! let mut notifications = client.notifications();
+//! client.subscribe(SubscribeParams::default()).await?;
+//! while let Some(notification) = notifications.next().await {
+//!     for event in notification.events {
+//!         println!("{event:?}");
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::process::{Child, Command};
+use tokio::sync::{broadcast, oneshot};
+
+pub use cos_cli::client::{
+    ActivateParams, ActivateResult, ActivateWsParams, ActivateWsResult, CloseParams, CloseResult,
+    InfoParams, MoveParams, MoveResult, MoveTarget, ProtocolVersion, StateParams, StateResult,
+    VersionResult,
+};
+pub use cos_cli::config::Target;
+pub use cos_cli::confirm::{Outcome, WindowOutcome};
+pub use cos_cli::cycle::CycleOrder;
+pub use cos_cli::error::ErrorObject;
+pub use cos_cli::events::{
+    Event, EventKind, Notification, SubscribeParams, SubscribeResult, UnsubscribeParams,
+    UnsubscribeResult,
+};
+pub use cos_cli::history::{HistoryParams, HistoryResult, JsonFocus};
+pub use cos_cli::operation::{
+    BatchItem, BatchParams, BatchResult, BatchStatus, OnError, Operation,
+};
+pub use cos_cli::{
+    JsonApp, JsonInfo, JsonOutput, JsonOutputRef, JsonSeat, JsonWorkspace, JsonWorkspaceGroup,
+    JsonWorkspaceRef, State,
+};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    /// The server answered with a JSON-RPC error object. See JSONRPC.md
+    /// for the codes.
+    Rpc {
+        code: i64,
+        message: String,
+        data: Option<Value>,
+    },
+    /// The server closed the connection before answering.
+    Closed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Json(e) => write!(f, "Invalid message: {e}"),
+            Error::Rpc { code, message, .. } => write!(f, "{message} ({code})"),
+            Error::Closed => f.write_str("Server closed the connection"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+/// Requests waiting for their response, by id. `None` once the server
+/// closed the connection.
+type Pending = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<Result<Value, Error>>>>>>;
+
+/// Removes the pending entry of a request when [`Client::call`] returns or
+/// its future is dropped before the response arrived.
+struct PendingGuard<'a> {
+    pending: &'a Pending,
+    id: u64,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+            pending.remove(&self.id);
+        }
+    }
+}
+
+/// A connection to a cos-cli JSON-RPC server. Requests may be issued
+/// concurrently from several tasks.
+pub struct Client {
+    writer: tokio::sync::Mutex<Box<dyn AsyncWrite + Send + Unpin>>,
+    pending: Pending,
+    next_id: AtomicU64,
+    notifications: broadcast::Sender<Notification>,
+    reader: tokio::task::JoinHandle<()>,
+    /// Server started by [`Client::spawn`], killed when the client is dropped
+    _child: Option<Child>,
+}
+
+impl Client {
+    /// Connects to a server started with `cos-cli serve --socket <path>`.
+    pub async fn connect(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let (reader, writer) = UnixStream::connect(path).await?.into_split();
+        Ok(Self::new(reader, writer, None))
+    }
+
+    /// Connects to the server on `$COS_CLI_SOCKET`, or
+    /// `$XDG_RUNTIME_DIR/cos-cli.sock`.
+    pub async fn connect_default() -> Result<Self, Error> {
+        let path = cos_cli::default_socket_path().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Neither COS_CLI_SOCKET nor XDG_RUNTIME_DIR is set",
+            )
+        })?;
+        Self::connect(path).await
+    }
+
+    /// Starts `cos-cli serve` from `PATH` and talks to it over stdio.
+    pub fn spawn() -> Result<Self, Error> {
+        Self::spawn_with("cos-cli")
+    }
+
+    /// Starts `<program> serve` and talks to it over stdio.
+    pub fn spawn_with(program: impl AsRef<OsStr>) -> Result<Self, Error> {
+        let mut child = Command::new(program)
+            .arg("serve")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(Error::Closed);
+        };
+        Ok(Self::new(stdout, stdin, Some(child)))
+    }
+
+    fn new(
+        reader: impl AsyncRead + Send + Unpin + 'static,
+        writer: impl AsyncWrite + Send + Unpin + 'static,
+        child: Option<Child>,
+    ) -> Self {
+        let pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        let (notifications, _) = broadcast::channel(256);
+        let reader = tokio::task::spawn(read_messages(
+            reader,
+            pending.clone(),
+            notifications.clone(),
+        ));
+        Self {
+            writer: tokio::sync::Mutex::new(Box::new(writer)),
+            pending,
+            next_id: AtomicU64::new(1),
+            notifications,
+            reader,
+            _child: child,
+        }
+    }
+
+    /// Sends a request and waits for its result.
+    pub async fn call<R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: impl Serialize,
+    ) -> Result<R, Error> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (response_tx, response_rx) = oneshot::channel();
+        match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(id, response_tx),
+            None => return Err(Error::Closed),
+        };
+        let _guard = PendingGuard {
+            pending: &self.pending,
+            id,
+        };
+
+        let mut line = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": id,
+        })
+        .to_string();
+        line.push('\n');
+        let written = {
+            let mut writer = self.writer.lock().await;
+            match writer.write_all(line.as_bytes()).await {
+                Ok(()) => writer.flush().await,
+                Err(e) => Err(e),
+            }
+        };
+        written?;
+
+        let result = response_rx.await.map_err(|_| Error::Closed)??;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    pub async fn info(&self) -> Result<JsonInfo, Error> {
+        self.call("info", InfoParams::default()).await
+    }
+
+    pub async fn move_window(&self, params: MoveParams) -> Result<MoveResult, Error> {
+        self.call("move", params).await
+    }
+
+    pub async fn activate(&self, params: ActivateParams) -> Result<ActivateResult, Error> {
+        self.call("activate", params).await
+    }
+
+    pub async fn activate_workspace(
+        &self,
+        params: ActivateWsParams,
+    ) -> Result<ActivateWsResult, Error> {
+        self.call("ws_activate", params).await
+    }
+
+    pub async fn set_state(&self, params: StateParams) -> Result<StateResult, Error> {
+        self.call("state", params).await
+    }
+
+    pub async fn close(&self, params: CloseParams) -> Result<CloseResult, Error> {
+        self.call("close", params).await
+    }
+
+    /// The open windows, most recently focused first.
+    pub async fn history(&self, params: HistoryParams) -> Result<HistoryResult, Error> {
+        self.call("history", params).await
+    }
+
+    /// Runs several operations against one snapshot and flushes their
+    /// requests at once.
+    pub async fn batch(&self, params: BatchParams) -> Result<BatchResult, Error> {
+        self.call("batch", params).await
+    }
+
+    /// Starts receiving the matching events on [`Client::notifications`].
+    /// Returns the subscription id.
+    pub async fn subscribe(&self, params: SubscribeParams) -> Result<u64, Error> {
+        let subscribed: SubscribeResult = self.call("subscribe", params).await?;
+        Ok(subscribed.subscription)
+    }
+
+    /// Removes one subscription, or all when `subscription` is `None`.
+    /// Returns the number of removed subscriptions.
+    pub async fn unsubscribe(&self, subscription: Option<u64>) -> Result<usize, Error> {
+        let unsubscribed: UnsubscribeResult = self
+            .call("unsubscribe", UnsubscribeParams { subscription })
+            .await?;
+        Ok(unsubscribed.removed)
+    }
+
+    pub async fn ping(&self) -> Result<(), Error> {
+        let _: String = self.call("ping", Value::Null).await?;
+        Ok(())
+    }
+
+    pub async fn version(&self) -> Result<VersionResult, Error> {
+        self.call("version", Value::Null).await
+    }
+
+    /// Stops the server. Requests other clients still have pending fail
+    /// with a `Disconnected` error.
+    pub async fn shutdown(&self) -> Result<(), Error> {
+        let _: Value = self.call("shutdown", Value::Null).await?;
+        Ok(())
+    }
+
+    /// The OpenRPC document describing the server's API.
+    pub async fn discover(&self) -> Result<Value, Error> {
+        self.call("rpc.discover", Value::Null).await
+    }
+
+    /// `state_change` notifications received from now on. Notifications
+    /// the stream is too slow to take are skipped; `seq` shows the gap.
+    /// `lagged` is set when the server dropped changes for this client.
+    pub fn notifications(&self) -> BoxStream<'static, Notification> {
+        let rx = self.notifications.subscribe();
+        futures::stream::unfold(rx, |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(notification) => return Some((notification, rx)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Routes responses to their pending requests and publishes notifications.
+async fn read_messages(
+    reader: impl AsyncRead + Unpin,
+    pending: Pending,
+    notifications: broadcast::Sender<Notification>,
+) {
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        if message.get("method").and_then(Value::as_str) == Some("state_change") {
+            if let Ok(notification) = serde_json::from_value(message["params"].clone()) {
+                let _ = notifications.send(notification);
+            }
+            continue;
+        }
+        let Some(id) = message.get("id").and_then(Value::as_u64) else {
+            continue;
+        };
+        let Some(response_tx) = pending
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|pending| pending.remove(&id))
+        else {
+            continue;
+        };
+        let response = match message.get("error") {
+            Some(error) => Err(Error::Rpc {
+                code: error["code"].as_i64().unwrap_or_default(),
+                message: error["message"].as_str().unwrap_or_default().to_string(),
+                data: error.get("data").cloned(),
+            }),
+            None => Ok(message.get("result").cloned().unwrap_or_default()),
+        };
+        let _ = response_tx.send(response);
+    }
+    // Requests still waiting and later ones get `Error::Closed`
+    pending.lock().unwrap().take();
+}
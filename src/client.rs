@@ -0,0 +1,1214 @@
+use rustix::event::{PollFd, PollFlags, Timespec};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+use cosmic_protocols::toplevel_management::v1::client::zcosmic_toplevel_manager_v1;
+use wayland_client::backend::WaylandError;
+use wayland_client::{Connection, EventQueue, Proxy};
+use wayland_protocols::ext::workspace::v1::client::ext_workspace_handle_v1;
+
+use crate::config::{Config, Target};
+use crate::confirm::{self, Expect, Outcome, Watch, WindowOutcome};
+use crate::cycle::{CycleOrder, MruCycle};
+use crate::error::ClientError;
+use crate::events::StateChange;
+use crate::history::{HistoryParams, HistoryResult};
+use crate::{
+    App, AppState, JsonApp, JsonInfo, JsonOutput, JsonWorkspaceGroup, NamedHandle, State, dispatch,
+};
+
+// ---------------------------------------------------------------------------
+// Parameter types
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct InfoParams {
+    /// Associate workspace groups with outputs by moving a window around
+    #[serde(default)]
+    pub discover_wg_output: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct MoveParams {
+    /// Application ID (partial match, case-insensitive) or alias from the config
+    #[serde(default)]
+    pub app_id: Option<String>,
+    /// Application index from `info`
+    #[serde(default)]
+    pub index: Option<usize>,
+    /// Window id from `info`, which stays the same while the window is open
+    #[serde(default)]
+    pub id: Option<u32>,
+    /// Target workspace index, label from the config or workspace name
+    pub workspace: Target,
+    /// Workspace group index (default: from the config)
+    #[serde(default)]
+    pub workspace_group: Option<usize>,
+    /// Output index (default: from the config, or 0)
+    #[serde(default)]
+    pub output_index: Option<usize>,
+    /// Seconds to wait for the app to appear
+    #[serde(default)]
+    pub wait: Option<u64>,
+    /// Wait until the compositor reflects the change
+    #[serde(default)]
+    pub confirm: bool,
+    /// Seconds `confirm` waits (default: 5), only valid with `confirm`
+    #[serde(default)]
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ActivateParams {
+    /// Application index from `info`
+    #[serde(default)]
+    pub index: Option<usize>,
+    /// Window id from `info`, which stays the same while the window is open
+    #[serde(default)]
+    pub id: Option<u32>,
+    /// The most recently focused window that does not have the focus
+    #[serde(default)]
+    pub previous: bool,
+    /// The window at this place in the focus history, 0 being the most
+    /// recently focused
+    #[serde(default)]
+    pub mru: Option<usize>,
+    /// The window after the focused one among those selected by `app_id`,
+    /// `same_app` and `workspace`
+    #[serde(default)]
+    pub cycle: bool,
+    /// With `cycle`: windows of this application ID (partial match,
+    /// case-insensitive) or alias from the config
+    #[serde(default)]
+    pub app_id: Option<String>,
+    /// With `cycle`: windows with the app ID of the focused window
+    #[serde(default)]
+    pub same_app: bool,
+    /// With `cycle`: windows on this workspace (index, label or name), or
+    /// `current` for the workspace of the focused window
+    #[serde(default)]
+    pub workspace: Option<Target>,
+    /// With `cycle`: the order to step through the windows in
+    #[serde(default)]
+    pub order: CycleOrder,
+    /// With `cycle`: step backwards
+    #[serde(default)]
+    pub reverse: bool,
+    /// With `cycle`: include minimized windows
+    #[serde(default)]
+    pub minimized: bool,
+    /// Seat index (default: from the config, or 0)
+    #[serde(default)]
+    pub seat: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct StateParams {
+    /// Application ID (partial match, case-insensitive) or alias from the config
+    #[serde(default)]
+    pub app_id: Option<String>,
+    /// Application index from `info`
+    #[serde(default)]
+    pub index: Option<usize>,
+    /// Window id from `info`, which stays the same while the window is open
+    #[serde(default)]
+    pub id: Option<u32>,
+    /// Seconds to wait for the app to appear
+    #[serde(default)]
+    pub wait: Option<u64>,
+    #[serde(default)]
+    pub maximize: bool,
+    #[serde(default)]
+    pub unmaximize: bool,
+    #[serde(default)]
+    pub minimize: bool,
+    #[serde(default)]
+    pub unminimize: bool,
+    #[serde(default)]
+    pub fullscreen: bool,
+    #[serde(default)]
+    pub unfullscreen: bool,
+    #[serde(default)]
+    pub sticky: bool,
+    #[serde(default)]
+    pub unsticky: bool,
+    /// Wait until the compositor reflects the change
+    #[serde(default)]
+    pub confirm: bool,
+    /// Seconds `confirm` waits (default: 5), only valid with `confirm`
+    #[serde(default)]
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ActivateWsParams {
+    /// Workspace index, label from the config or workspace name
+    pub workspace: Target,
+    /// Workspace group index (default: from the config)
+    #[serde(default)]
+    pub workspace_group: Option<usize>,
+    /// Wait until the compositor reflects the change
+    #[serde(default)]
+    pub confirm: bool,
+    /// Seconds `confirm` waits (default: 5), only valid with `confirm`
+    #[serde(default)]
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct CloseParams {
+    /// Application ID (partial match, case-insensitive) or alias from the config
+    #[serde(default)]
+    pub app_id: Option<String>,
+    /// Application index from `info`
+    #[serde(default)]
+    pub index: Option<usize>,
+    /// Window id from `info`, which stays the same while the window is open
+    #[serde(default)]
+    pub id: Option<u32>,
+    /// Seconds to wait for the app to appear
+    #[serde(default)]
+    pub wait: Option<u64>,
+    /// Wait until the compositor reflects the change
+    #[serde(default)]
+    pub confirm: bool,
+    /// Seconds `confirm` waits (default: 5), only valid with `confirm`
+    #[serde(default)]
+    pub timeout: Option<u64>,
+}
+
+// ---------------------------------------------------------------------------
+// Result types
+// ---------------------------------------------------------------------------
+
+/// Where windows were moved to.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MoveTarget {
+    pub workspace_group: usize,
+    pub workspace: usize,
+    pub workspace_name: String,
+    pub output_index: usize,
+    pub output: String,
+}
+
+/// `windows` are snapshots taken before the requests were sent, so a client
+/// can undo the change. `requests` are the protocol requests sent per window.
+/// `confirmations` are only present when the request asked to `confirm`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MoveResult {
+    pub windows: Vec<JsonApp>,
+    pub target: MoveTarget,
+    pub requests: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmations: Option<Vec<WindowOutcome>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ActivateResult {
+    pub index: usize,
+    pub seat: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ActivateWsResult {
+    pub workspace_group: usize,
+    pub workspace: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<Outcome>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct StateResult {
+    pub windows: Vec<JsonApp>,
+    pub requests: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmations: Option<Vec<WindowOutcome>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CloseResult {
+    pub windows: Vec<JsonApp>,
+    pub requests: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmations: Option<Vec<WindowOutcome>>,
+}
+
+/// A wayland protocol bound on the compositor connection.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ProtocolVersion {
+    pub interface: String,
+    pub version: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct VersionResult {
+    /// cos-cli version
+    pub version: String,
+    pub protocols: Vec<ProtocolVersion>,
+}
+
+// ---------------------------------------------------------------------------
+// Client
+// ---------------------------------------------------------------------------
+
+/// A connection to the compositor together with everything discovered
+/// through it.
+///
+/// The state is only updated while the client dispatches wayland events:
+/// during [`CosmicClient::dispatch`] and the operations that wait for the
+/// compositor (`wait` and `confirm`).
+pub struct CosmicClient {
+    connection: Connection,
+    event_queue: EventQueue<AppState>,
+    state: AppState,
+    config: Config,
+    pub(crate) requests: Requests,
+    pub(crate) confirming: Confirming,
+    pub(crate) mru_cycle: MruCycle,
+}
+
+/// How an operation waits for the compositor to reflect its `confirm`.
+#[derive(Debug, Default)]
+pub(crate) enum Confirming {
+    /// Dispatches until the outcome is known
+    #[default]
+    Wait,
+    /// Returns at once and leaves what to wait for here, for
+    /// [`CosmicClient::start`]
+    Defer(Option<(Watch, Duration)>),
+}
+
+/// What happens to the protocol requests an operation sends.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) enum Requests {
+    /// Flushed to the compositor when the operation is done
+    #[default]
+    Flush,
+    /// Left in the connection buffer until the batch flushes
+    Queue,
+    /// Not sent at all, the operation only resolves its params
+    Discard,
+}
+
+impl CosmicClient {
+    /// Connects to the compositor from the environment and waits until the
+    /// initial burst of apps, workspaces, outputs and seats settled.
+    pub fn connect() -> Result<Self, ClientError> {
+        let connection = Connection::connect_to_env().map_err(|e| ClientError::Disconnected {
+            reason: e.to_string(),
+        })?;
+        let mut event_queue = connection.new_event_queue();
+        let qh = event_queue.handle();
+
+        let mut state = AppState::new();
+        let registry = connection.display().get_registry(&qh, ());
+
+        event_queue.roundtrip(&mut state)?;
+        dispatch::bind(&registry, &qh, &mut state);
+        event_queue.roundtrip(&mut state)?;
+        tracing::debug!("Discovered {}", state.entities_count(),);
+
+        let mut entities_count = 0;
+        for i in 0..10 {
+            event_queue.roundtrip(&mut state)?;
+            thread::sleep(Duration::from_millis(100));
+            let new_entities_count = state.entities_count();
+            tracing::debug!(
+                "Step {i}. Discovered {new_entities_count} (previous: {entities_count})"
+            );
+
+            if new_entities_count == entities_count {
+                break;
+            }
+            entities_count = new_entities_count;
+        }
+
+        let mut client = Self {
+            connection,
+            event_queue,
+            state,
+            config: Config::default(),
+            requests: Requests::Flush,
+            confirming: Confirming::Wait,
+            mru_cycle: MruCycle::default(),
+        };
+        client.infer_workspace_group_outputs();
+        Ok(client)
+    }
+
+    /// Resolves aliases, workspace labels and defaults through `config`.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Dispatches pending wayland events and publishes the resulting state
+    /// changes to subscribers.
+    pub fn dispatch(&mut self) -> Result<(), ClientError> {
+        self.event_queue.roundtrip(&mut self.state)?;
+        self.connection.flush()?;
+        self.infer_workspace_group_outputs();
+        self.state.flush_events();
+        Ok(())
+    }
+
+    /// Like `dispatch`, but blocks until the compositor sends events or
+    /// `timeout` passes, forever without one.
+    pub fn dispatch_timeout(&mut self, timeout: Option<Duration>) -> Result<(), ClientError> {
+        self.connection.flush()?;
+        // None when events are queued already, which are dispatched below
+        if let Some(guard) = self.event_queue.prepare_read() {
+            // A timeout too long for a timespec is forever
+            let timeout = timeout.and_then(|timeout| Timespec::try_from(timeout).ok());
+            let mut fds = [PollFd::from_borrowed_fd(
+                guard.connection_fd(),
+                PollFlags::IN,
+            )];
+            match rustix::event::poll(&mut fds, timeout.as_ref()) {
+                Ok(0) | Err(rustix::io::Errno::INTR) => {}
+                Ok(_) => match guard.read() {
+                    Err(WaylandError::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    result => {
+                        result?;
+                    }
+                },
+                Err(e) => return Err(WaylandError::Io(e.into()).into()),
+            }
+        }
+        self.event_queue.dispatch_pending(&mut self.state)?;
+        self.connection.flush()?;
+        self.infer_workspace_group_outputs();
+        self.state.flush_events();
+        Ok(())
+    }
+
+    /// A snapshot of apps, workspaces, outputs and seats.
+    pub fn info(&self) -> JsonInfo {
+        JsonInfo::from(&self.state)
+    }
+
+    pub fn windows(&self) -> Vec<JsonApp> {
+        (0..self.state.apps.len())
+            .filter_map(|index| self.state.json_app(index))
+            .collect()
+    }
+
+    /// The open windows in the order they last got the focus.
+    pub fn history(&self, params: HistoryParams) -> HistoryResult {
+        let mut windows = self.state.json_history();
+        windows.truncate(params.limit.unwrap_or(usize::MAX));
+        HistoryResult { windows }
+    }
+
+    pub fn workspaces(&self) -> Vec<JsonWorkspaceGroup> {
+        self.info().workspace_groups
+    }
+
+    pub fn outputs(&self) -> Vec<JsonOutput> {
+        self.info().outputs
+    }
+
+    /// The cos-cli version and the negotiated protocol versions.
+    pub fn version(&self) -> VersionResult {
+        VersionResult {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            protocols: self
+                .state
+                .bound_protocols
+                .iter()
+                .map(|(interface, version)| ProtocolVersion {
+                    interface: interface.to_string(),
+                    version: *version,
+                })
+                .collect(),
+        }
+    }
+
+    /// Receives a batch of events for every dispatch cycle that changed the
+    /// state.
+    pub fn subscribe(&mut self) -> broadcast::Receiver<Arc<StateChange>> {
+        self.event_sender().subscribe()
+    }
+
+    /// The sender behind [`CosmicClient::subscribe`], for handing out
+    /// receivers from other threads.
+    pub fn event_sender(&mut self) -> broadcast::Sender<Arc<StateChange>> {
+        match &self.state.events {
+            Some(tx) => tx.clone(),
+            None => self.state.enable_notify(),
+        }
+    }
+
+    /// Associates workspace groups with the outputs their windows are on.
+    fn infer_workspace_group_outputs(&mut self) {
+        for app in &self.state.apps {
+            self.state
+                .workspace_groups
+                .iter_mut()
+                .filter(|wg| wg.workspaces.iter().any(|w| app.workspaces.contains(w)))
+                .for_each(|wg| {
+                    for output in &app.outputs {
+                        if !wg.outputs.contains(output) {
+                            wg.outputs.push(output.clone());
+                        }
+                    }
+                });
+        }
+    }
+
+    /// Associates workspace groups with outputs by moving the last window to
+    /// every other workspace group and output and back.
+    pub fn discover_workspace_group_outputs(&mut self) -> Result<(), ClientError> {
+        let manager = self.toplevel_manager()?.clone();
+        let Some(last_app) = self.state.apps.last().cloned() else {
+            return Err(ClientError::Internal(
+                "No apps found to discover workspace group outputs".into(),
+            ));
+        };
+
+        let Some(initial_app_output) = last_app.outputs.first() else {
+            return Err(ClientError::Internal("App without output".into()));
+        };
+
+        let Some(initial_app_workspace) = last_app.workspaces.first() else {
+            return Err(ClientError::Internal("App without workspace".into()));
+        };
+
+        let Some(initial_app_group) = self
+            .state
+            .workspace_groups
+            .iter()
+            .find(|wg| wg.workspaces.contains(initial_app_workspace))
+            .map(|wg| wg.object_id.to_owned())
+        else {
+            return Err(ClientError::Internal("Workspace without group.".into()));
+        };
+
+        let move_plan = self
+            .state
+            .workspace_groups
+            .iter()
+            .enumerate()
+            .filter_map(|(index, g)| {
+                if g.object_id != initial_app_group {
+                    Some((index, g.object_id.clone(), g.workspaces.first()?.clone()))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // start move app from one workspace group to another
+        let mut was_moved = false;
+        for (group_index, group_id, workspace_id) in move_plan {
+            let Some(nh) = self
+                .state
+                .handle_map
+                .workspace_handle
+                .get(&workspace_id)
+                .cloned()
+            else {
+                continue;
+            };
+
+            for (output_index, output_id) in self.state.outputs.clone().into_iter().enumerate() {
+                if initial_app_output == &output_id {
+                    continue;
+                }
+
+                let Some(output) = self.state.handle_map.output.get(&output_id).cloned() else {
+                    continue;
+                };
+
+                tracing::debug!(
+                    "Moving app to workspace group {group_index} workspace '{}' at output: {output_index}",
+                    nh.name.as_deref().unwrap_or_default()
+                );
+                manager.move_to_ext_workspace(&last_app.handle, &nh.handle, &output.handle);
+                self.connection.flush()?;
+
+                std::thread::sleep(std::time::Duration::from_millis(300));
+                self.event_queue.roundtrip(&mut self.state)?;
+
+                // check new state
+                let Some(new_app_state) =
+                    self.state.apps.iter().find(|a| a.handle == last_app.handle)
+                else {
+                    continue;
+                };
+
+                // app was change output - associate it with workspace group
+                if new_app_state.outputs.contains(&output_id) {
+                    was_moved = true;
+                    tracing::debug!("App moved. Add output {output_index} to the workspace group");
+                    let Some(wg) = self
+                        .state
+                        .workspace_groups
+                        .iter_mut()
+                        .find(|g| g.object_id == group_id)
+                    else {
+                        continue;
+                    };
+                    if !wg.outputs.contains(&output_id) {
+                        wg.outputs.push(output_id.clone())
+                    }
+                } else {
+                    tracing::debug!("App not changes output");
+                }
+            }
+        }
+
+        if was_moved
+            && let Some(workspace) = self
+                .state
+                .handle_map
+                .workspace_handle
+                .get(initial_app_workspace)
+                .map(|nh| &nh.handle)
+            && let Some(output) = self
+                .state
+                .handle_map
+                .output
+                .get(initial_app_output)
+                .map(|nh| &nh.handle)
+        {
+            tracing::debug!("Discover output by app moving done");
+            // move app back
+            manager.move_to_ext_workspace(&last_app.handle, workspace, output);
+            self.connection.flush()?;
+
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            self.event_queue.roundtrip(&mut self.state)?;
+        } else {
+            tracing::debug!("Failed to discover output by app moving");
+        }
+        Ok(())
+    }
+
+    fn toplevel_manager(
+        &self,
+    ) -> Result<&zcosmic_toplevel_manager_v1::ZcosmicToplevelManagerV1, ClientError> {
+        self.state
+            .cosmic_toplevel_manager
+            .as_ref()
+            .ok_or(ClientError::CapabilityMissing {
+                protocol: "toplevel management",
+            })
+    }
+
+    /// Resolves a workspace index, optionally within a group, to
+    /// `(group_index, workspace_index)`.
+    fn find_workspace(
+        &self,
+        workspace: usize,
+        workspace_group: Option<usize>,
+    ) -> Result<(usize, usize), ClientError> {
+        let not_found = ClientError::WorkspaceNotFound {
+            workspace: workspace.into(),
+            workspace_group,
+        };
+        if let Some(group_index) = workspace_group {
+            let group = self
+                .state
+                .workspace_groups
+                .get(group_index)
+                .ok_or(not_found)?;
+            if workspace >= group.workspaces.len() {
+                return Err(ClientError::WorkspaceNotFound {
+                    workspace: workspace.into(),
+                    workspace_group,
+                });
+            }
+            Ok((group_index, workspace))
+        } else {
+            self.state
+                .workspace_groups
+                .iter()
+                .position(|group| workspace < group.workspaces.len())
+                .map(|group_index| (group_index, workspace))
+                .ok_or(not_found)
+        }
+    }
+
+    fn workspace_handle(
+        &self,
+        (group_index, idx): (usize, usize),
+    ) -> Result<&NamedHandle<ext_workspace_handle_v1::ExtWorkspaceHandleV1>, ClientError> {
+        let workspace_id = &self.state.workspace_groups[group_index].workspaces[idx];
+        self.state
+            .handle_map
+            .workspace_handle
+            .get(workspace_id)
+            .ok_or(ClientError::WorkspaceNotFound {
+                workspace: idx.into(),
+                workspace_group: Some(group_index),
+            })
+    }
+
+    /// Resolves a workspace index, label from the config or workspace name to
+    /// `(group_index, workspace_index)`. An explicit group takes precedence
+    /// over the group of a label, which takes precedence over the default.
+    pub(crate) fn resolve_workspace(
+        &self,
+        workspace: &Target,
+        workspace_group: Option<usize>,
+    ) -> Result<(usize, usize), ClientError> {
+        let default_group = self.config.defaults.workspace_group;
+        let name = match workspace {
+            Target::Index(index) => {
+                return self.find_workspace(*index, workspace_group.or(default_group));
+            }
+            Target::Name(name) => name,
+        };
+        if let Some(label) = self.config.workspaces.get(name) {
+            let group = workspace_group
+                .or(label.workspace_group())
+                .or(default_group);
+            return self.find_workspace(label.workspace(), group);
+        }
+        let group = workspace_group.or(default_group);
+        self.info()
+            .workspace_groups
+            .iter()
+            .filter(|g| group.is_none_or(|index| index == g.index))
+            .find_map(|g| {
+                g.workspaces
+                    .iter()
+                    .find(|w| &w.name == name)
+                    .map(|w| (g.index, w.index))
+            })
+            .ok_or(ClientError::WorkspaceNotFound {
+                workspace: workspace.clone(),
+                workspace_group: group,
+            })
+    }
+
+    /// Resolves an output name to its index.
+    pub(crate) fn resolve_output(&self, output: &Target) -> Result<usize, ClientError> {
+        match output {
+            Target::Index(index) => Ok(*index),
+            Target::Name(name) => self
+                .outputs()
+                .iter()
+                .find(|o| &o.name == name)
+                .map(|o| o.index)
+                .ok_or_else(|| ClientError::InvalidParams(format!("No output named '{name}'"))),
+        }
+    }
+
+    /// Resolves a seat name to its index.
+    pub(crate) fn resolve_seat(&self, seat: &Target) -> Result<usize, ClientError> {
+        match seat {
+            Target::Index(index) => Ok(*index),
+            Target::Name(name) => self
+                .info()
+                .seats
+                .iter()
+                .find(|s| &s.name == name)
+                .map(|s| s.index)
+                .ok_or_else(|| ClientError::InvalidParams(format!("No seat named '{name}'"))),
+        }
+    }
+
+    /// Moves the selected windows to a workspace on an output.
+    pub fn move_window(&mut self, params: MoveParams) -> Result<MoveResult, ClientError> {
+        let apps = self.find_apps(params.app_id.clone(), params.index, params.id, params.wait)?;
+        let windows = self.snapshots(&apps);
+
+        let manager = self.toplevel_manager()?;
+        let (group_index, idx) =
+            self.resolve_workspace(&params.workspace, params.workspace_group)?;
+        let workspace = self.workspace_handle((group_index, idx))?;
+
+        let output_index = match (params.output_index, &self.config.defaults.output) {
+            (Some(index), _) => index,
+            (None, Some(output)) => self.resolve_output(output)?,
+            (None, None) => 0,
+        };
+        let output = self
+            .state
+            .outputs
+            .get(output_index)
+            .and_then(|oid| self.state.handle_map.output.get(oid))
+            .ok_or(ClientError::OutputNotFound {
+                output_index: params.output_index,
+            })?;
+
+        if self.sends() {
+            for app in &apps {
+                manager.move_to_ext_workspace(&app.handle, &workspace.handle, &output.handle);
+            }
+        }
+
+        self.flush()?;
+
+        let target = MoveTarget {
+            workspace_group: group_index,
+            workspace: idx,
+            workspace_name: workspace.name.clone().unwrap_or_default(),
+            output_index,
+            output: output.name.clone().unwrap_or_default(),
+        };
+        let expect = [Expect::Workspace(workspace.handle.id())];
+        let confirmations = self
+            .confirm(
+                params.confirm,
+                params.timeout,
+                Watch::windows(&apps, &expect),
+            )?
+            .map(Watch::window_outcomes);
+
+        Ok(MoveResult {
+            windows,
+            target,
+            requests: vec!["move_to_ext_workspace".into()],
+            confirmations,
+        })
+    }
+
+    /// Activates (focuses) a window on a seat.
+    pub fn activate(&mut self, params: ActivateParams) -> Result<ActivateResult, ClientError> {
+        let index = self.activate_index(&params)?;
+        let manager = self.toplevel_manager()?;
+
+        let Some(app) = self.state.apps.get(index) else {
+            return Err(ClientError::AppNotFound {
+                app_id: None,
+                index: Some(index),
+                id: None,
+            });
+        };
+
+        let seat_index = match (params.seat, &self.config.defaults.seat) {
+            (Some(index), _) => index,
+            (None, Some(seat)) => self.resolve_seat(seat)?,
+            (None, None) => 0,
+        };
+        let seat = self
+            .state
+            .seats
+            .get(seat_index)
+            .and_then(|sid| self.state.handle_map.seat.get(sid))
+            .map(|h| &h.handle)
+            .ok_or(ClientError::SeatNotFound { seat: params.seat })?;
+
+        if self.sends() {
+            manager.activate(&app.handle, seat);
+        }
+        self.flush()?;
+
+        Ok(ActivateResult {
+            index,
+            seat: seat_index,
+        })
+    }
+
+    /// The index of the window `params` select, by index, from the focus
+    /// history or by cycling.
+    fn activate_index(&mut self, params: &ActivateParams) -> Result<usize, ClientError> {
+        let cycling = params.app_id.is_some()
+            || params.same_app
+            || params.workspace.is_some()
+            || params.order != CycleOrder::default()
+            || params.reverse
+            || params.minimized;
+        if cycling && !params.cycle {
+            return Err(ClientError::InvalidParams(
+                "app_id, same_app, workspace, order, reverse and minimized only apply to cycle"
+                    .into(),
+            ));
+        }
+        let index = match (params.index, params.id) {
+            (Some(_), Some(_)) => {
+                return Err(ClientError::InvalidParams(
+                    "Only one of index or id can be provided".into(),
+                ));
+            }
+            (None, Some(id)) => Some(self.window_index(id)?),
+            (index, None) => index,
+        };
+        let history = self.state.json_history();
+        let found = match (index, params.previous, params.mru, params.cycle) {
+            (Some(index), false, None, false) => return Ok(index),
+            (None, false, None, true) => return self.cycle_index(params),
+            (None, true, None, false) => history
+                .iter()
+                .find(|focus| !focus.app.state.contains(&State::Activated)),
+            (None, false, Some(mru), false) => history.get(mru),
+            _ => {
+                return Err(ClientError::InvalidParams(
+                    "Exactly one of index, id, previous, mru or cycle must be provided".into(),
+                ));
+            }
+        };
+        found.map(|focus| focus.app.index).ok_or_else(|| {
+            ClientError::InvalidParams(format!(
+                "Not enough windows in the focus history ({}), it is kept by a running \
+                 'cos-cli serve'",
+                history.len()
+            ))
+        })
+    }
+
+    /// Activates a workspace.
+    pub fn activate_workspace(
+        &mut self,
+        params: ActivateWsParams,
+    ) -> Result<ActivateWsResult, ClientError> {
+        let Some(manager) = &self.state.workspace_manager else {
+            return Err(ClientError::CapabilityMissing {
+                protocol: "workspace management",
+            });
+        };
+        let (group_index, idx) =
+            self.resolve_workspace(&params.workspace, params.workspace_group)?;
+        let ws = self.workspace_handle((group_index, idx))?;
+
+        if self.sends() {
+            ws.handle.activate();
+            manager.commit();
+        }
+        self.flush()?;
+
+        let watch = Watch::workspace(ws.handle.id());
+        let confirmation = self
+            .confirm(params.confirm, params.timeout, watch)?
+            .map(Watch::workspace_outcome);
+
+        Ok(ActivateWsResult {
+            workspace_group: group_index,
+            workspace: idx,
+            confirmation,
+        })
+    }
+
+    /// The index of the active workspace in a group.
+    pub(crate) fn active_workspace(&self, group_index: usize) -> Option<usize> {
+        self.state
+            .workspace_groups
+            .get(group_index)?
+            .workspaces
+            .iter()
+            .position(|w| self.state.active_workspaces.contains(w))
+    }
+
+    /// Moves a workspace, as `(group_index, workspace_index)`, to another
+    /// workspace group and with it to the outputs of that group.
+    pub(crate) fn assign_workspace(
+        &mut self,
+        workspace: (usize, usize),
+        group_index: usize,
+    ) -> Result<(), ClientError> {
+        let Some(manager) = &self.state.workspace_manager else {
+            return Err(ClientError::CapabilityMissing {
+                protocol: "workspace management",
+            });
+        };
+        let ws = self.workspace_handle(workspace)?;
+        let group =
+            self.state
+                .workspace_groups
+                .get(group_index)
+                .ok_or(ClientError::InvalidParams(format!(
+                    "No workspace group {group_index}"
+                )))?;
+        if self.sends() {
+            ws.handle.assign(&group.handle);
+            manager.commit();
+        }
+        self.flush()
+    }
+
+    /// Sets or unsets the maximized, minimized, fullscreen and sticky states
+    /// of the selected windows.
+    pub fn set_state(&mut self, params: StateParams) -> Result<StateResult, ClientError> {
+        let apps = self.find_apps(params.app_id.clone(), params.index, params.id, params.wait)?;
+        let windows = self.snapshots(&apps);
+
+        let manager = self.toplevel_manager()?;
+
+        let mut requests = Vec::new();
+        if params.maximize {
+            requests.push("set_maximized");
+        }
+        if params.unmaximize {
+            requests.push("unset_maximized");
+        }
+        if params.minimize {
+            requests.push("set_minimized");
+        }
+        if params.unminimize {
+            requests.push("unset_minimized");
+        }
+        if params.fullscreen {
+            requests.push("set_fullscreen");
+        }
+        if params.unfullscreen {
+            requests.push("unset_fullscreen");
+        }
+        if params.sticky {
+            requests.push("set_sticky");
+        }
+        if params.unsticky {
+            requests.push("unset_sticky");
+        }
+        if requests.is_empty() {
+            return Err(ClientError::InvalidParams(
+                "No action specified for 'state' method.".into(),
+            ));
+        }
+
+        if self.sends() {
+            for app in &apps {
+                if params.maximize {
+                    manager.set_maximized(&app.handle);
+                }
+                if params.unmaximize {
+                    manager.unset_maximized(&app.handle);
+                }
+                if params.minimize {
+                    manager.set_minimized(&app.handle);
+                }
+                if params.unminimize {
+                    manager.unset_minimized(&app.handle);
+                }
+                if params.fullscreen {
+                    manager.set_fullscreen(&app.handle, None);
+                }
+                if params.unfullscreen {
+                    manager.unset_fullscreen(&app.handle);
+                }
+                if params.sticky {
+                    manager.set_sticky(&app.handle);
+                }
+                if params.unsticky {
+                    manager.unset_sticky(&app.handle);
+                }
+            }
+        }
+
+        self.flush()?;
+
+        let expect = Expect::states(&[
+            (params.maximize, State::Maximized, true),
+            (params.unmaximize, State::Maximized, false),
+            (params.minimize, State::Minimized, true),
+            (params.unminimize, State::Minimized, false),
+            (params.fullscreen, State::Fullscreen, true),
+            (params.unfullscreen, State::Fullscreen, false),
+            (params.sticky, State::Sticky, true),
+            (params.unsticky, State::Sticky, false),
+        ]);
+        let confirmations = self
+            .confirm(
+                params.confirm,
+                params.timeout,
+                Watch::windows(&apps, &expect),
+            )?
+            .map(Watch::window_outcomes);
+
+        Ok(StateResult {
+            windows,
+            requests: requests.into_iter().map(Into::into).collect(),
+            confirmations,
+        })
+    }
+
+    /// Asks the selected windows to close.
+    pub fn close(&mut self, params: CloseParams) -> Result<CloseResult, ClientError> {
+        let apps = self.find_apps(params.app_id.clone(), params.index, params.id, params.wait)?;
+        let windows = self.snapshots(&apps);
+
+        let manager = self.toplevel_manager()?;
+
+        if self.sends() {
+            for app in &apps {
+                manager.close(&app.handle);
+            }
+        }
+
+        self.flush()?;
+
+        let watch = Watch::windows(&apps, &[Expect::Closed]);
+        let confirmations = self
+            .confirm(params.confirm, params.timeout, watch)?
+            .map(Watch::window_outcomes);
+
+        Ok(CloseResult {
+            windows,
+            requests: vec!["close".into()],
+            confirmations,
+        })
+    }
+
+    /// Flushes the requests of an operation, unless it runs in a batch.
+    pub(crate) fn flush(&self) -> Result<(), ClientError> {
+        if self.requests == Requests::Flush {
+            self.connection.flush()?;
+        }
+        Ok(())
+    }
+
+    fn sends(&self) -> bool {
+        self.requests != Requests::Discard
+    }
+
+    /// Waits until `watch` settles when the request asked to `confirm`, or
+    /// leaves it to the caller of [`CosmicClient::start`].
+    fn confirm(
+        &mut self,
+        confirm: bool,
+        timeout: Option<u64>,
+        mut watch: Watch,
+    ) -> Result<Option<Watch>, ClientError> {
+        if !confirm {
+            return Ok(None);
+        }
+        let timeout = Duration::from_secs(timeout.unwrap_or(confirm::DEFAULT_TIMEOUT));
+        if let Confirming::Defer(deferred) = &mut self.confirming {
+            *deferred = Some((watch, timeout));
+            return Ok(None);
+        }
+        // Checked after every batch of events, as soon as it arrives
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            self.dispatch_timeout(Some(remaining))?;
+            if watch.update(&self.state) || remaining.is_zero() {
+                return Ok(Some(watch));
+            }
+        }
+    }
+
+    pub(crate) fn app_state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// Snapshots of `apps` as reported by `info`.
+    fn snapshots(&self, apps: &[App]) -> Vec<JsonApp> {
+        apps.iter()
+            .filter_map(|app| {
+                let index = self
+                    .state
+                    .apps
+                    .iter()
+                    .position(|a| a.handle == app.handle)?;
+                self.state.json_app(index)
+            })
+            .collect()
+    }
+
+    /// The index of the window with the protocol `id`.
+    fn window_index(&self, id: u32) -> Result<usize, ClientError> {
+        self.state
+            .apps
+            .iter()
+            .position(|app| app.ready && app.handle.id().protocol_id() == id)
+            .ok_or(ClientError::AppNotFound {
+                app_id: None,
+                index: None,
+                id: Some(id),
+            })
+    }
+
+    fn find_apps(
+        &mut self,
+        app_id: Option<String>,
+        app_index: Option<usize>,
+        window_id: Option<u32>,
+        wait: Option<u64>,
+    ) -> Result<Vec<App>, ClientError> {
+        let given = [app_id.is_some(), app_index.is_some(), window_id.is_some()];
+        if given.into_iter().filter(|given| *given).count() > 1 {
+            return Err(ClientError::InvalidParams(
+                "Only one of app_id, index or id can be provided".into(),
+            ));
+        }
+        let app_index = match window_id {
+            Some(id) => Some(self.window_index(id)?),
+            None => app_index,
+        };
+        if let Some(index) = app_index {
+            if let Some(app) = self.state.apps.get(index) {
+                Ok(vec![app.clone()])
+            } else {
+                Err(ClientError::AppNotFound {
+                    app_id: None,
+                    index: Some(index),
+                    id: None,
+                })
+            }
+        } else if let Some(id) = app_id {
+            // An alias selects by its app_id and title instead
+            let (app_id_pattern, title_pattern) = match self.config.aliases.get(&id) {
+                Some(alias) => (
+                    alias.app_id().map(str::to_lowercase),
+                    alias.title().map(str::to_lowercase),
+                ),
+                None => (Some(id.to_lowercase()), None),
+            };
+            let contains = |value: &Option<String>, pattern: &Option<String>| {
+                pattern.as_ref().is_none_or(|pattern| {
+                    value
+                        .as_ref()
+                        .is_some_and(|v| v.to_lowercase().contains(pattern))
+                })
+            };
+            let sleep = std::time::Duration::from_millis(500);
+            let wait_dur = wait.map(std::time::Duration::from_secs);
+            let now = std::time::Instant::now();
+            let mut apps;
+            loop {
+                apps = self
+                    .state
+                    .apps
+                    .iter()
+                    .filter(|app| {
+                        contains(&app.app_id, &app_id_pattern)
+                            && contains(&app.title, &title_pattern)
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                if !apps.is_empty() {
+                    break;
+                }
+
+                if let Some(wait) = wait_dur {
+                    // A server retries the operation instead, see `Waiting`
+                    if now.elapsed() > wait || matches!(self.confirming, Confirming::Defer(_)) {
+                        break;
+                    }
+                    thread::sleep(sleep);
+                    self.event_queue.roundtrip(&mut self.state)?;
+                } else {
+                    break;
+                }
+            }
+
+            if apps.is_empty() {
+                return Err(match wait {
+                    Some(wait) => ClientError::Timeout { app_id: id, wait },
+                    None => ClientError::AppNotFound {
+                        app_id: Some(id),
+                        index: None,
+                        id: None,
+                    },
+                });
+            }
+            Ok(apps)
+        } else {
+            Err(ClientError::InvalidParams(
+                "One of app_id, index or id must be provided".into(),
+            ))
+        }
+    }
+}
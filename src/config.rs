@@ -0,0 +1,496 @@
+//! The configuration file, `$XDG_CONFIG_HOME/cos-cli/config.toml`:
+//!
+//! ```toml
+//! [defaults]
+//! output = "DP-1"          # name or index
+//! seat = 0
+//! workspace_group = 0
+//!
+//! [aliases]
+//! browser = "org.mozilla.firefox"
+//! term = { app_id = "wezterm", title = "main" }
+//!
+//! [workspaces]
+//! mail = 0
+//! code = { workspace = 2, workspace_group = 1 }
+//!
+//! [profiles.docked]        # see crate::profile
+//! outputs = ["eDP-1", "DP-1"]
+//! workspaces = [{ workspace = "code", output = "DP-1" }]
+//!
+//! [hooks]                  # run by `cos-cli watch`
+//! timeout = 10
+//! [hooks.on]
+//! app_added = 'notify-send "$COS_APP_ID"'
+//! ```
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::events::EventKind;
+use crate::profile::Profile;
+use crate::{ClientError, JsonInfo};
+
+/// A workspace, output or seat, by index from `info` or by name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Target {
+    Index(usize),
+    Name(String),
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Target::Index(0)
+    }
+}
+
+impl From<usize> for Target {
+    fn from(index: usize) -> Self {
+        Target::Index(index)
+    }
+}
+
+impl FromStr for Target {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.parse()
+            .map_or_else(|_| Target::Name(s.to_string()), Target::Index))
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Index(index) => write!(f, "{index}"),
+            Target::Name(name) => write!(f, "'{name}'"),
+        }
+    }
+}
+
+/// Used when a command does not give them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    #[serde(default)]
+    pub output: Option<Target>,
+    #[serde(default)]
+    pub seat: Option<Target>,
+    #[serde(default)]
+    pub workspace_group: Option<usize>,
+}
+
+/// Windows an alias stands for: an app_id, or an app_id and title
+/// (both partial, case-insensitive).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Alias {
+    AppId(String),
+    Match {
+        #[serde(default)]
+        app_id: Option<String>,
+        #[serde(default)]
+        title: Option<String>,
+    },
+}
+
+impl Alias {
+    pub fn app_id(&self) -> Option<&str> {
+        match self {
+            Alias::AppId(app_id) => Some(app_id),
+            Alias::Match { app_id, .. } => app_id.as_deref(),
+        }
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        match self {
+            Alias::AppId(_) => None,
+            Alias::Match { title, .. } => title.as_deref(),
+        }
+    }
+}
+
+/// A workspace label: an index, or an index in a group.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum WorkspaceLabel {
+    Index(usize),
+    InGroup {
+        workspace: usize,
+        #[serde(default)]
+        workspace_group: Option<usize>,
+    },
+}
+
+impl WorkspaceLabel {
+    pub fn workspace(&self) -> usize {
+        match self {
+            WorkspaceLabel::Index(workspace) => *workspace,
+            WorkspaceLabel::InGroup { workspace, .. } => *workspace,
+        }
+    }
+
+    pub fn workspace_group(&self) -> Option<usize> {
+        match self {
+            WorkspaceLabel::Index(_) => None,
+            WorkspaceLabel::InGroup {
+                workspace_group, ..
+            } => *workspace_group,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub defaults: Defaults,
+    /// Names usable wherever an app_id is expected
+    #[serde(default)]
+    pub aliases: BTreeMap<String, Alias>,
+    /// Names usable wherever a workspace is expected
+    #[serde(default)]
+    pub workspaces: BTreeMap<String, WorkspaceLabel>,
+    /// Layouts applied when the connected outputs change
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(default)]
+    pub hooks: Hooks,
+}
+
+/// One shell command or several, run in order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Commands {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Commands {
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        match self {
+            Commands::One(command) => std::slice::from_ref(command).iter(),
+            Commands::Many(commands) => commands.iter(),
+        }
+    }
+}
+
+/// Commands run on state changes. The commands of one event type run one
+/// after another, those of different types in parallel.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    /// Seconds a command may run before it is killed (default: 10)
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// Milliseconds between two runs for the same event type (default: 0)
+    #[serde(default)]
+    pub interval: Option<u64>,
+    /// Commands per event type, run with `sh -c`
+    #[serde(default)]
+    pub on: BTreeMap<EventKind, Commands>,
+}
+
+impl FromStr for Config {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s).map_err(|e| ClientError::InvalidParams(e.to_string()))
+    }
+}
+
+impl Config {
+    /// `$COS_CLI_CONFIG`, or `$XDG_CONFIG_HOME/cos-cli/config.toml` with
+    /// `~/.config` as fallback for `$XDG_CONFIG_HOME`.
+    pub fn path() -> Option<PathBuf> {
+        std::env::var_os("COS_CLI_CONFIG")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("XDG_CONFIG_HOME")
+                    .map(PathBuf::from)
+                    .or_else(|| {
+                        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+                    })
+                    .map(|dir| dir.join("cos-cli").join("config.toml"))
+            })
+    }
+
+    /// Reads the file at [`Config::path`]. A missing file is an empty
+    /// configuration.
+    pub fn load() -> Result<Self, ClientError> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(content) => content.parse().map_err(|e| {
+                ClientError::InvalidParams(format!("Invalid config in {}: {e}", path.display()))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(ClientError::InvalidParams(format!(
+                "Failed to read {}: {e}",
+                path.display()
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Something [`Config::check`] found.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Problem {
+    fn error(message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    fn warning(message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+        }
+    }
+}
+
+impl Config {
+    /// Checks the defaults and labels against the live state. Aliases that
+    /// match no open window are only warnings, the app may not be running.
+    pub fn check(&self, info: &JsonInfo) -> Vec<Problem> {
+        let mut problems = Vec::new();
+
+        let outputs = info.outputs.iter().map(|o| (o.index, o.name.as_str()));
+        if let Some(output) = &self.defaults.output
+            && !target_exists(output, outputs)
+        {
+            problems.push(Problem::error(format!(
+                "defaults.output: no output {output}"
+            )));
+        }
+        let seats = info.seats.iter().map(|s| (s.index, s.name.as_str()));
+        if let Some(seat) = &self.defaults.seat
+            && !target_exists(seat, seats)
+        {
+            problems.push(Problem::error(format!("defaults.seat: no seat {seat}")));
+        }
+        if let Some(group) = self.defaults.workspace_group
+            && group >= info.workspace_groups.len()
+        {
+            problems.push(Problem::error(format!(
+                "defaults.workspace_group: no workspace group {group}"
+            )));
+        }
+
+        for (name, label) in &self.workspaces {
+            let group = label.workspace_group().or(self.defaults.workspace_group);
+            let exists = info
+                .workspace_groups
+                .iter()
+                .filter(|g| group.is_none_or(|index| index == g.index))
+                .any(|g| label.workspace() < g.workspaces.len());
+            if !exists {
+                problems.push(Problem::error(match group {
+                    Some(group) => format!(
+                        "workspaces.{name}: no workspace {} in workspace group {group}",
+                        label.workspace()
+                    ),
+                    None => format!("workspaces.{name}: no workspace {}", label.workspace()),
+                }));
+            }
+            if info
+                .workspace_groups
+                .iter()
+                .flat_map(|g| &g.workspaces)
+                .any(|w| &w.name == name)
+            {
+                problems.push(Problem::warning(format!(
+                    "workspaces.{name}: hides the workspace named '{name}'"
+                )));
+            }
+        }
+
+        for (name, profile) in &self.profiles {
+            if profile.outputs.is_empty() {
+                problems.push(Problem::error(format!(
+                    "profiles.{name}: needs at least one output"
+                )));
+            }
+        }
+
+        for (name, alias) in &self.aliases {
+            if alias.app_id().is_none() && alias.title().is_none() {
+                problems.push(Problem::error(format!(
+                    "aliases.{name}: needs an app_id or a title"
+                )));
+                continue;
+            }
+            let contains = |value: &str, pattern: Option<&str>| {
+                pattern.is_none_or(|p| value.to_lowercase().contains(&p.to_lowercase()))
+            };
+            let matches = info
+                .apps
+                .iter()
+                .filter(|app| {
+                    contains(&app.app_id, alias.app_id()) && contains(&app.title, alias.title())
+                })
+                .count();
+            if matches == 0 {
+                problems.push(Problem::warning(format!(
+                    "aliases.{name}: matches no open window"
+                )));
+            }
+        }
+        problems
+    }
+}
+
+fn target_exists<'a>(target: &Target, mut items: impl Iterator<Item = (usize, &'a str)>) -> bool {
+    match target {
+        Target::Index(index) => items.any(|(i, _)| i == *index),
+        Target::Name(name) => items.any(|(_, n)| n == name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{JsonApp, JsonOutput, JsonSeat, JsonWorkspace, JsonWorkspaceGroup};
+
+    const CONFIG: &str = r#"
+        [defaults]
+        output = "DP-1"
+        seat = 0
+
+        [aliases]
+        browser = "firefox"
+        term = { app_id = "wezterm", title = "main" }
+
+        [workspaces]
+        mail = 0
+        code = { workspace = 1, workspace_group = 0 }
+    "#;
+
+    fn info() -> JsonInfo {
+        JsonInfo {
+            apps: vec![JsonApp {
+                title: "Mozilla Firefox".into(),
+                ..crate::test_app(1, "org.mozilla.firefox")
+            }],
+            workspace_groups: vec![JsonWorkspaceGroup {
+                index: 0,
+                workspaces: ["1", "2"]
+                    .iter()
+                    .enumerate()
+                    .map(|(index, name)| JsonWorkspace {
+                        index,
+                        name: name.to_string(),
+                    })
+                    .collect(),
+                outputs: vec!["DP-1".into()],
+            }],
+            outputs: vec![JsonOutput {
+                index: 0,
+                name: "DP-1".into(),
+                make: String::new(),
+                model: String::new(),
+            }],
+            seats: vec![JsonSeat {
+                index: 0,
+                name: "seat0".into(),
+            }],
+        }
+    }
+
+    fn messages(problems: &[Problem], severity: Severity) -> Vec<&str> {
+        problems
+            .iter()
+            .filter(|p| p.severity == severity)
+            .map(|p| p.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn parses_every_section() {
+        let config: Config = CONFIG.parse().unwrap();
+        assert_eq!(config.defaults.output, Some(Target::Name("DP-1".into())));
+        assert_eq!(config.defaults.seat, Some(Target::Index(0)));
+        assert_eq!(config.aliases["browser"].app_id(), Some("firefox"));
+        assert_eq!(config.aliases["term"].title(), Some("main"));
+        assert_eq!(config.workspaces["mail"], WorkspaceLabel::Index(0));
+        assert_eq!(config.workspaces["code"].workspace_group(), Some(0));
+    }
+
+    #[test]
+    fn rejects_invalid_toml_and_unknown_keys() {
+        assert!(matches!(
+            "[defaults".parse::<Config>(),
+            Err(ClientError::InvalidParams(_))
+        ));
+        assert!(matches!(
+            "[defaults]\noutputs = 1".parse::<Config>(),
+            Err(ClientError::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn valid_config_has_only_warnings_for_closed_apps() {
+        let config: Config = CONFIG.parse().unwrap();
+        let problems = config.check(&info());
+        assert!(messages(&problems, Severity::Error).is_empty());
+        assert_eq!(
+            messages(&problems, Severity::Warning),
+            ["aliases.term: matches no open window"]
+        );
+    }
+
+    #[test]
+    fn check_reports_targets_that_point_nowhere() {
+        let config: Config = r#"
+            [defaults]
+            output = "HDMI-1"
+            seat = 1
+            workspace_group = 2
+
+            [workspaces]
+            far = 5
+            1 = 0
+
+            [aliases]
+            empty = {}
+        "#
+        .parse()
+        .unwrap();
+        let problems = config.check(&info());
+        assert_eq!(
+            messages(&problems, Severity::Error),
+            [
+                "defaults.output: no output 'HDMI-1'",
+                "defaults.seat: no seat 1",
+                "defaults.workspace_group: no workspace group 2",
+                "workspaces.1: no workspace 0 in workspace group 2",
+                "workspaces.far: no workspace 5 in workspace group 2",
+                "aliases.empty: needs an app_id or a title",
+            ]
+        );
+        assert_eq!(
+            messages(&problems, Severity::Warning),
+            ["workspaces.1: hides the workspace named '1'"]
+        );
+    }
+}
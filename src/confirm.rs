@@ -0,0 +1,213 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+use wayland_client::{Proxy, backend::ObjectId};
+
+use crate::client::{CloseResult, MoveResult, StateResult};
+use crate::{App, AppState, CosmicClient, Output, State};
+
+/// Seconds to wait for a confirmation when no timeout is given.
+pub const DEFAULT_TIMEOUT: u64 = 5;
+
+/// A change a request is expected to cause on a window.
+#[derive(Debug, Clone)]
+pub(crate) enum Expect {
+    /// The window entered the workspace
+    Workspace(ObjectId),
+    /// The state flag was set (`true`) or unset (`false`)
+    State(State, bool),
+    /// The window was closed
+    Closed,
+}
+
+impl Expect {
+    /// Expectations for the state flags requested by a `state` command,
+    /// given as `(requested, state, set)` triples.
+    pub(crate) fn states(flags: &[(bool, State, bool)]) -> Vec<Expect> {
+        flags
+            .iter()
+            .filter(|(requested, _, _)| *requested)
+            .map(|(_, state, set)| Expect::State(state.clone(), *set))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The compositor reflects the change
+    Confirmed,
+    /// The window disappeared before the change was reflected
+    Failed,
+    /// The change was not reflected within the timeout
+    Timeout,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Outcome::Confirmed => "confirmed",
+            Outcome::Failed => "failed",
+            Outcome::Timeout => "timeout",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WindowOutcome {
+    pub id: u32,
+    pub app_id: String,
+    pub outcome: Outcome,
+}
+
+/// The worst outcome: a failure outweighs a timeout, which outweighs success.
+pub fn overall<'a>(outcomes: impl IntoIterator<Item = &'a Outcome>) -> Outcome {
+    outcomes
+        .into_iter()
+        .fold(Outcome::Confirmed, |acc, outcome| match (acc, outcome) {
+            (Outcome::Failed, _) | (_, Outcome::Failed) => Outcome::Failed,
+            (Outcome::Timeout, _) | (_, Outcome::Timeout) => Outcome::Timeout,
+            _ => Outcome::Confirmed,
+        })
+}
+
+fn check(state: &AppState, app: &App, expect: &[Expect]) -> Option<Outcome> {
+    let closed_expected = expect.iter().any(|e| matches!(e, Expect::Closed));
+    let Some(current) = state.apps.iter().find(|a| a.handle == app.handle) else {
+        return Some(if closed_expected {
+            Outcome::Confirmed
+        } else {
+            Outcome::Failed
+        });
+    };
+    let done = expect.iter().all(|e| match e {
+        Expect::Workspace(workspace) => current.workspaces.contains(workspace),
+        Expect::State(s, set) => current.state.contains(s) == *set,
+        Expect::Closed => false,
+    });
+    done.then_some(Outcome::Confirmed)
+}
+
+/// What a confirmation waits for.
+#[derive(Debug, Clone)]
+pub(crate) enum Watch {
+    /// Every window in `apps` reflects `expect`
+    Windows {
+        apps: Vec<App>,
+        expect: Vec<Expect>,
+        outcomes: Vec<Option<Outcome>>,
+    },
+    /// The workspace is active
+    Workspace {
+        workspace: ObjectId,
+        outcome: Option<Outcome>,
+    },
+}
+
+impl Watch {
+    pub(crate) fn windows(apps: &[App], expect: &[Expect]) -> Self {
+        Watch::Windows {
+            apps: apps.to_vec(),
+            expect: expect.to_vec(),
+            outcomes: vec![None; apps.len()],
+        }
+    }
+
+    pub(crate) fn workspace(workspace: ObjectId) -> Self {
+        Watch::Workspace {
+            workspace,
+            outcome: None,
+        }
+    }
+
+    /// Records the outcomes `state` settles. Returns true once all are known.
+    pub(crate) fn update(&mut self, state: &AppState) -> bool {
+        match self {
+            Watch::Windows {
+                apps,
+                expect,
+                outcomes,
+            } => {
+                for (app, outcome) in apps.iter().zip(outcomes.iter_mut()) {
+                    if outcome.is_none() {
+                        *outcome = check(state, app, expect);
+                    }
+                }
+                outcomes.iter().all(Option::is_some)
+            }
+            Watch::Workspace { workspace, outcome } => {
+                if state.active_workspaces.contains(workspace) {
+                    *outcome = Some(Outcome::Confirmed);
+                }
+                outcome.is_some()
+            }
+        }
+    }
+
+    /// The outcome per window, a timeout for the ones not known yet.
+    pub(crate) fn window_outcomes(self) -> Vec<WindowOutcome> {
+        let Watch::Windows { apps, outcomes, .. } = self else {
+            return Vec::new();
+        };
+        apps.iter()
+            .zip(outcomes)
+            .map(|(app, outcome)| WindowOutcome {
+                id: app.handle.id().protocol_id(),
+                app_id: app.app_id.clone().unwrap_or_default(),
+                outcome: outcome.unwrap_or(Outcome::Timeout),
+            })
+            .collect()
+    }
+
+    /// Whether the workspace got active, a timeout if not yet.
+    pub(crate) fn workspace_outcome(self) -> Outcome {
+        match self {
+            Watch::Workspace { outcome, .. } => outcome.unwrap_or(Outcome::Timeout),
+            Watch::Windows { .. } => Outcome::Timeout,
+        }
+    }
+}
+
+/// An operation that sent its requests and waits until the compositor
+/// reflects them, started by [`CosmicClient::start`].
+#[derive(Debug)]
+pub struct Confirmation {
+    output: Output,
+    watch: Watch,
+    deadline: Instant,
+}
+
+impl Confirmation {
+    pub(crate) fn new(output: Output, watch: Watch, timeout: Duration) -> Self {
+        Self {
+            output,
+            watch,
+            deadline: Instant::now() + timeout,
+        }
+    }
+
+    /// Checks the state the client dispatched so far. Returns true once the
+    /// outcome is known or the timeout passed.
+    pub fn poll(&mut self, client: &CosmicClient) -> bool {
+        self.watch.update(client.app_state()) || Instant::now() >= self.deadline
+    }
+
+    /// The result of the operation, with a timeout outcome for the windows
+    /// that did not reflect the change yet.
+    pub fn finish(self) -> Output {
+        let mut output = self.output;
+        match &mut output {
+            Output::Move(MoveResult { confirmations, .. })
+            | Output::State(StateResult { confirmations, .. })
+            | Output::Close(CloseResult { confirmations, .. }) => {
+                *confirmations = Some(self.watch.window_outcomes());
+            }
+            Output::WsActivate(result) => {
+                result.confirmation = Some(self.watch.workspace_outcome());
+            }
+            _ => {}
+        }
+        output
+    }
+}
@@ -0,0 +1,257 @@
+//! `activate` with `cycle`: the window after the focused one among the
+//! windows of an app or a workspace, for a keybinding that steps through
+//! them.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::client::ActivateParams;
+use crate::config::Target;
+use crate::history::HistoryParams;
+use crate::selector::Selector;
+use crate::{ClientError, CosmicClient, JsonApp, State};
+
+/// Workspace name of `cycle` for the workspace of the focused window.
+const CURRENT: &str = "current";
+
+/// Order `cycle` steps through the windows in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CycleOrder {
+    /// By protocol id, the order the windows were opened in
+    #[default]
+    Id,
+    /// Most recently focused first, then the windows without focus history
+    /// by id
+    Mru,
+}
+
+impl std::str::FromStr for CycleOrder {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(CycleOrder::Id),
+            "mru" => Ok(CycleOrder::Mru),
+            order => Err(ClientError::InvalidParams(format!(
+                "Unknown order: {order}. Expected id or mru"
+            ))),
+        }
+    }
+}
+
+/// The focus history when an `mru` cycle started. Activating the windows of
+/// the cycle changes the history, so stepping by the current one would only
+/// switch between the two most recent windows.
+#[derive(Debug, Default)]
+pub(crate) struct MruCycle {
+    /// Window ids, most recently focused first
+    history: Vec<u32>,
+    /// The window the cycle activated last
+    last: Option<u32>,
+}
+
+impl MruCycle {
+    /// The history of the cycle that activated `focused`, or `history` as
+    /// the start of a new cycle when the focus moved elsewhere since.
+    fn history(&mut self, focused: Option<u32>, history: Vec<u32>) -> &[u32] {
+        if focused.is_none() || self.last != focused {
+            self.history = history;
+        }
+        &self.history
+    }
+}
+
+/// Sorts the windows of a cycle by `order`, with `history` the window ids of
+/// the `mru` order.
+fn sort(candidates: &mut [&JsonApp], order: CycleOrder, history: &[u32], reverse: bool) {
+    candidates.sort_by_key(|app| app.id);
+    if order == CycleOrder::Mru {
+        candidates.sort_by_key(|app| {
+            history
+                .iter()
+                .position(|id| *id == app.id)
+                .unwrap_or(usize::MAX)
+        });
+    }
+    if reverse {
+        candidates.reverse();
+    }
+}
+
+/// The window after `focused` among the sorted `candidates`, the first of
+/// them when none of them has the focus.
+fn step<'a>(candidates: &[&'a JsonApp], focused: Option<u32>) -> Option<&'a JsonApp> {
+    match candidates.iter().position(|app| focused == Some(app.id)) {
+        Some(position) => Some(candidates[(position + 1) % candidates.len()]),
+        None => candidates.first().copied(),
+    }
+}
+
+impl CosmicClient {
+    /// The workspaces of `cycle`, as (group, index): those of the focused
+    /// window for `current`, or the active ones when no window has the
+    /// focus.
+    fn cycle_workspaces(
+        &self,
+        workspace: &Target,
+        focused: Option<&JsonApp>,
+    ) -> Result<Vec<(usize, usize)>, ClientError> {
+        if workspace != &Target::Name(CURRENT.into()) {
+            return Ok(vec![self.resolve_workspace(workspace, None)?]);
+        }
+        Ok(match focused {
+            Some(app) => app
+                .workspaces
+                .iter()
+                .map(|w| (w.group_index, w.index))
+                .collect(),
+            None => self
+                .workspaces()
+                .iter()
+                .filter_map(|g| Some((g.index, self.active_workspace(g.index)?)))
+                .collect(),
+        })
+    }
+
+    /// The index of the window after the focused one among those `params`
+    /// select, or the first of them when none of them has the focus.
+    pub(crate) fn cycle_index(&mut self, params: &ActivateParams) -> Result<usize, ClientError> {
+        if params.app_id.is_some() && params.same_app {
+            return Err(ClientError::InvalidParams(
+                "Only one of app_id or same_app can be provided".into(),
+            ));
+        }
+        let windows = self.windows();
+        let focused = windows
+            .iter()
+            .find(|app| app.state.contains(&State::Activated));
+
+        let selector = Selector {
+            app_id: params.app_id.clone(),
+            ..Default::default()
+        }
+        .with_config(self.config());
+        let same_app = match (params.same_app, focused) {
+            (false, _) => None,
+            (true, Some(focused)) => Some(focused.app_id.as_str()),
+            (true, None) => {
+                return Err(ClientError::InvalidParams(
+                    "same_app needs a window with the focus".into(),
+                ));
+            }
+        };
+        let workspaces = match &params.workspace {
+            Some(workspace) => Some(self.cycle_workspaces(workspace, focused)?),
+            None => None,
+        };
+
+        let mut candidates = windows
+            .iter()
+            .filter(|app| selector.matches(app))
+            .filter(|app| same_app.is_none_or(|app_id| app.app_id == app_id))
+            .filter(|app| {
+                workspaces.as_ref().is_none_or(|workspaces| {
+                    app.workspaces
+                        .iter()
+                        .any(|w| workspaces.contains(&(w.group_index, w.index)))
+                })
+            })
+            .filter(|app| {
+                params.minimized
+                    || !app.state.contains(&State::Minimized)
+                    || app.state.contains(&State::Activated)
+            })
+            .collect::<Vec<_>>();
+        let focused = focused.map(|app| app.id);
+        let history = match params.order {
+            CycleOrder::Id => &[],
+            CycleOrder::Mru => {
+                let history = self.history(HistoryParams::default()).windows;
+                self.mru_cycle
+                    .history(focused, history.iter().map(|focus| focus.app.id).collect())
+            }
+        };
+        sort(&mut candidates, params.order, history, params.reverse);
+
+        let next = step(&candidates, focused).ok_or(ClientError::AppNotFound {
+            app_id: params.app_id.clone(),
+            index: None,
+            id: None,
+        })?;
+        self.mru_cycle.last = (params.order == CycleOrder::Mru).then_some(next.id);
+        Ok(next.index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(id: u32) -> JsonApp {
+        crate::test_app(id, "kitty")
+    }
+
+    fn ids(candidates: &[&JsonApp]) -> Vec<u32> {
+        candidates.iter().map(|app| app.id).collect()
+    }
+
+    #[test]
+    fn parses_orders() {
+        assert_eq!("id".parse::<CycleOrder>().unwrap(), CycleOrder::Id);
+        assert_eq!("mru".parse::<CycleOrder>().unwrap(), CycleOrder::Mru);
+        assert!(matches!(
+            "recent".parse::<CycleOrder>(),
+            Err(ClientError::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn sorts_by_id_or_history() {
+        let windows = [app(3), app(1), app(4), app(2)];
+        let mut candidates = windows.iter().collect::<Vec<_>>();
+        sort(&mut candidates, CycleOrder::Id, &[], false);
+        assert_eq!(ids(&candidates), [1, 2, 3, 4]);
+        // Windows without focus history come last, by id
+        sort(&mut candidates, CycleOrder::Mru, &[4, 2], false);
+        assert_eq!(ids(&candidates), [4, 2, 1, 3]);
+        sort(&mut candidates, CycleOrder::Mru, &[4, 2], true);
+        assert_eq!(ids(&candidates), [3, 1, 2, 4]);
+    }
+
+    #[test]
+    fn steps_after_the_focused_window() {
+        let windows = [app(1), app(2), app(3)];
+        let candidates = windows.iter().collect::<Vec<_>>();
+        let next = |focused| step(&candidates, focused).map(|app| app.id);
+        assert_eq!(next(Some(1)), Some(2));
+        assert_eq!(next(Some(3)), Some(1));
+        assert_eq!(next(Some(7)), Some(1));
+        assert_eq!(next(None), Some(1));
+        assert!(step(&[], Some(1)).is_none());
+    }
+
+    #[test]
+    fn mru_cycle_visits_every_window() {
+        let windows = [app(1), app(2), app(3)];
+        let mut cycle = MruCycle::default();
+        // Activating a window puts it first in the focus history
+        let mut history = vec![2, 3, 1];
+        let mut visited = Vec::new();
+        for _ in 0..3 {
+            let focused = history[0];
+            let mut candidates = windows.iter().collect::<Vec<_>>();
+            let order = cycle.history(Some(focused), history.clone()).to_vec();
+            sort(&mut candidates, CycleOrder::Mru, &order, false);
+            let next = step(&candidates, Some(focused)).unwrap().id;
+            cycle.last = Some(next);
+            history.retain(|id| *id != next);
+            history.insert(0, next);
+            visited.push(next);
+        }
+        assert_eq!(visited, [3, 1, 2]);
+
+        // Focusing a window outside the cycle starts a new one
+        assert_eq!(cycle.history(Some(3), vec![3, 2, 1]), [3, 2, 1]);
+    }
+}
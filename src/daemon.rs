@@ -0,0 +1,173 @@
+//! `cos-cli rules`: applies window rules to every new toplevel, and the
+//! layout profile of the outputs when they change, and runs the hooks of the
+//! config until stopped.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast::error::TryRecvError;
+
+use cos_cli::events::Event;
+use cos_cli::rules::Rules;
+use cos_cli::{CosmicClient, JsonApp};
+
+use crate::CliError;
+use crate::hooks::HookRunner;
+use crate::hotplug::Hotplug;
+
+/// How often wayland events are dispatched and the rules file is checked.
+const POLL: Duration = Duration::from_millis(100);
+
+/// The rules file, reloaded when it changes.
+struct RulesFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    rules: Rules,
+}
+
+impl RulesFile {
+    fn load(path: PathBuf) -> Result<Self, Box<dyn Error>> {
+        let modified = modified(&path);
+        let rules = read(&path)?;
+        tracing::info!("Loaded {} rules from {}", rules.rules.len(), path.display());
+        Ok(Self {
+            path,
+            modified,
+            rules,
+        })
+    }
+
+    /// Reloads the rules if the file changed. Invalid rules are logged and
+    /// the previous ones stay in effect.
+    fn reload(&mut self) {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        match read(&self.path) {
+            Ok(rules) => {
+                tracing::info!("Reloaded {} rules", rules.rules.len());
+                self.rules = rules;
+            }
+            Err(e) => tracing::error!("Keeping the previous rules: {e}"),
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn read(path: &Path) -> Result<Rules, Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| CliError::new(format!("Failed to read {}: {e}", path.display())))?;
+    content
+        .parse()
+        .map_err(|e| CliError::new(format!("Invalid rules in {}: {e}", path.display())).into())
+}
+
+fn apply(client: &mut CosmicClient, rules: &Rules, app: &JsonApp) {
+    for (index, rule) in rules.matching(app) {
+        let label = rule.label(index);
+        match client.apply_rule(rule, app) {
+            Ok(requests) => tracing::info!(
+                "Rule {label} applied to {} [{}]: {}",
+                app.app_id,
+                app.id,
+                requests.join(", ")
+            ),
+            Err(e) => tracing::warn!("Rule {label} failed for {} [{}]: {e}", app.app_id, app.id),
+        }
+    }
+}
+
+/// Rules the windows whose events were dropped: those that opened, and the
+/// pending ones that set their app_id meanwhile.
+fn catch_up(
+    client: &mut CosmicClient,
+    rules: &Rules,
+    seen: &mut HashSet<u32>,
+    pending: &mut HashSet<u32>,
+) {
+    let windows = client.windows();
+    seen.retain(|id| windows.iter().any(|app| app.id == *id));
+    pending.retain(|id| seen.contains(id));
+    for app in &windows {
+        let new = seen.insert(app.id);
+        if app.app_id.is_empty() {
+            if new {
+                pending.insert(app.id);
+            }
+        } else if new || pending.remove(&app.id) {
+            apply(client, rules, app);
+        }
+    }
+}
+
+/// Applies the rules in `path` to every toplevel that appears, and with
+/// `existing` to those already open together with the profile of the
+/// connected outputs. Runs until the compositor connection is lost.
+pub fn run(path: PathBuf, existing: bool) -> Result<(), Box<dyn Error>> {
+    let mut rules = RulesFile::load(path)?;
+    let mut client = crate::connect()?;
+    let mut events = client.subscribe();
+    let mut hotplug = Hotplug::new(&client);
+    let mut hooks = HookRunner::with_config(&client, Vec::new(), None, None);
+    if existing {
+        for app in client.windows() {
+            apply(&mut client, &rules.rules, &app);
+        }
+        crate::hotplug::apply(&mut client);
+    }
+
+    // Windows that are open and were ruled, or were open at the start
+    let mut seen = client
+        .windows()
+        .iter()
+        .map(|app| app.id)
+        .collect::<HashSet<_>>();
+    // Toplevels that appeared without an app_id are ruled once they set one
+    let mut pending = HashSet::new();
+    loop {
+        client.dispatch()?;
+        let mut lagged = false;
+        loop {
+            let change = match events.try_recv() {
+                Ok(change) => change,
+                Err(TryRecvError::Lagged(skipped)) => {
+                    tracing::warn!("Dropped {skipped} state changes");
+                    lagged = true;
+                    continue;
+                }
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            };
+            for event in &change.events {
+                hooks.handle(event);
+                match event {
+                    Event::AppAdded { app } if !seen.insert(app.id) => {}
+                    Event::AppAdded { app } if app.app_id.is_empty() => {
+                        pending.insert(app.id);
+                    }
+                    Event::AppAdded { app } => apply(&mut client, &rules.rules, app),
+                    Event::AppIdChanged { app } if pending.remove(&app.id) => {
+                        apply(&mut client, &rules.rules, app)
+                    }
+                    Event::AppRemoved { app } => {
+                        seen.remove(&app.id);
+                        pending.remove(&app.id);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if lagged {
+            catch_up(&mut client, &rules.rules, &mut seen, &mut pending);
+        }
+        hotplug.poll(&mut client);
+        thread::sleep(POLL);
+        rules.reload();
+    }
+}
@@ -0,0 +1,434 @@
+//! `serve --dbus`: the operations as methods of `org.cosmic_cli.WindowManager`
+//! on the session bus, and window and workspace changes as its signals.
+
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{self, OwnedValue};
+
+use cos_cli::events::{Event, StateChange};
+use cos_cli::{ClientError, JsonApp, JsonWorkspaceRef, Operation};
+
+use crate::server::ServerHandler;
+
+/// Well-known bus name, also the name of the interface.
+pub const BUS_NAME: &str = "org.cosmic_cli.WindowManager";
+pub const OBJECT_PATH: &str = "/org/cosmic_cli/WindowManager";
+
+/// [`ClientError`] as D-Bus error, e.g. `org.cosmic_cli.Error.AppNotFound`.
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.cosmic_cli.Error")]
+enum DbusError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    AppNotFound(String),
+    WorkspaceNotFound(String),
+    OutputNotFound(String),
+    SeatNotFound(String),
+    CapabilityMissing(String),
+    Timeout(String),
+    Disconnected(String),
+    InvalidParams(String),
+    Internal(String),
+}
+
+impl From<ClientError> for DbusError {
+    fn from(e: ClientError) -> Self {
+        let message = e.to_string();
+        match e {
+            ClientError::AppNotFound { .. } => Self::AppNotFound(message),
+            ClientError::WorkspaceNotFound { .. } => Self::WorkspaceNotFound(message),
+            ClientError::OutputNotFound { .. } => Self::OutputNotFound(message),
+            ClientError::SeatNotFound { .. } => Self::SeatNotFound(message),
+            ClientError::CapabilityMissing { .. } => Self::CapabilityMissing(message),
+            ClientError::Timeout { .. } => Self::Timeout(message),
+            ClientError::Disconnected { .. } => Self::Disconnected(message),
+            ClientError::InvalidParams(_) => Self::InvalidParams(message),
+            ClientError::Internal(_) => Self::Internal(message),
+        }
+    }
+}
+
+/// D-Bus method params, `a{sv}`, as JSON-RPC params object.
+type Params = HashMap<String, OwnedValue>;
+
+fn to_json(value: &zvariant::Value<'_>) -> Value {
+    use zvariant::Value as V;
+    match value {
+        V::U8(v) => json!(v),
+        V::Bool(v) => json!(v),
+        V::I16(v) => json!(v),
+        V::U16(v) => json!(v),
+        V::I32(v) => json!(v),
+        V::U32(v) => json!(v),
+        V::I64(v) => json!(v),
+        V::U64(v) => json!(v),
+        V::F64(v) => json!(v),
+        V::Str(v) => json!(v.as_str()),
+        V::Signature(v) => json!(v.to_string()),
+        V::ObjectPath(v) => json!(v.as_str()),
+        V::Value(v) => to_json(v),
+        V::Array(v) => v.inner().iter().map(to_json).collect(),
+        V::Dict(v) => v
+            .iter()
+            .map(|(key, value)| {
+                let key = match key {
+                    V::Str(key) => key.to_string(),
+                    key => to_json(key).to_string(),
+                };
+                (key, to_json(value))
+            })
+            .collect::<Map<_, _>>()
+            .into(),
+        V::Structure(v) => v.fields().iter().map(to_json).collect(),
+        // File descriptors
+        _ => Value::Null,
+    }
+}
+
+fn params_to_json(params: &Params) -> Value {
+    params
+        .iter()
+        .map(|(key, value)| (key.clone(), to_json(value)))
+        .collect::<Map<_, _>>()
+        .into()
+}
+
+struct WindowManager {
+    handler: Arc<ServerHandler>,
+}
+
+impl WindowManager {
+    /// Runs the operation `method` and returns its result as JSON, the same
+    /// object the JSON-RPC method returns.
+    async fn call(&self, method: &str, params: Value) -> Result<String, DbusError> {
+        let operation = Operation::parse(method, params)?;
+        Ok(self.handler.execute(operation).await?.to_string())
+    }
+}
+
+/// Every method takes the params of the JSON-RPC method of the same name as
+/// `a{sv}` and returns its result as JSON string.
+#[zbus::interface(name = "org.cosmic_cli.WindowManager")]
+impl WindowManager {
+    async fn info(&self, params: Params) -> Result<String, DbusError> {
+        self.call("info", params_to_json(&params)).await
+    }
+
+    #[zbus(name = "Move")]
+    async fn move_window(&self, params: Params) -> Result<String, DbusError> {
+        self.call("move", params_to_json(&params)).await
+    }
+
+    async fn activate(&self, params: Params) -> Result<String, DbusError> {
+        self.call("activate", params_to_json(&params)).await
+    }
+
+    async fn ws_activate(&self, params: Params) -> Result<String, DbusError> {
+        self.call("ws_activate", params_to_json(&params)).await
+    }
+
+    async fn state(&self, params: Params) -> Result<String, DbusError> {
+        self.call("state", params_to_json(&params)).await
+    }
+
+    async fn close(&self, params: Params) -> Result<String, DbusError> {
+        self.call("close", params_to_json(&params)).await
+    }
+
+    async fn history(&self, params: Params) -> Result<String, DbusError> {
+        self.call("history", params_to_json(&params)).await
+    }
+
+    /// `operations` are `(method, params)` pairs, `params` the other params
+    /// of `batch`, i.e. `on_error`.
+    async fn batch(
+        &self,
+        operations: Vec<(String, Params)>,
+        params: Params,
+    ) -> Result<String, DbusError> {
+        let mut params = params_to_json(&params);
+        params["operations"] = operations
+            .iter()
+            .map(|(method, params)| json!({ "method": method, "params": params_to_json(params) }))
+            .collect();
+        self.call("batch", params).await
+    }
+
+    fn ping(&self) -> &'static str {
+        "pong"
+    }
+
+    fn version(&self) -> String {
+        self.handler.version().to_string()
+    }
+
+    fn shutdown(&self) {
+        tracing::info!("Shutdown requested");
+        self.handler.request_shutdown();
+    }
+
+    #[zbus(signal)]
+    async fn window_added(
+        emitter: &SignalEmitter<'_>,
+        id: u32,
+        app_id: &str,
+        title: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn window_removed(
+        emitter: &SignalEmitter<'_>,
+        id: u32,
+        app_id: &str,
+        title: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn window_focused(
+        emitter: &SignalEmitter<'_>,
+        id: u32,
+        app_id: &str,
+        title: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn workspace_activated(
+        emitter: &SignalEmitter<'_>,
+        group_index: u32,
+        index: u32,
+        name: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// The signals of the interface, with the event they are emitted for.
+#[derive(Debug)]
+enum Signal<'a> {
+    WindowAdded(&'a JsonApp),
+    WindowRemoved(&'a JsonApp),
+    WindowFocused(&'a JsonApp),
+    WorkspaceActivated(&'a JsonWorkspaceRef),
+}
+
+impl<'a> Signal<'a> {
+    /// The signal for `event`, if it has one.
+    fn of(event: &'a Event) -> Option<Self> {
+        match event {
+            Event::AppAdded { app } => Some(Signal::WindowAdded(app)),
+            Event::AppRemoved { app } => Some(Signal::WindowRemoved(app)),
+            Event::FocusChanged { app } => Some(Signal::WindowFocused(app)),
+            Event::WorkspaceActivated { workspace } => Some(Signal::WorkspaceActivated(workspace)),
+            _ => None,
+        }
+    }
+
+    async fn emit(&self, emitter: &SignalEmitter<'_>) -> zbus::Result<()> {
+        match self {
+            Signal::WindowAdded(app) => {
+                WindowManager::window_added(emitter, app.id, &app.app_id, &app.title).await
+            }
+            Signal::WindowRemoved(app) => {
+                WindowManager::window_removed(emitter, app.id, &app.app_id, &app.title).await
+            }
+            Signal::WindowFocused(app) => {
+                WindowManager::window_focused(emitter, app.id, &app.app_id, &app.title).await
+            }
+            Signal::WorkspaceActivated(workspace) => {
+                WindowManager::workspace_activated(
+                    emitter,
+                    workspace.group_index as u32,
+                    workspace.index as u32,
+                    &workspace.workspace,
+                )
+                .await
+            }
+        }
+    }
+}
+
+async fn publish_signals(
+    mut events_rx: broadcast::Receiver<Arc<StateChange>>,
+    emitter: SignalEmitter<'static>,
+) {
+    loop {
+        let change = match events_rx.recv().await {
+            Ok(change) => change,
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!("Dropped {skipped} state changes");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        for signal in change.events.iter().filter_map(Signal::of) {
+            if let Err(e) = signal.emit(&emitter).await {
+                tracing::warn!("Failed to emit D-Bus signal: {e}");
+            }
+        }
+    }
+}
+
+/// Exports the object on the session bus and emits signals until the
+/// connection is closed.
+pub async fn serve(
+    handler: Arc<ServerHandler>,
+    events_tx: broadcast::Sender<Arc<StateChange>>,
+) -> Result<(), Box<dyn StdError>> {
+    serve_on(zbus::connection::Builder::session()?, handler, events_tx).await
+}
+
+/// [`serve`] on the bus `builder` connects to.
+async fn serve_on(
+    builder: zbus::connection::Builder<'_>,
+    handler: Arc<ServerHandler>,
+    events_tx: broadcast::Sender<Arc<StateChange>>,
+) -> Result<(), Box<dyn StdError>> {
+    let connection = builder
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, WindowManager { handler })?
+        .build()
+        .await
+        .map_err(|e| format!("Failed to register {BUS_NAME} on the session bus: {e}"))?;
+    tracing::info!("Serving {BUS_NAME} on the session bus");
+
+    let emitter = SignalEmitter::new(&connection, OBJECT_PATH)?;
+    publish_signals(events_tx.subscribe(), emitter).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cos_cli::JsonInfo;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::time::Duration;
+    use zvariant::Value as V;
+
+    fn params(entries: Vec<(&str, V<'static>)>) -> Params {
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn params_become_a_json_object() {
+        let params = params(vec![
+            ("app_id", V::from("firefox")),
+            ("workspace", V::from(2u32)),
+            ("workspace_group", V::Value(Box::new(V::from(1i64)))),
+            ("confirm", V::from(true)),
+        ]);
+        let json = params_to_json(&params);
+        assert_eq!(
+            json,
+            json!({"app_id": "firefox", "workspace": 2, "workspace_group": 1, "confirm": true})
+        );
+        assert!(matches!(
+            Operation::parse("move", json),
+            Ok(Operation::Move(_))
+        ));
+    }
+
+    #[test]
+    fn arrays_and_dicts_become_json() {
+        assert_eq!(to_json(&V::from(vec!["a", "b"])), json!(["a", "b"]));
+        let dict: HashMap<&str, V> = [("index", V::from(3u32))].into();
+        assert_eq!(to_json(&V::from(dict)), json!({"index": 3}));
+    }
+
+    #[test]
+    fn events_map_to_signals() {
+        let app = crate::test_app(3, "kitty");
+        let added = Event::AppAdded { app: app.clone() };
+        let removed = Event::AppRemoved { app: app.clone() };
+        let focused = Event::FocusChanged { app: app.clone() };
+        let activated = Event::WorkspaceActivated {
+            workspace: JsonWorkspaceRef {
+                group_index: 0,
+                index: 1,
+                workspace: "2".into(),
+            },
+        };
+        assert!(matches!(Signal::of(&added), Some(Signal::WindowAdded(app)) if app.id == 3));
+        assert!(matches!(Signal::of(&removed), Some(Signal::WindowRemoved(app)) if app.id == 3));
+        assert!(matches!(Signal::of(&focused), Some(Signal::WindowFocused(app)) if app.id == 3));
+        assert!(matches!(
+            Signal::of(&activated),
+            Some(Signal::WorkspaceActivated(workspace)) if workspace.index == 1
+        ));
+        assert!(Signal::of(&Event::TitleChanged { app }).is_none());
+        assert!(Signal::of(&Event::WorkspacesChanged).is_none());
+    }
+
+    /// Serves on a bus of its own, so no session bus is needed.
+    #[tokio::test]
+    #[ignore = "needs dbus-daemon"]
+    async fn serves_on_a_private_bus() {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("dbus-daemon");
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        let address = address.trim();
+
+        let (events_tx, _) = broadcast::channel(8);
+        let builder = zbus::connection::Builder::address(address).unwrap();
+        let server = serve_on(builder, ServerHandler::disconnected(), events_tx.clone());
+        let client = async {
+            let connection = zbus::connection::Builder::address(address)?.build().await?;
+            let proxy = zbus::Proxy::new(&connection, BUS_NAME, OBJECT_PATH, BUS_NAME).await?;
+            while events_tx.receiver_count() == 0 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+
+            let pong: String = proxy.call("Ping", &()).await?;
+            assert_eq!(pong, "pong");
+            let version: String = proxy.call("Version", &()).await?;
+            assert!(version.contains(env!("CARGO_PKG_VERSION")));
+
+            // Without a wayland thread the operations fail, with the error
+            // name of the ClientError
+            let params: HashMap<&str, V> = [("app_id", V::from("kitty"))].into();
+            match proxy.call::<_, _, String>("Close", &(params,)).await {
+                Err(zbus::Error::MethodError(name, _, _)) => {
+                    assert_eq!(name.as_str(), "org.cosmic_cli.Error.Disconnected")
+                }
+                result => panic!("Expected Disconnected, got {result:?}"),
+            }
+
+            let mut focused = proxy.receive_signal("WindowFocused").await?;
+            let change = StateChange {
+                events: vec![Event::FocusChanged {
+                    app: crate::test_app(3, "kitty"),
+                }],
+                state: JsonInfo {
+                    apps: Vec::new(),
+                    workspace_groups: Vec::new(),
+                    outputs: Vec::new(),
+                    seats: Vec::new(),
+                },
+            };
+            events_tx.send(Arc::new(change)).unwrap();
+            let signal = futures::StreamExt::next(&mut focused).await.unwrap();
+            let (id, app_id, title): (u32, String, String) = signal.body().deserialize()?;
+            assert_eq!((id, app_id.as_str(), title.as_str()), (3, "kitty", ""));
+            Ok::<_, zbus::Error>(())
+        };
+
+        let result = tokio::select! {
+            served = server => panic!("Server stopped: {served:?}"),
+            result = client => result,
+        };
+        let _ = daemon.kill();
+        let _ = daemon.wait();
+        result.unwrap();
+    }
+}
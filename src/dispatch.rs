@@ -9,22 +9,27 @@ use cosmic_protocols::workspace::v2::client::{
     zcosmic_workspace_handle_v2, zcosmic_workspace_manager_v2,
 };
 
-use wayland_client::Proxy;
+use wayland_client::backend::ObjectId;
 use wayland_client::protocol::wl_seat;
 use wayland_client::{
     Connection, Dispatch, QueueHandle, event_created_child,
     protocol::{wl_output, wl_registry},
 };
+use wayland_client::{Proxy, WEnum};
 use wayland_protocols::ext::workspace::v1::client::{
     ext_workspace_group_handle_v1, ext_workspace_handle_v1, ext_workspace_manager_v1,
 };
 
-use crate::{App, AppState, NamedHandle, State};
+use crate::events::Event;
+use crate::{App, AppState, JsonSeat, NamedHandle, State};
 
 pub fn bind(proxy: &wl_registry::WlRegistry, qh: &QueueHandle<AppState>, state: &mut AppState) {
     if let Some(items) = state.available_interfaces.get("ext_workspace_manager_v1") {
         for (name, version) in items {
             tracing::debug!("Bind ext_workspace_manager_v1 name: {name} version: {version}");
+            state
+                .bound_protocols
+                .insert("ext_workspace_manager_v1", *version);
             state.workspace_manager = proxy
                 .bind::<ext_workspace_manager_v1::ExtWorkspaceManagerV1, _, _>(
                     *name,
@@ -41,6 +46,9 @@ pub fn bind(proxy: &wl_registry::WlRegistry, qh: &QueueHandle<AppState>, state:
     {
         for (name, version) in items {
             tracing::debug!("Bind zcosmic_workspace_manager_v1 name: {name} version: {version}");
+            state
+                .bound_protocols
+                .insert("zcosmic_workspace_manager_v1", *version);
             proxy.bind::<zcosmic_workspace_manager_v1::ZcosmicWorkspaceManagerV1, _, _>(
                 *name,
                 *version,
@@ -55,6 +63,9 @@ pub fn bind(proxy: &wl_registry::WlRegistry, qh: &QueueHandle<AppState>, state:
     {
         for (name, version) in items {
             tracing::debug!("Bind zcosmic_toplevel_manager_v1 name: {name} version: {version}");
+            state
+                .bound_protocols
+                .insert("zcosmic_toplevel_manager_v1", *version);
             state.cosmic_toplevel_manager = Some(
                 proxy.bind::<zcosmic_toplevel_manager_v1::ZcosmicToplevelManagerV1, _, _>(
                     *name,
@@ -71,6 +82,9 @@ pub fn bind(proxy: &wl_registry::WlRegistry, qh: &QueueHandle<AppState>, state:
     {
         for (name, version) in items {
             tracing::debug!("Bind zcosmic_workspace_manager_v2 name: {name} version: {version}");
+            state
+                .bound_protocols
+                .insert("zcosmic_workspace_manager_v2", *version);
             proxy.bind::<zcosmic_workspace_manager_v2::ZcosmicWorkspaceManagerV2, _, _>(
                 *name,
                 *version,
@@ -85,6 +99,9 @@ pub fn bind(proxy: &wl_registry::WlRegistry, qh: &QueueHandle<AppState>, state:
     {
         for (name, version) in items {
             tracing::debug!("Bind zcosmic_workspace_handle_v2 name: {name} version: {version}");
+            state
+                .bound_protocols
+                .insert("zcosmic_workspace_handle_v2", *version);
             proxy.bind::<zcosmic_workspace_handle_v2::ZcosmicWorkspaceHandleV2, _, _>(
                 *name,
                 *version,
@@ -93,53 +110,107 @@ pub fn bind(proxy: &wl_registry::WlRegistry, qh: &QueueHandle<AppState>, state:
             );
         }
     }
-    if let Some(items) = state.available_interfaces.get("wl_output") {
+    if let Some(items) = state.available_interfaces.get("wl_output").cloned() {
         for (name, version) in items {
-            tracing::debug!("Bind wl_output name: {name} version: {version}");
-            proxy.bind::<wl_output::WlOutput, _, _>(*name, *version, qh, ());
+            bind_output(proxy, name, version, qh, state);
         }
     }
     if let Some(items) = state.available_interfaces.get("wl_seat") {
         for (name, version) in items {
             tracing::debug!("Bind wl_seat name: {name} version: {version}");
+            state.bound_protocols.insert("wl_seat", *version);
             proxy.bind::<wl_seat::WlSeat, _, _>(*name, *version, qh, ());
         }
     }
     if let Some(items) = state.available_interfaces.get("zcosmic_toplevel_info_v1") {
         for (name, version) in items {
             tracing::debug!("Bind zcosmic_toplevel_info_v1 name: {name} version: {version}");
+            state.bound_protocols.insert("zcosmic_toplevel_info_v1", 1);
             proxy.bind::<zcosmic_toplevel_info_v1::ZcosmicToplevelInfoV1, _, _>(*name, 1, qh, ());
         }
     }
+    state.globals_bound = true;
+}
+
+fn bind_output(
+    proxy: &wl_registry::WlRegistry,
+    name: u32,
+    version: u32,
+    qh: &QueueHandle<AppState>,
+    state: &mut AppState,
+) {
+    tracing::debug!("Bind wl_output name: {name} version: {version}");
+    state.bound_protocols.insert("wl_output", version);
+    let output = proxy.bind::<wl_output::WlOutput, _, _>(name, version, qh, ());
+    state.output_globals.insert(name, output.id());
+}
+
+/// Forgets an unplugged output.
+fn remove_output(state: &mut AppState, id: ObjectId) {
+    let output = state
+        .outputs
+        .iter()
+        .position(|o| o == &id)
+        .and_then(|index| state.json_output(index));
+    state.outputs.retain(|o| o != &id);
+    state.handle_map.output_model.remove(&id);
+    if let Some(output) = state.handle_map.output.remove(&id)
+        && output.handle.version() >= 3
+    {
+        output.handle.release();
+    }
+    for group in &mut state.workspace_groups {
+        group.outputs.retain(|o| o != &id);
+    }
+    for app in &mut state.apps {
+        app.outputs.retain(|o| o != &id);
+    }
+    if let Some(output) = output {
+        state.notify(Event::OutputRemoved { output });
+    }
 }
 
 impl Dispatch<wl_registry::WlRegistry, ()> for AppState {
     fn event(
         state: &mut Self,
-        _proxy: &wl_registry::WlRegistry,
+        proxy: &wl_registry::WlRegistry,
         event: wl_registry::Event,
         _data: &(),
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
+        qh: &QueueHandle<Self>,
     ) {
-        if let wl_registry::Event::Global {
-            name,
-            interface,
-            version,
-        } = event
-        {
-            tracing::debug!(
-                name = name,
-                interface = interface,
-                version = version,
-                "WlRegistry Global",
-            );
-            state
-                .available_interfaces
-                .entry(interface.clone())
-                .and_modify(|items| items.push((name, version)))
-                .or_insert_with(|| vec![(name, version)]);
-            state.notify();
+        match event {
+            wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } => {
+                tracing::debug!(
+                    name = name,
+                    interface = interface,
+                    version = version,
+                    "WlRegistry Global",
+                );
+                // Outputs plugged in after the initial burst
+                if state.globals_bound && interface == "wl_output" {
+                    bind_output(proxy, name, version, qh, state);
+                }
+                state
+                    .available_interfaces
+                    .entry(interface.clone())
+                    .and_modify(|items| items.push((name, version)))
+                    .or_insert_with(|| vec![(name, version)]);
+            }
+            wl_registry::Event::GlobalRemove { name } => {
+                tracing::debug!(name = name, "WlRegistry GlobalRemove");
+                for items in state.available_interfaces.values_mut() {
+                    items.retain(|(n, _)| *n != name);
+                }
+                if let Some(id) = state.output_globals.remove(&name) {
+                    remove_output(state, id);
+                }
+            }
+            _ => {}
         }
     }
 }
@@ -155,6 +226,12 @@ impl Dispatch<wl_output::WlOutput, ()> for AppState {
     ) {
         tracing::debug!(event = ?event, output = ?output, "WlOutput");
         match event {
+            wl_output::Event::Geometry { make, model, .. } => {
+                app_data
+                    .handle_map
+                    .output_model
+                    .insert(output.id(), (make, model));
+            }
             wl_output::Event::Name { name } => {
                 let output_id = output.id();
                 app_data.handle_map.output.insert(
@@ -162,7 +239,9 @@ impl Dispatch<wl_output::WlOutput, ()> for AppState {
                     NamedHandle::named(&name, output.to_owned()),
                 );
                 app_data.outputs.push(output_id);
-                app_data.notify();
+                if let Some(output) = app_data.json_output(app_data.outputs.len() - 1) {
+                    app_data.notify(Event::OutputAdded { output });
+                }
             }
             wl_output::Event::Done => {}
             _ => {}
@@ -187,7 +266,12 @@ impl Dispatch<wl_seat::WlSeat, ()> for AppState {
                 .seat
                 .insert(id.clone(), NamedHandle::named(&name, seat.to_owned()));
             app_data.seats.push(id);
-            app_data.notify();
+            app_data.notify(Event::SeatAdded {
+                seat: JsonSeat {
+                    index: app_data.seats.len() - 1,
+                    name,
+                },
+            });
         }
     }
 }
@@ -205,10 +289,11 @@ impl Dispatch<ext_workspace_manager_v1::ExtWorkspaceManagerV1, ()> for AppState
         if let ext_workspace_manager_v1::Event::WorkspaceGroup { workspace_group } = event {
             state.workspace_groups.push(crate::WorkspaceGroup {
                 object_id: workspace_group.id(),
+                handle: workspace_group,
                 workspaces: Vec::new(),
                 outputs: Vec::new(),
             });
-            state.notify();
+            state.notify(Event::WorkspacesChanged);
         }
     }
 
@@ -260,12 +345,37 @@ impl Dispatch<ext_workspace_handle_v1::ExtWorkspaceHandleV1, ()> for AppState {
         _qh: &QueueHandle<Self>,
     ) {
         tracing::debug!(event = ?event, proxy = ?proxy, "ExtWorkspaceHandleV1");
-        if let ext_workspace_handle_v1::Event::Name { name } = event {
-            state
-                .handle_map
-                .workspace_handle
-                .insert(proxy.id(), NamedHandle::named(&name, proxy.to_owned()));
-            state.notify();
+        match event {
+            ext_workspace_handle_v1::Event::Name { name } => {
+                state
+                    .handle_map
+                    .workspace_handle
+                    .insert(proxy.id(), NamedHandle::named(&name, proxy.to_owned()));
+                state.notify(Event::WorkspacesChanged);
+            }
+            ext_workspace_handle_v1::Event::State {
+                state: WEnum::Value(workspace_state),
+            } => {
+                let id = proxy.id();
+                let was_active = state.active_workspaces.contains(&id);
+                state.active_workspaces.retain(|w| w != &id);
+                if workspace_state.contains(ext_workspace_handle_v1::State::Active) {
+                    if !was_active && let Some(workspace) = state.json_workspace_ref(&id) {
+                        state.notify(Event::WorkspaceActivated { workspace });
+                    }
+                    state.active_workspaces.push(id);
+                }
+            }
+            ext_workspace_handle_v1::Event::Removed => {
+                let id = proxy.id();
+                state.handle_map.workspace_handle.remove(&id);
+                state.active_workspaces.retain(|w| w != &id);
+                for group in &mut state.workspace_groups {
+                    group.workspaces.retain(|w| w != &id);
+                }
+                state.notify(Event::WorkspacesChanged);
+            }
+            _ => {}
         }
     }
 }
@@ -280,19 +390,41 @@ impl Dispatch<ext_workspace_group_handle_v1::ExtWorkspaceGroupHandleV1, ()> for
         _qh: &QueueHandle<Self>,
     ) {
         tracing::debug!(event = ?event, proxy = ?proxy, "ExtWorkspaceGroupHandleV1");
-        if let ext_workspace_group_handle_v1::Event::WorkspaceEnter { workspace } = event {
-            let id = proxy.id();
-            if let Some(group) = state
-                .workspace_groups
-                .iter_mut()
-                .find(|g| g.object_id == id)
-            {
+        let id = proxy.id();
+        if let ext_workspace_group_handle_v1::Event::Removed = event {
+            state.workspace_groups.retain(|g| g.object_id != id);
+            state.notify(Event::WorkspacesChanged);
+            return;
+        }
+        let Some(group) = state
+            .workspace_groups
+            .iter_mut()
+            .find(|g| g.object_id == id)
+        else {
+            tracing::debug!("Workspace group not found");
+            return;
+        };
+        match event {
+            ext_workspace_group_handle_v1::Event::WorkspaceEnter { workspace } => {
                 group.workspaces.push(workspace.id());
-                state.notify();
-            } else {
-                tracing::debug!("Workspace group not found")
             }
+            ext_workspace_group_handle_v1::Event::WorkspaceLeave { workspace } => {
+                let workspace_id = workspace.id();
+                group.workspaces.retain(|w| w != &workspace_id);
+            }
+            ext_workspace_group_handle_v1::Event::OutputEnter { output } => {
+                let output_id = output.id();
+                if !group.outputs.contains(&output_id) {
+                    group.outputs.push(output_id);
+                }
+            }
+            ext_workspace_group_handle_v1::Event::OutputLeave { output } => {
+                let output_id = output.id();
+                group.outputs.retain(|o| o != &output_id);
+            }
+            _ => return,
         }
+        state.notify(Event::WorkspacesChanged);
     }
 }
 
@@ -347,8 +479,8 @@ impl Dispatch<zcosmic_toplevel_info_v1::ZcosmicToplevelInfoV1, ()> for AppState
                 outputs: Vec::new(),
                 workspaces: Vec::new(),
                 state: Vec::new(),
+                ready: false,
             });
-            app_data.notify();
         }
     }
 
@@ -371,47 +503,55 @@ impl Dispatch<zcosmic_toplevel_handle_v1::ZcosmicToplevelHandleV1, ()> for AppSt
         _: &QueueHandle<AppState>,
     ) {
         tracing::debug!(event = ?event, proxy = ?toplevel, "ZcosmicToplevelHandleV1");
+        let Some(index) = app_data.apps.iter().position(|t| &t.handle == toplevel) else {
+            return;
+        };
         if let zcosmic_toplevel_handle_v1::Event::Closed = event {
-            let app_id = app_data
-                .apps
-                .iter()
-                .find(|t| &t.handle == toplevel)
-                .and_then(|a| a.app_id.clone())
-                .unwrap_or_default();
-            tracing::debug!("Toplevel closed by compositor: {}", app_id);
-            app_data.apps.retain(|t| &t.handle != toplevel);
-            app_data.notify();
+            tracing::debug!(
+                "Toplevel closed by compositor: {}",
+                app_data.apps[index].app_id.as_deref().unwrap_or_default()
+            );
+            let app = app_data
+                .json_app(index)
+                .filter(|_| app_data.apps[index].ready);
+            app_data.apps.remove(index);
+            app_data.forget_focus(&toplevel.id());
+            if let Some(app) = app {
+                app_data.notify(Event::AppRemoved { app });
+            }
             return;
         }
-        let Some(info) = app_data.apps.iter_mut().find(|t| &t.handle == toplevel) else {
-            return;
-        };
+        let info = &mut app_data.apps[index];
         match event {
+            zcosmic_toplevel_handle_v1::Event::Done if !info.ready => {
+                info.ready = true;
+                app_data.notify_app(index, |app| Event::AppAdded { app });
+            }
             zcosmic_toplevel_handle_v1::Event::Title { title } => {
                 info.title = Some(title);
-                app_data.notify();
+                app_data.notify_app(index, |app| Event::TitleChanged { app });
             }
             zcosmic_toplevel_handle_v1::Event::AppId { app_id } => {
                 info.app_id = Some(app_id);
-                app_data.notify();
+                app_data.notify_app(index, |app| Event::AppIdChanged { app });
             }
             zcosmic_toplevel_handle_v1::Event::OutputEnter { output } => {
                 info.outputs.push(output.id());
-                app_data.notify();
+                app_data.notify_app(index, |app| Event::AppOutputChanged { app });
             }
             zcosmic_toplevel_handle_v1::Event::OutputLeave { output } => {
                 let output_id = output.id();
                 info.outputs.retain(|o| o != &output_id);
-                app_data.notify();
+                app_data.notify_app(index, |app| Event::AppOutputChanged { app });
             }
             zcosmic_toplevel_handle_v1::Event::ExtWorkspaceEnter { workspace } => {
                 info.workspaces.push(workspace.id());
-                app_data.notify();
+                app_data.notify_app(index, |app| Event::AppWorkspaceChanged { app });
             }
             zcosmic_toplevel_handle_v1::Event::ExtWorkspaceLeave { workspace } => {
                 let workspace_id = workspace.id();
                 info.workspaces.retain(|w| w != &workspace_id);
-                app_data.notify();
+                app_data.notify_app(index, |app| Event::AppWorkspaceChanged { app });
             }
             // zcosmic_toplevel_handle_v1::Event::WorkspaceEnter { workspace } => {
             //         info.workspaces.push(workspace);
@@ -420,12 +560,26 @@ impl Dispatch<zcosmic_toplevel_handle_v1::ZcosmicToplevelHandleV1, ()> for AppSt
             //         info.workspaces.retain(|w| w != &workspace);
             // }
             zcosmic_toplevel_handle_v1::Event::State { state } => {
-                info.state = state
-                    .chunks_exact(4)
-                    .map(|chunk| u32::from_ne_bytes(chunk.try_into().unwrap()))
-                    .flat_map(|val| State::try_from(val).ok())
-                    .collect::<Vec<_>>();
-                app_data.notify();
+                let previous = std::mem::replace(
+                    &mut info.state,
+                    state
+                        .chunks_exact(4)
+                        .map(|chunk| u32::from_ne_bytes(chunk.try_into().unwrap()))
+                        .flat_map(|val| State::try_from(val).ok())
+                        .collect::<Vec<_>>(),
+                );
+                if previous == info.state {
+                    return;
+                }
+                let activated = info.state.contains(&State::Activated);
+                let focused = activated && !previous.contains(&State::Activated);
+                if activated != previous.contains(&State::Activated) {
+                    app_data.record_focus(toplevel.id(), activated);
+                }
+                app_data.notify_app(index, |app| Event::AppStateChanged { app, previous });
+                if focused {
+                    app_data.notify_app(index, |app| Event::FocusChanged { app });
+                }
             }
             _ => {}
         }
@@ -0,0 +1,180 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use wayland_client::{DispatchError, backend::WaylandError};
+
+use crate::config::Target;
+
+/// Failure of a [`CosmicClient`](crate::CosmicClient) operation. Every variant
+/// maps to a stable JSON-RPC error code (see JSONRPC.md) and carries
+/// structured `data`.
+#[derive(Debug)]
+pub enum ClientError {
+    AppNotFound {
+        app_id: Option<String>,
+        index: Option<usize>,
+        id: Option<u32>,
+    },
+    WorkspaceNotFound {
+        workspace: Target,
+        workspace_group: Option<usize>,
+    },
+    OutputNotFound {
+        output_index: Option<usize>,
+    },
+    SeatNotFound {
+        seat: Option<usize>,
+    },
+    CapabilityMissing {
+        protocol: &'static str,
+    },
+    Timeout {
+        app_id: String,
+        wait: u64,
+    },
+    Disconnected {
+        reason: String,
+    },
+    InvalidParams(String),
+    Internal(String),
+}
+
+impl ClientError {
+    pub const APP_NOT_FOUND: i64 = -32001;
+    pub const WORKSPACE_NOT_FOUND: i64 = -32002;
+    pub const OUTPUT_NOT_FOUND: i64 = -32003;
+    pub const SEAT_NOT_FOUND: i64 = -32004;
+    pub const CAPABILITY_MISSING: i64 = -32005;
+    pub const TIMEOUT: i64 = -32006;
+    pub const DISCONNECTED: i64 = -32007;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL: i64 = -32603;
+
+    /// The JSON-RPC error code.
+    pub fn code(&self) -> i64 {
+        match self {
+            ClientError::AppNotFound { .. } => Self::APP_NOT_FOUND,
+            ClientError::WorkspaceNotFound { .. } => Self::WORKSPACE_NOT_FOUND,
+            ClientError::OutputNotFound { .. } => Self::OUTPUT_NOT_FOUND,
+            ClientError::SeatNotFound { .. } => Self::SEAT_NOT_FOUND,
+            ClientError::CapabilityMissing { .. } => Self::CAPABILITY_MISSING,
+            ClientError::Timeout { .. } => Self::TIMEOUT,
+            ClientError::Disconnected { .. } => Self::DISCONNECTED,
+            ClientError::InvalidParams(_) => Self::INVALID_PARAMS,
+            ClientError::Internal(_) => Self::INTERNAL,
+        }
+    }
+
+    /// The JSON-RPC error `data`.
+    pub fn data(&self) -> Option<serde_json::Value> {
+        let data = match self {
+            ClientError::AppNotFound { app_id, index, id } => {
+                serde_json::json!({ "app_id": app_id, "index": index, "id": id })
+            }
+            ClientError::WorkspaceNotFound {
+                workspace,
+                workspace_group,
+            } => serde_json::json!({ "workspace": workspace, "workspace_group": workspace_group }),
+            ClientError::OutputNotFound { output_index } => {
+                serde_json::json!({ "output_index": output_index })
+            }
+            ClientError::SeatNotFound { seat } => serde_json::json!({ "seat": seat }),
+            ClientError::CapabilityMissing { protocol } => {
+                serde_json::json!({ "protocol": protocol })
+            }
+            ClientError::Timeout { app_id, wait } => {
+                serde_json::json!({ "app_id": app_id, "wait": wait })
+            }
+            ClientError::Disconnected { reason } => serde_json::json!({ "reason": reason }),
+            ClientError::InvalidParams(_) | ClientError::Internal(_) => return None,
+        };
+        Some(data)
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::AppNotFound {
+                index: Some(index), ..
+            } => write!(f, "App index not found: {}", index),
+            ClientError::AppNotFound {
+                app_id: Some(app_id),
+                ..
+            } => write!(f, "App id not found: {}", app_id),
+            ClientError::AppNotFound { id: Some(id), .. } => {
+                write!(f, "Window id not found: {}", id)
+            }
+            ClientError::AppNotFound { .. } => write!(f, "App not found"),
+            ClientError::WorkspaceNotFound {
+                workspace_group: Some(group_index),
+                workspace,
+            } => write!(
+                f,
+                "Workspace {} not found in workspace group {}",
+                workspace, group_index
+            ),
+            ClientError::WorkspaceNotFound { workspace, .. } => {
+                write!(f, "Workspace not found: {}", workspace)
+            }
+            ClientError::OutputNotFound {
+                output_index: Some(index),
+            } => write!(f, "Output index not found: {}", index),
+            ClientError::OutputNotFound { .. } => write!(f, "No outputs found."),
+            ClientError::SeatNotFound { seat: Some(index) } => {
+                write!(f, "Seat index not found: {}", index)
+            }
+            ClientError::SeatNotFound { .. } => write!(f, "No seats found."),
+            ClientError::CapabilityMissing { protocol } => {
+                write!(f, "Compositor does not support {} protocol.", protocol)
+            }
+            ClientError::Timeout { app_id, wait } => {
+                write!(f, "App id not found after {}s: {}", wait, app_id)
+            }
+            ClientError::Disconnected { reason } => {
+                write!(f, "Compositor connection lost: {}", reason)
+            }
+            ClientError::InvalidParams(message) | ClientError::Internal(message) => {
+                f.write_str(message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// A [`ClientError`] in the shape of a JSON-RPC error object, for results
+/// that report errors per item.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorObject {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+impl From<&ClientError> for ErrorObject {
+    fn from(e: &ClientError) -> Self {
+        ErrorObject {
+            code: e.code(),
+            message: e.to_string(),
+            data: e.data(),
+        }
+    }
+}
+
+impl From<WaylandError> for ClientError {
+    fn from(e: WaylandError) -> Self {
+        ClientError::Disconnected {
+            reason: e.to_string(),
+        }
+    }
+}
+
+impl From<DispatchError> for ClientError {
+    fn from(e: DispatchError) -> Self {
+        ClientError::Disconnected {
+            reason: e.to_string(),
+        }
+    }
+}
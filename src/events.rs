@@ -0,0 +1,473 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{ClientError, JsonApp, JsonInfo, JsonOutput, JsonSeat, JsonWorkspaceRef, State};
+
+/// Kind of a state change, used by clients to pick what they subscribe to.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    AppAdded,
+    AppRemoved,
+    AppIdChanged,
+    TitleChanged,
+    AppStateChanged,
+    FocusChanged,
+    AppWorkspaceChanged,
+    AppOutputChanged,
+    OutputAdded,
+    OutputRemoved,
+    SeatAdded,
+    WorkspacesChanged,
+    WorkspaceActivated,
+}
+
+impl FromStr for EventKind {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(s.trim().into())
+            .map_err(|_| ClientError::InvalidParams(format!("Unknown event type: {s}")))
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            EventKind::AppAdded => "app_added",
+            EventKind::AppRemoved => "app_removed",
+            EventKind::AppIdChanged => "app_id_changed",
+            EventKind::TitleChanged => "title_changed",
+            EventKind::AppStateChanged => "app_state_changed",
+            EventKind::FocusChanged => "focus_changed",
+            EventKind::AppWorkspaceChanged => "app_workspace_changed",
+            EventKind::AppOutputChanged => "app_output_changed",
+            EventKind::OutputAdded => "output_added",
+            EventKind::OutputRemoved => "output_removed",
+            EventKind::SeatAdded => "seat_added",
+            EventKind::WorkspacesChanged => "workspaces_changed",
+            EventKind::WorkspaceActivated => "workspace_activated",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    AppAdded { app: JsonApp },
+    AppRemoved { app: JsonApp },
+    AppIdChanged { app: JsonApp },
+    TitleChanged { app: JsonApp },
+    AppStateChanged { app: JsonApp, previous: Vec<State> },
+    FocusChanged { app: JsonApp },
+    AppWorkspaceChanged { app: JsonApp },
+    AppOutputChanged { app: JsonApp },
+    OutputAdded { output: JsonOutput },
+    OutputRemoved { output: JsonOutput },
+    SeatAdded { seat: JsonSeat },
+    WorkspacesChanged,
+    WorkspaceActivated { workspace: JsonWorkspaceRef },
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::AppAdded { .. } => EventKind::AppAdded,
+            Event::AppRemoved { .. } => EventKind::AppRemoved,
+            Event::AppIdChanged { .. } => EventKind::AppIdChanged,
+            Event::TitleChanged { .. } => EventKind::TitleChanged,
+            Event::AppStateChanged { .. } => EventKind::AppStateChanged,
+            Event::FocusChanged { .. } => EventKind::FocusChanged,
+            Event::AppWorkspaceChanged { .. } => EventKind::AppWorkspaceChanged,
+            Event::AppOutputChanged { .. } => EventKind::AppOutputChanged,
+            Event::OutputAdded { .. } => EventKind::OutputAdded,
+            Event::OutputRemoved { .. } => EventKind::OutputRemoved,
+            Event::SeatAdded { .. } => EventKind::SeatAdded,
+            Event::WorkspacesChanged => EventKind::WorkspacesChanged,
+            Event::WorkspaceActivated { .. } => EventKind::WorkspaceActivated,
+        }
+    }
+
+    /// The app this event is about, if any.
+    pub fn app(&self) -> Option<&JsonApp> {
+        match self {
+            Event::AppAdded { app }
+            | Event::AppRemoved { app }
+            | Event::AppIdChanged { app }
+            | Event::TitleChanged { app }
+            | Event::AppStateChanged { app, .. }
+            | Event::FocusChanged { app }
+            | Event::AppWorkspaceChanged { app }
+            | Event::AppOutputChanged { app } => Some(app),
+            Event::OutputAdded { .. }
+            | Event::OutputRemoved { .. }
+            | Event::SeatAdded { .. }
+            | Event::WorkspacesChanged
+            | Event::WorkspaceActivated { .. } => None,
+        }
+    }
+}
+
+impl Event {
+    /// Two events with the same key describe the same thing, so only the
+    /// latest one needs to be delivered.
+    fn same_subject(&self, other: &Event) -> bool {
+        if self.kind() != other.kind() {
+            return false;
+        }
+        match (self, other) {
+            (Event::OutputAdded { output: a }, Event::OutputAdded { output: b })
+            | (Event::OutputRemoved { output: a }, Event::OutputRemoved { output: b }) => {
+                a.name == b.name
+            }
+            (Event::SeatAdded { seat: a }, Event::SeatAdded { seat: b }) => a.index == b.index,
+            // One active workspace per group
+            (
+                Event::WorkspaceActivated { workspace: a },
+                Event::WorkspaceActivated { workspace: b },
+            ) => a.group_index == b.group_index,
+            _ => self.app().map(|a| a.id) == other.app().map(|a| a.id),
+        }
+    }
+
+    /// Adds `event` to the end of `events` and removes an older event about
+    /// the same subject, so that the last events tell the final state.
+    pub fn coalesce(events: &mut Vec<Event>, mut event: Event) {
+        if let Some(position) = events.iter().position(|e| e.same_subject(&event)) {
+            let mut older = events.remove(position);
+            // Keep the state from before the whole burst
+            if let (
+                Event::AppStateChanged { previous, .. },
+                Event::AppStateChanged {
+                    previous: newer_previous,
+                    ..
+                },
+            ) = (&mut older, &mut event)
+            {
+                std::mem::swap(newer_previous, previous);
+            }
+        }
+        events.push(event);
+    }
+}
+
+/// A batch of coalesced state changes together with the state they produced.
+#[derive(Debug, Clone)]
+pub struct StateChange {
+    pub events: Vec<Event>,
+    pub state: JsonInfo,
+}
+
+impl StateChange {
+    /// Folds a later batch into this one.
+    pub fn merge(&mut self, later: &StateChange) {
+        for event in &later.events {
+            Event::coalesce(&mut self.events, event.clone());
+        }
+        self.state = later.state.clone();
+    }
+}
+
+/// Params of the `state_change` JSON-RPC notification.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Notification {
+    /// Increases by one per notification sent to a client
+    pub seq: u64,
+    /// Changes were dropped because the client read too slowly, so `events`
+    /// is incomplete. `state` is still the current state.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lagged: bool,
+    pub events: Vec<Event>,
+    pub state: JsonInfo,
+}
+
+// ---------------------------------------------------------------------------
+// Subscriptions
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SubscribeParams {
+    /// Event kinds to receive. Empty means every kind.
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+    /// Only events about apps whose app_id contains this (case-insensitive).
+    #[serde(default)]
+    pub app_id: Option<String>,
+    /// Only events about apps whose title contains this (case-insensitive).
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct UnsubscribeParams {
+    /// Subscription to remove. When omitted all subscriptions are removed.
+    #[serde(default)]
+    pub subscription: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SubscribeResult {
+    /// Id to pass to `unsubscribe`
+    pub subscription: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UnsubscribeResult {
+    /// Number of removed subscriptions
+    pub removed: usize,
+}
+
+impl SubscribeParams {
+    fn matches(&self, event: &Event) -> bool {
+        if !self.events.is_empty() && !self.events.contains(&event.kind()) {
+            return false;
+        }
+        if self.app_id.is_none() && self.title.is_none() {
+            return true;
+        }
+        let Some(app) = event.app() else {
+            return false;
+        };
+        let contains =
+            |value: &str, pattern: &str| value.to_lowercase().contains(&pattern.to_lowercase());
+        self.app_id
+            .as_deref()
+            .is_none_or(|pattern| contains(&app.app_id, pattern))
+            && self
+                .title
+                .as_deref()
+                .is_none_or(|pattern| contains(&app.title, pattern))
+    }
+}
+
+/// Per-client set of subscriptions. Nothing matches until the client subscribes.
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    next_id: u64,
+    items: HashMap<u64, SubscribeParams>,
+}
+
+impl Subscriptions {
+    pub fn subscribe(&mut self, params: SubscribeParams) -> u64 {
+        self.next_id += 1;
+        self.items.insert(self.next_id, params);
+        self.next_id
+    }
+
+    /// Returns the number of removed subscriptions.
+    pub fn unsubscribe(&mut self, subscription: Option<u64>) -> usize {
+        match subscription {
+            Some(id) => self.items.remove(&id).map_or(0, |_| 1),
+            None => {
+                let count = self.items.len();
+                self.items.clear();
+                count
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        self.items.values().any(|s| s.matches(event))
+    }
+
+    /// Returns the events of `change` the client subscribed to.
+    pub fn filter(&self, change: &StateChange) -> Vec<Event> {
+        change
+            .events
+            .iter()
+            .filter(|e| self.matches(e))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(id: u32, app_id: &str, title: &str) -> JsonApp {
+        JsonApp {
+            title: title.into(),
+            ..crate::test_app(id, app_id)
+        }
+    }
+
+    #[test]
+    fn nothing_matches_without_subscriptions() {
+        let subscriptions = Subscriptions::default();
+        assert!(!subscriptions.matches(&Event::WorkspacesChanged));
+    }
+
+    #[test]
+    fn subscription_filters_by_kind() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(SubscribeParams {
+            events: vec![EventKind::AppAdded],
+            ..Default::default()
+        });
+        let firefox = app(1, "firefox", "Mozilla Firefox");
+        assert!(subscriptions.matches(&Event::AppAdded {
+            app: firefox.clone()
+        }));
+        assert!(!subscriptions.matches(&Event::AppRemoved { app: firefox }));
+    }
+
+    #[test]
+    fn subscription_filters_by_app_id_and_title() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(SubscribeParams {
+            app_id: Some("FIRE".into()),
+            title: Some("mozilla".into()),
+            ..Default::default()
+        });
+        let matching = app(1, "firefox", "Mozilla Firefox");
+        let other_title = app(2, "firefox", "Private Browsing");
+        assert!(subscriptions.matches(&Event::TitleChanged { app: matching }));
+        assert!(!subscriptions.matches(&Event::TitleChanged { app: other_title }));
+        // Events without an app never match an app filter
+        assert!(!subscriptions.matches(&Event::WorkspacesChanged));
+    }
+
+    #[test]
+    fn unsubscribe_removes_one_or_all() {
+        let mut subscriptions = Subscriptions::default();
+        let first = subscriptions.subscribe(SubscribeParams::default());
+        let second = subscriptions.subscribe(SubscribeParams::default());
+        assert_ne!(first, second);
+        assert_eq!(subscriptions.unsubscribe(Some(first)), 1);
+        assert_eq!(subscriptions.unsubscribe(Some(first)), 0);
+        assert!(subscriptions.matches(&Event::WorkspacesChanged));
+        subscriptions.subscribe(SubscribeParams::default());
+        assert_eq!(subscriptions.unsubscribe(None), 2);
+        assert!(!subscriptions.matches(&Event::WorkspacesChanged));
+    }
+
+    #[test]
+    fn coalesce_moves_the_newer_event_last() {
+        let (a, b) = (app(1, "firefox", "A"), app(2, "kitty", "B"));
+        let mut events = Vec::new();
+        Event::coalesce(&mut events, Event::TitleChanged { app: a.clone() });
+        Event::coalesce(&mut events, Event::TitleChanged { app: b.clone() });
+        Event::coalesce(
+            &mut events,
+            Event::TitleChanged {
+                app: app(1, "firefox", "A2"),
+            },
+        );
+        let titles = events
+            .iter()
+            .map(|e| e.app().unwrap().title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["B", "A2"]);
+        // Another kind about the same app is a different subject
+        Event::coalesce(&mut events, Event::FocusChanged { app: a });
+        assert_eq!(events.len(), 3);
+    }
+
+    #[test]
+    fn coalesce_ends_with_the_last_focus() {
+        let (a, b) = (app(1, "firefox", "A"), app(2, "kitty", "B"));
+        let mut events = Vec::new();
+        for app in [&a, &b, &a] {
+            Event::coalesce(&mut events, Event::FocusChanged { app: app.clone() });
+        }
+        let focused = events
+            .iter()
+            .map(|e| e.app().unwrap().id)
+            .collect::<Vec<_>>();
+        assert_eq!(focused, [2, 1]);
+    }
+
+    #[test]
+    fn coalesce_ends_with_a_readded_output() {
+        let output = JsonOutput {
+            index: 0,
+            name: "DP-1".into(),
+            make: String::new(),
+            model: String::new(),
+        };
+        let mut events = Vec::new();
+        Event::coalesce(
+            &mut events,
+            Event::OutputAdded {
+                output: output.clone(),
+            },
+        );
+        Event::coalesce(
+            &mut events,
+            Event::OutputRemoved {
+                output: output.clone(),
+            },
+        );
+        Event::coalesce(&mut events, Event::OutputAdded { output });
+        assert!(matches!(
+            &events[..],
+            [Event::OutputRemoved { .. }, Event::OutputAdded { .. }]
+        ));
+    }
+
+    #[test]
+    fn coalesce_keeps_the_first_previous_state() {
+        let mut events = Vec::new();
+        let app = app(1, "firefox", "A");
+        Event::coalesce(
+            &mut events,
+            Event::AppStateChanged {
+                app: app.clone(),
+                previous: vec![State::Minimized],
+            },
+        );
+        Event::coalesce(
+            &mut events,
+            Event::AppStateChanged {
+                app,
+                previous: vec![State::Maximized],
+            },
+        );
+        assert!(matches!(
+            &events[..],
+            [Event::AppStateChanged { previous, .. }] if previous == &[State::Minimized]
+        ));
+    }
+
+    #[test]
+    fn filter_keeps_subscribed_events_in_order() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(SubscribeParams {
+            events: vec![EventKind::AppAdded, EventKind::AppRemoved],
+            ..Default::default()
+        });
+        let change = StateChange {
+            events: vec![
+                Event::AppRemoved {
+                    app: app(1, "firefox", "A"),
+                },
+                Event::WorkspacesChanged,
+                Event::AppAdded {
+                    app: app(2, "kitty", "B"),
+                },
+            ],
+            state: JsonInfo {
+                apps: Vec::new(),
+                workspace_groups: Vec::new(),
+                outputs: Vec::new(),
+                seats: Vec::new(),
+            },
+        };
+        let kinds = subscriptions
+            .filter(&change)
+            .iter()
+            .map(Event::kind)
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, [EventKind::AppRemoved, EventKind::AppAdded]);
+    }
+}
@@ -0,0 +1,99 @@
+//! Focus history: the open windows in the order they last got the focus,
+//! built from the `activated` state transitions the compositor reports.
+//!
+//! Every client keeps one, but only a long-running one such as
+//! `cos-cli serve` sees enough transitions for it to be useful. A client that
+//! just connected only knows the window that has the focus now.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use wayland_client::{Proxy, backend::ObjectId};
+
+use crate::{AppState, JsonApp};
+
+/// When a window got and lost the focus, as Unix time in milliseconds.
+#[derive(Debug, Clone)]
+pub(crate) struct Focus {
+    toplevel: ObjectId,
+    focused_at: u64,
+    unfocused_at: Option<u64>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct HistoryParams {
+    /// At most this many windows (default: all)
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JsonFocus {
+    pub app: JsonApp,
+    /// Unix time in milliseconds the window last got the focus
+    pub focused_at: u64,
+    /// Unix time in milliseconds the window lost the focus, missing while it
+    /// has it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unfocused_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct HistoryResult {
+    /// Most recently focused first
+    pub windows: Vec<JsonFocus>,
+}
+
+impl AppState {
+    /// Moves `toplevel` to the front of the history when it got the focus,
+    /// or records when it lost it.
+    pub(crate) fn record_focus(&mut self, toplevel: ObjectId, focused: bool) {
+        let now = now();
+        if focused {
+            self.focus_history.retain(|f| f.toplevel != toplevel);
+            self.focus_history.insert(
+                0,
+                Focus {
+                    toplevel,
+                    focused_at: now,
+                    unfocused_at: None,
+                },
+            );
+        } else if let Some(focus) = self
+            .focus_history
+            .iter_mut()
+            .find(|f| f.toplevel == toplevel)
+        {
+            focus.unfocused_at = Some(now);
+        }
+    }
+
+    pub(crate) fn forget_focus(&mut self, toplevel: &ObjectId) {
+        self.focus_history.retain(|f| &f.toplevel != toplevel);
+    }
+
+    /// The windows of the history, most recently focused first.
+    pub(crate) fn json_history(&self) -> Vec<JsonFocus> {
+        self.focus_history
+            .iter()
+            .filter_map(|focus| {
+                let index = self
+                    .apps
+                    .iter()
+                    .position(|app| app.ready && app.handle.id() == focus.toplevel)?;
+                Some(JsonFocus {
+                    app: self.json_app(index)?,
+                    focused_at: focus.focused_at,
+                    unfocused_at: focus.unfocused_at,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,388 @@
+//! Runs shell commands on state changes, for `cos-cli watch --on` and the
+//! `[hooks]` section of the config, which `serve` and `rules` run as well.
+//!
+//! Every event type has a worker thread that runs its commands one after
+//! another with the event in environment variables. A command runs in its own
+//! process group, which is killed when it runs longer than the timeout, and
+//! events that arrive while the queue of their type is full are dropped.
+
+use rustix::process::{Pid, Signal, kill_process_group};
+use std::collections::BTreeMap;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError, sync_channel};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cos_cli::config::Hooks;
+use cos_cli::events::{Event, EventKind};
+use cos_cli::{CosmicClient, JsonApp, State};
+
+/// Seconds a command may run when neither `--timeout` nor the config says.
+pub const DEFAULT_TIMEOUT: u64 = 10;
+/// Events of one type waiting for their commands.
+const QUEUE: usize = 32;
+/// How often a running command is checked for completion.
+const POLL: Duration = Duration::from_millis(20);
+
+type Env = Vec<(&'static str, String)>;
+
+pub struct HookSettings {
+    /// A command is killed after this
+    pub timeout: Duration,
+    /// Least time between two runs for the same event type
+    pub interval: Duration,
+}
+
+pub struct HookRunner {
+    queues: BTreeMap<EventKind, SyncSender<Env>>,
+    /// The window that had the focus before the last `focus_changed`
+    focused: Option<JsonApp>,
+}
+
+impl HookRunner {
+    /// Starts a worker per event type in `hooks`. `focused` is the window
+    /// that has the focus now.
+    pub fn new(
+        hooks: BTreeMap<EventKind, Vec<String>>,
+        settings: HookSettings,
+        focused: Option<JsonApp>,
+    ) -> Self {
+        let mut queues = BTreeMap::new();
+        for (kind, commands) in hooks {
+            let (tx, rx) = sync_channel(QUEUE);
+            let (timeout, interval) = (settings.timeout, settings.interval);
+            thread::spawn(move || work(kind, &commands, rx, timeout, interval));
+            queues.insert(kind, tx);
+        }
+        Self { queues, focused }
+    }
+
+    /// Starts the hooks of the config and `extra` ones. `timeout` and
+    /// `interval` override the settings of the config.
+    pub fn with_config(
+        client: &CosmicClient,
+        extra: Vec<(EventKind, String)>,
+        timeout: Option<u64>,
+        interval: Option<u64>,
+    ) -> Self {
+        let config: &Hooks = &client.config().hooks;
+        let mut commands = BTreeMap::<EventKind, Vec<String>>::new();
+        for (kind, configured) in &config.on {
+            commands
+                .entry(*kind)
+                .or_default()
+                .extend(configured.iter().cloned());
+        }
+        for (kind, command) in extra {
+            commands.entry(kind).or_default().push(command);
+        }
+
+        let settings = HookSettings {
+            timeout: Duration::from_secs(timeout.or(config.timeout).unwrap_or(DEFAULT_TIMEOUT)),
+            interval: Duration::from_millis(interval.or(config.interval).unwrap_or(0)),
+        };
+        let focused = client
+            .windows()
+            .into_iter()
+            .find(|app| app.state.contains(&State::Activated));
+        Self::new(commands, settings, focused)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    /// Queues the commands of `event`.
+    pub fn handle(&mut self, event: &Event) {
+        let kind = event.kind();
+        if let Some(queue) = self.queues.get(&kind) {
+            match queue.try_send(environment(event, self.focused.as_ref())) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    tracing::warn!("Dropped {kind} hook, {QUEUE} are waiting already")
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    tracing::error!("The {kind} hook worker stopped")
+                }
+            }
+        }
+        if let Event::FocusChanged { app } = event {
+            self.focused = Some(app.clone());
+        }
+    }
+}
+
+fn work(
+    kind: EventKind,
+    commands: &[String],
+    rx: Receiver<Env>,
+    timeout: Duration,
+    interval: Duration,
+) {
+    let mut last_run: Option<Instant> = None;
+    for env in rx {
+        if let Some(last_run) = last_run {
+            thread::sleep(interval.saturating_sub(last_run.elapsed()));
+        }
+        last_run = Some(Instant::now());
+        for command in commands {
+            run(kind, command, &env, timeout);
+        }
+    }
+}
+
+fn run(kind: EventKind, command: &str, env: &Env, timeout: Duration) {
+    tracing::debug!("Running {kind} hook: {command}");
+    let spawned = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().cloned())
+        .stdin(Stdio::null())
+        // So that the processes the command starts are killed with it
+        .process_group(0)
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            tracing::warn!("Failed to run {kind} hook '{command}': {e}");
+            return;
+        }
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return,
+            Ok(Some(status)) => {
+                tracing::warn!("{kind} hook '{command}' failed: {status}");
+                return;
+            }
+            Ok(None) if Instant::now() >= deadline => {
+                tracing::warn!(
+                    "{kind} hook '{command}' killed after {}s",
+                    timeout.as_secs()
+                );
+                // The group has the id of the command, which leads it
+                let _ = kill_process_group(Pid::from_child(&child), Signal::KILL);
+                let _ = child.wait();
+                return;
+            }
+            Ok(None) => thread::sleep(POLL),
+            Err(e) => {
+                tracing::warn!("{kind} hook '{command}': {e}");
+                return;
+            }
+        }
+    }
+}
+
+/// The environment variables describing `event`. `focused` is the window
+/// that had the focus before it.
+fn environment(event: &Event, focused: Option<&JsonApp>) -> Env {
+    let mut env = vec![
+        ("COS_EVENT", event.kind().to_string()),
+        (
+            "COS_EVENT_JSON",
+            serde_json::to_string(event).unwrap_or_default(),
+        ),
+    ];
+    if let Some(app) = event.app() {
+        env.push(("COS_APP_ID", app.app_id.clone()));
+        env.push(("COS_TITLE", app.title.clone()));
+        env.push(("COS_WINDOW_ID", app.id.to_string()));
+        env.push(("COS_APP_INDEX", app.index.to_string()));
+        env.push(("COS_STATE", join(&app.state)));
+        if let Some(workspace) = app.workspaces.first() {
+            env.push(("COS_WORKSPACE", workspace.workspace.clone()));
+            env.push(("COS_WORKSPACE_INDEX", workspace.index.to_string()));
+            env.push(("COS_WORKSPACE_GROUP", workspace.group_index.to_string()));
+        }
+        if let Some(output) = app.outputs.first() {
+            env.push(("COS_OUTPUT", output.name.clone()));
+        }
+    }
+    match event {
+        Event::AppStateChanged { previous, .. } => {
+            env.push(("COS_PREVIOUS_STATE", join(previous)));
+        }
+        Event::FocusChanged { .. } => {
+            if let Some(previous) = focused {
+                env.push(("COS_PREVIOUS_APP_ID", previous.app_id.clone()));
+                env.push(("COS_PREVIOUS_TITLE", previous.title.clone()));
+                env.push(("COS_PREVIOUS_WINDOW_ID", previous.id.to_string()));
+            }
+        }
+        Event::WorkspaceActivated { workspace } => {
+            env.push(("COS_WORKSPACE", workspace.workspace.clone()));
+            env.push(("COS_WORKSPACE_INDEX", workspace.index.to_string()));
+            env.push(("COS_WORKSPACE_GROUP", workspace.group_index.to_string()));
+        }
+        Event::OutputAdded { output } | Event::OutputRemoved { output } => {
+            env.push(("COS_OUTPUT", output.name.clone()));
+            env.push(("COS_OUTPUT_MAKE", output.make.clone()));
+            env.push(("COS_OUTPUT_MODEL", output.model.clone()));
+        }
+        Event::SeatAdded { seat } => env.push(("COS_SEAT", seat.name.clone())),
+        _ => {}
+    }
+    env
+}
+
+fn join(states: &[cos_cli::State]) -> String {
+    states
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cos_cli::{JsonOutput, JsonOutputRef, JsonSeat, JsonWorkspaceRef};
+
+    fn var<'a>(env: &'a Env, name: &str) -> Option<&'a str> {
+        env.iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn workspace() -> JsonWorkspaceRef {
+        JsonWorkspaceRef {
+            group_index: 1,
+            index: 2,
+            workspace: "Web".into(),
+        }
+    }
+
+    #[test]
+    fn window_events_describe_the_window() {
+        let app = JsonApp {
+            title: "GitHub".into(),
+            state: vec![State::Maximized, State::Activated],
+            outputs: vec![JsonOutputRef {
+                index: 0,
+                name: "DP-1".into(),
+            }],
+            workspaces: vec![workspace()],
+            ..crate::test_app(7, "firefox")
+        };
+        let env = environment(
+            &Event::AppStateChanged {
+                app,
+                previous: vec![State::Minimized],
+            },
+            None,
+        );
+        assert_eq!(var(&env, "COS_EVENT"), Some("app_state_changed"));
+        assert_eq!(var(&env, "COS_APP_ID"), Some("firefox"));
+        assert_eq!(var(&env, "COS_TITLE"), Some("GitHub"));
+        assert_eq!(var(&env, "COS_WINDOW_ID"), Some("7"));
+        assert_eq!(var(&env, "COS_STATE"), Some("maximized,activated"));
+        assert_eq!(var(&env, "COS_PREVIOUS_STATE"), Some("minimized"));
+        assert_eq!(var(&env, "COS_WORKSPACE"), Some("Web"));
+        assert_eq!(var(&env, "COS_WORKSPACE_INDEX"), Some("2"));
+        assert_eq!(var(&env, "COS_WORKSPACE_GROUP"), Some("1"));
+        assert_eq!(var(&env, "COS_OUTPUT"), Some("DP-1"));
+        assert!(var(&env, "COS_EVENT_JSON").is_some_and(|json| json.contains("\"previous\"")));
+    }
+
+    #[test]
+    fn focus_changes_describe_the_previous_window() {
+        let event = Event::FocusChanged {
+            app: crate::test_app(2, "kitty"),
+        };
+        let previous = JsonApp {
+            title: "GitHub".into(),
+            ..crate::test_app(1, "firefox")
+        };
+        let env = environment(&event, Some(&previous));
+        assert_eq!(var(&env, "COS_APP_ID"), Some("kitty"));
+        assert_eq!(var(&env, "COS_PREVIOUS_APP_ID"), Some("firefox"));
+        assert_eq!(var(&env, "COS_PREVIOUS_TITLE"), Some("GitHub"));
+        assert_eq!(var(&env, "COS_PREVIOUS_WINDOW_ID"), Some("1"));
+        assert!(var(&environment(&event, None), "COS_PREVIOUS_APP_ID").is_none());
+    }
+
+    #[test]
+    fn other_events_describe_their_subject() {
+        let env = environment(
+            &Event::WorkspaceActivated {
+                workspace: workspace(),
+            },
+            None,
+        );
+        assert_eq!(var(&env, "COS_WORKSPACE"), Some("Web"));
+        assert_eq!(var(&env, "COS_WORKSPACE_GROUP"), Some("1"));
+        assert!(var(&env, "COS_APP_ID").is_none());
+
+        let output = JsonOutput {
+            index: 0,
+            name: "HDMI-A-1".into(),
+            make: "Dell".into(),
+            model: "U2720Q".into(),
+        };
+        let env = environment(&Event::OutputRemoved { output }, None);
+        assert_eq!(var(&env, "COS_EVENT"), Some("output_removed"));
+        assert_eq!(var(&env, "COS_OUTPUT"), Some("HDMI-A-1"));
+        assert_eq!(var(&env, "COS_OUTPUT_MAKE"), Some("Dell"));
+        assert_eq!(var(&env, "COS_OUTPUT_MODEL"), Some("U2720Q"));
+
+        let seat = JsonSeat {
+            index: 0,
+            name: "seat0".into(),
+        };
+        let env = environment(&Event::SeatAdded { seat }, None);
+        assert_eq!(var(&env, "COS_SEAT"), Some("seat0"));
+    }
+
+    #[test]
+    fn runs_of_one_event_type_keep_the_interval() {
+        let (tx, rx) = sync_channel(QUEUE);
+        tx.send(Vec::new()).unwrap();
+        tx.send(Vec::new()).unwrap();
+        drop(tx);
+        let interval = Duration::from_millis(300);
+        let started = Instant::now();
+        work(
+            EventKind::AppAdded,
+            &["true".into()],
+            rx,
+            Duration::from_secs(5),
+            interval,
+        );
+        assert!(started.elapsed() >= interval);
+    }
+
+    #[test]
+    fn a_command_over_time_is_killed_with_its_children() {
+        let pid_file =
+            std::env::temp_dir().join(format!("cos-cli-hook-test-{}", std::process::id()));
+        let command = format!("sleep 10 & echo $! > {}; wait", pid_file.display());
+        let started = Instant::now();
+        run(
+            EventKind::AppAdded,
+            &command,
+            &Vec::new(),
+            Duration::from_millis(200),
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let _ = std::fs::remove_file(&pid_file);
+        // Gone, or a zombie until its new parent reaps it
+        let stat = format!("/proc/{}/stat", pid.trim());
+        let killed = || {
+            std::fs::read_to_string(&stat).map_or(true, |stat| {
+                stat.rsplit(')')
+                    .next()
+                    .is_some_and(|s| s.trim_start().starts_with('Z'))
+            })
+        };
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !killed() && Instant::now() < deadline {
+            thread::sleep(POLL);
+        }
+        assert!(killed(), "sleep {} survived the timeout", pid.trim());
+    }
+}
@@ -0,0 +1,73 @@
+//! Applies the layout profile of the connected outputs when outputs are
+//! plugged in or removed, for `serve` and `rules`.
+
+use std::time::{Duration, Instant};
+
+use cos_cli::CosmicClient;
+
+/// How long the outputs must stay the same before the profile is applied.
+/// Docking connects several outputs one after another, and their workspace
+/// groups follow later.
+const SETTLE: Duration = Duration::from_secs(2);
+
+pub struct Hotplug {
+    /// Names of the connected outputs, sorted
+    outputs: Vec<String>,
+    changed: Option<Instant>,
+}
+
+fn output_names(client: &CosmicClient) -> Vec<String> {
+    let mut names = client
+        .outputs()
+        .into_iter()
+        .map(|o| o.name)
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+impl Hotplug {
+    pub fn new(client: &CosmicClient) -> Self {
+        Self {
+            outputs: output_names(client),
+            changed: None,
+        }
+    }
+
+    /// Applies the profile once the outputs changed and settled. Called after
+    /// every dispatch.
+    pub fn poll(&mut self, client: &mut CosmicClient) {
+        let outputs = output_names(client);
+        if outputs != self.outputs {
+            tracing::info!("Outputs changed: {}", outputs.join(", "));
+            self.outputs = outputs;
+            self.changed = Some(Instant::now());
+            return;
+        }
+        if self
+            .changed
+            .is_some_and(|changed| changed.elapsed() >= SETTLE)
+        {
+            self.changed = None;
+            apply(client);
+        }
+    }
+}
+
+/// Applies the profile of the connected outputs, if there is one.
+pub fn apply(client: &mut CosmicClient) {
+    let config = client.config().clone();
+    let Some((name, profile)) = config.profile(&client.outputs()) else {
+        if !config.profiles.is_empty() {
+            tracing::info!("No profile for the connected outputs");
+        }
+        return;
+    };
+    tracing::info!("Applying profile '{name}'");
+    for outcome in client.apply_profile(profile) {
+        match outcome {
+            Ok(placed) => tracing::info!("Profile '{name}': {placed}"),
+            Err(e) => tracing::warn!("Profile '{name}': {e}"),
+        }
+    }
+}
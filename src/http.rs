@@ -0,0 +1,390 @@
+//! `serve --http`: REST-ish routes for the operations and a WebSocket that
+//! streams `state_change` notifications, backed by the same wayland thread
+//! as the JSON-RPC transports.
+
+use axum::body::Bytes;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{HeaderValue, Method, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::error::Error as StdError;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::channel;
+
+use cos_cli::error::ErrorObject;
+use cos_cli::events::{EventKind, StateChange, SubscribeParams, Subscriptions};
+use cos_cli::{ClientError, Operation};
+
+use crate::server::{self, ServerHandler};
+
+#[derive(Debug)]
+pub struct HttpArgs {
+    pub addr: SocketAddr,
+    /// Required as `Authorization: Bearer <token>` or `?token=<token>`
+    pub token: Option<String>,
+    /// Allow listening on other than loopback addresses
+    pub allow_remote: bool,
+}
+
+#[derive(Clone)]
+struct HttpState {
+    handler: Arc<ServerHandler>,
+    events_tx: broadcast::Sender<Arc<StateChange>>,
+    debounce: Duration,
+    token: Option<Arc<str>>,
+}
+
+/// A [`ClientError`] as HTTP response: a status for the error kind and the
+/// JSON-RPC error object as body.
+struct ApiError(ClientError);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match &self.0 {
+            ClientError::AppNotFound { .. }
+            | ClientError::WorkspaceNotFound { .. }
+            | ClientError::OutputNotFound { .. }
+            | ClientError::SeatNotFound { .. } => StatusCode::NOT_FOUND,
+            ClientError::InvalidParams(_) => StatusCode::BAD_REQUEST,
+            ClientError::CapabilityMissing { .. } => StatusCode::NOT_IMPLEMENTED,
+            ClientError::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            ClientError::Disconnected { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ClientError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(ErrorObject::from(&self.0))).into_response()
+    }
+}
+
+type ApiResult = Result<Json<Value>, ApiError>;
+
+async fn execute(state: &HttpState, method: &str, params: Value) -> ApiResult {
+    let operation = Operation::parse(method, params).map_err(ApiError)?;
+    state
+        .handler
+        .execute(operation)
+        .await
+        .map(Json)
+        .map_err(ApiError)
+}
+
+/// The request body as params object. An empty body means no params.
+fn params(body: &Bytes) -> Result<Value, ApiError> {
+    if body.is_empty() {
+        return Ok(json!({}));
+    }
+    match serde_json::from_slice(body) {
+        Ok(params @ Value::Object(_)) => Ok(params),
+        Ok(_) => Err(ApiError(ClientError::InvalidParams(
+            "Body must be a JSON object".into(),
+        ))),
+        Err(e) => Err(ApiError(ClientError::InvalidParams(e.to_string()))),
+    }
+}
+
+/// `GET /info`, or one of its fields for `/windows`, `/workspaces` and
+/// `/outputs`.
+async fn info(state: &HttpState, field: Option<&str>) -> ApiResult {
+    let Json(mut info) = execute(state, "info", json!({})).await?;
+    Ok(Json(match field {
+        Some(field) => info[field].take(),
+        None => info,
+    }))
+}
+
+/// `POST /windows/{id}/{action}` for `move`, `activate`, `state` and
+/// `close`. Windows are addressed by their protocol id, which unlike the
+/// index does not change when other windows close.
+async fn window_action(
+    State(state): State<HttpState>,
+    Path((id, action)): Path<(u32, String)>,
+    body: Bytes,
+) -> ApiResult {
+    if !["move", "activate", "state", "close"].contains(&action.as_str()) {
+        return Err(ApiError(ClientError::InvalidParams(format!(
+            "Unknown window action: {action}"
+        ))));
+    }
+    let mut params = params(&body)?;
+    params["id"] = id.into();
+    execute(&state, &action, params).await
+}
+
+/// `POST /workspaces/{index}/activate`.
+async fn workspace_activate(
+    State(state): State<HttpState>,
+    Path(index): Path<usize>,
+    body: Bytes,
+) -> ApiResult {
+    let mut params = params(&body)?;
+    params["workspace"] = index.into();
+    execute(&state, "ws_activate", params).await
+}
+
+/// `POST /{method}` with the JSON-RPC params as body, for every operation.
+async fn call(
+    State(state): State<HttpState>,
+    Path(method): Path<String>,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    if !Operation::METHODS.contains(&method.as_str()) {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let params = params(&body)?;
+    Ok(execute(&state, &method, params).await.into_response())
+}
+
+/// Initial subscription of an `/events` WebSocket, e.g.
+/// `?events=focus_changed,app_added&app_id=firefox`.
+#[derive(Deserialize)]
+struct EventsQuery {
+    events: Option<String>,
+    app_id: Option<String>,
+    title: Option<String>,
+}
+
+impl EventsQuery {
+    fn subscribe_params(self) -> Result<SubscribeParams, ApiError> {
+        let events = self
+            .events
+            .iter()
+            .flat_map(|events| events.split(','))
+            .filter(|kind| !kind.is_empty())
+            .map(|kind| kind.parse::<EventKind>().map_err(ApiError))
+            .collect::<Result<_, _>>()?;
+        Ok(SubscribeParams {
+            events,
+            app_id: self.app_id,
+            title: self.title,
+        })
+    }
+}
+
+async fn events(
+    State(state): State<HttpState>,
+    Query(query): Query<EventsQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let params = query.subscribe_params()?;
+    Ok(ws.on_upgrade(move |socket| stream_events(socket, state, params)))
+}
+
+/// Sends `state_change` notifications matching `params` over the WebSocket.
+/// Text messages from the client are handled as JSON-RPC requests, like on
+/// the unix socket, so it can change its subscriptions or call methods.
+async fn stream_events(socket: WebSocket, state: HttpState, params: SubscribeParams) {
+    let subscriptions = Arc::new(Mutex::new(Subscriptions::default()));
+    subscriptions.lock().unwrap().subscribe(params);
+    let io = Arc::new(server::io_handler(
+        state.handler.clone(),
+        subscriptions.clone(),
+    ));
+
+    let (mut sender, mut receiver) = socket.split();
+    let (out_tx, mut out_rx) = channel::<String>(server::OUTBOUND_QUEUE);
+    let notifier = tokio::task::spawn(server::publish_notifications(
+        state.events_tx.subscribe(),
+        subscriptions,
+        state.debounce,
+        out_tx.clone(),
+    ));
+    let writer = tokio::task::spawn(async move {
+        while let Some(text) = out_rx.recv().await {
+            if sender.send(Message::Text(text.into())).await.is_err() {
+                break;
+            }
+        }
+    })
+    .abort_handle();
+
+    while let Some(Ok(message)) = receiver.next().await {
+        if writer.is_finished() {
+            break;
+        }
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let io = io.clone();
+        let out_tx = out_tx.clone();
+        let writer = writer.clone();
+        tokio::task::spawn(async move {
+            if let Some(response) = io.handle_request(text.as_str()).await {
+                server::queue_response(&out_tx, response, &writer);
+            }
+        });
+    }
+    notifier.abort();
+    writer.abort();
+}
+
+/// Whether `host`, a `Host` header with an optional port, names the local
+/// machine.
+fn is_loopback_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.eq_ignore_ascii_case("localhost")
+        || name
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Checks the token and answers CORS preflights. Without a token, requests
+/// from web pages (those with an `Origin` header) are refused, so that
+/// arbitrary sites cannot drive the desktop through the browser, and so are
+/// requests for other hosts than the local machine, which a site gets by
+/// pointing its own name at 127.0.0.1 (DNS rebinding).
+async fn authorize(State(state): State<HttpState>, request: Request, next: Next) -> Response {
+    let origin = request.headers().get(header::ORIGIN).cloned();
+    let Some(token) = &state.token else {
+        let host = request
+            .headers()
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| {
+                request
+                    .uri()
+                    .authority()
+                    .map(|authority| authority.as_str())
+            });
+        if origin.is_some() || !host.is_some_and(is_loopback_host) {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        return next.run(request).await;
+    };
+
+    if request.method() == Method::OPTIONS {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        allow_origin(&mut response, origin);
+        return response;
+    }
+
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let query = request
+        .uri()
+        .query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("token="));
+    if bearer != Some(token) && query != Some(token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let mut response = next.run(request).await;
+    allow_origin(&mut response, origin);
+    response
+}
+
+fn allow_origin(response: &mut Response, origin: Option<HeaderValue>) {
+    let Some(origin) = origin else {
+        return;
+    };
+    let headers = response.headers_mut();
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_HEADERS,
+        HeaderValue::from_static("authorization, content-type"),
+    );
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        HeaderValue::from_static("GET, POST"),
+    );
+}
+
+pub async fn serve(
+    args: &HttpArgs,
+    handler: Arc<ServerHandler>,
+    events_tx: broadcast::Sender<Arc<StateChange>>,
+    debounce: Duration,
+) -> Result<(), Box<dyn StdError>> {
+    if !args.addr.ip().is_loopback() {
+        if !args.allow_remote {
+            return Err(format!(
+                "Refusing to listen on {}: not a loopback address, see --allow-remote",
+                args.addr
+            )
+            .into());
+        }
+        if args.token.is_none() {
+            return Err("--allow-remote requires --token".into());
+        }
+    }
+
+    let state = HttpState {
+        handler,
+        events_tx,
+        debounce,
+        token: args.token.as_deref().map(Arc::from),
+    };
+    let router = Router::new()
+        .route("/info", get(|State(s)| async move { info(&s, None).await }))
+        .route(
+            "/windows",
+            get(|State(s)| async move { info(&s, Some("apps")).await }),
+        )
+        .route(
+            "/workspaces",
+            get(|State(s)| async move { info(&s, Some("workspace_groups")).await }),
+        )
+        .route(
+            "/outputs",
+            get(|State(s)| async move { info(&s, Some("outputs")).await }),
+        )
+        .route(
+            "/history",
+            get(|State(s)| async move { execute(&s, "history", json!({})).await }),
+        )
+        .route("/windows/{id}/{action}", post(window_action))
+        .route("/workspaces/{index}/activate", post(workspace_activate))
+        .route("/events", get(events))
+        .route("/{method}", post(call))
+        .layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(args.addr).await?;
+    tracing::info!("Listening on http://{}", args.addr);
+    axum::serve(listener, router).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback_hosts() {
+        for host in [
+            "localhost",
+            "LOCALHOST:7878",
+            "127.0.0.1:7878",
+            "127.1.2.3",
+            "[::1]:7878",
+        ] {
+            assert!(is_loopback_host(host), "{host}");
+        }
+        for host in [
+            "example.com",
+            "evil.com:7878",
+            "192.168.1.2:7878",
+            "[::2]",
+            "",
+        ] {
+            assert!(!is_loopback_host(host), "{host}");
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! Toplevel and workspace management for the COSMIC desktop.
+//!
+//! [`CosmicClient`] connects to the compositor and exposes the operations of
+//! the `cos-cli` binary as typed methods. The `Json*` types are the model
+//! returned by them and serialized by `cos-cli info --json` and the JSON-RPC
+//! server.
+
+use cosmic_protocols::toplevel_info::v1::client::zcosmic_toplevel_handle_v1;
+use cosmic_protocols::toplevel_management::v1::client::zcosmic_toplevel_manager_v1;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use wayland_client::{
+    Proxy,
+    backend::ObjectId,
+    protocol::{wl_output, wl_seat},
+};
+use wayland_protocols::ext::workspace::v1::client::{
+    ext_workspace_group_handle_v1, ext_workspace_handle_v1, ext_workspace_manager_v1,
+};
+
+pub mod client;
+pub mod config;
+pub mod confirm;
+pub mod cycle;
+mod dispatch;
+pub mod error;
+pub mod events;
+pub mod history;
+pub mod operation;
+pub mod profile;
+pub mod rules;
+pub mod schema;
+pub mod selector;
+pub mod session;
+
+pub use client::CosmicClient;
+pub use error::ClientError;
+pub use operation::{Operation, Output};
+
+/// Socket of `cos-cli serve --socket` that commands are forwarded to:
+/// `$COS_CLI_SOCKET`, or `$XDG_RUNTIME_DIR/cos-cli.sock`.
+pub fn default_socket_path() -> Option<PathBuf> {
+    std::env::var_os("COS_CLI_SOCKET")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join("cos-cli.sock"))
+        })
+}
+
+#[derive(Clone)]
+struct NamedHandle<T> {
+    name: Option<String>,
+    handle: T,
+}
+
+impl<T: Clone> NamedHandle<T> {
+    // fn new(handle: T) -> Self {
+    //     Self { name: None, handle }
+    // }
+    fn named(name: &str, handle: T) -> Self {
+        Self {
+            name: name.to_string().into(),
+            handle,
+        }
+    }
+}
+
+#[derive(Default)]
+struct HandleMap {
+    // workspace_group_handle:
+    //     HashMap<ObjectId, NamedHandle<ext_workspace_group_handle_v1::ExtWorkspaceGroupHandleV1>>,
+    workspace_handle: HashMap<ObjectId, NamedHandle<ext_workspace_handle_v1::ExtWorkspaceHandleV1>>,
+    output: HashMap<ObjectId, NamedHandle<wl_output::WlOutput>>,
+    /// Make and model of the outputs
+    output_model: HashMap<ObjectId, (String, String)>,
+    seat: HashMap<ObjectId, NamedHandle<wl_seat::WlSeat>>,
+}
+
+struct WorkspaceGroup {
+    object_id: ObjectId,
+    handle: ext_workspace_group_handle_v1::ExtWorkspaceGroupHandleV1,
+    workspaces: Vec<ObjectId>,
+    outputs: Vec<ObjectId>,
+}
+
+impl HandleMap {
+    fn workspace_names(&self, group: &WorkspaceGroup) -> impl Iterator<Item = &str> {
+        group
+            .workspaces
+            .iter()
+            .filter_map(|w| self.workspace_handle.get(w))
+            .filter_map(|nh| nh.name.as_deref())
+    }
+}
+
+#[derive(Default)]
+struct AppState {
+    handle_map: HandleMap,
+    cosmic_toplevel_manager: Option<zcosmic_toplevel_manager_v1::ZcosmicToplevelManagerV1>,
+    workspace_manager: Option<ext_workspace_manager_v1::ExtWorkspaceManagerV1>,
+    available_interfaces: HashMap<String, Vec<(u32, u32)>>,
+    /// Set once `dispatch::bind` ran, later globals are bound as they appear
+    globals_bound: bool,
+    /// Registry names of the bound outputs, to tell which one was unplugged
+    output_globals: HashMap<u32, ObjectId>,
+    /// Versions of the protocols bound in `dispatch::bind`
+    bound_protocols: BTreeMap<&'static str, u32>,
+    workspace_groups: Vec<WorkspaceGroup>,
+    outputs: Vec<ObjectId>,
+    seats: Vec<ObjectId>,
+    apps: Vec<App>,
+    active_workspaces: Vec<ObjectId>,
+    /// Most recently focused first
+    focus_history: Vec<history::Focus>,
+    events: Option<tokio::sync::broadcast::Sender<Arc<events::StateChange>>>,
+    pending_events: Vec<events::Event>,
+}
+
+impl AppState {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn entities_count(&self) -> usize {
+        self.outputs.len() + self.seats.len() + self.apps.len() + self.workspace_groups.len()
+    }
+    fn enable_notify(&mut self) -> tokio::sync::broadcast::Sender<Arc<events::StateChange>> {
+        let (tx, _) = tokio::sync::broadcast::channel(256);
+        self.events = tx.clone().into();
+        tx
+    }
+    fn notify(&mut self, event: events::Event) {
+        if self.events.is_some() {
+            events::Event::coalesce(&mut self.pending_events, event);
+        }
+    }
+    /// Notifies about a change of the app at `index`. Changes that are part of
+    /// the initial burst of a new toplevel are reported by `AppAdded` instead.
+    fn notify_app(&mut self, index: usize, event: impl FnOnce(JsonApp) -> events::Event) {
+        if self.events.is_some()
+            && let Some(app) = self.apps.get(index)
+            && app.ready
+        {
+            let event = event(JsonApp::new(self, index, app));
+            self.notify(event);
+        }
+    }
+    /// Publishes the changes collected since the last call as one batch.
+    /// Called once per wayland dispatch cycle so that clients never see
+    /// half-applied state.
+    fn flush_events(&mut self) {
+        if self.pending_events.is_empty() {
+            return;
+        }
+        let events = std::mem::take(&mut self.pending_events);
+        if let Some(tx) = &self.events
+            && tx.receiver_count() > 0
+        {
+            let _ = tx.send(Arc::new(events::StateChange {
+                events,
+                state: JsonInfo::from(&*self),
+            }));
+        }
+    }
+    fn json_workspace_ref(&self, workspace: &ObjectId) -> Option<JsonWorkspaceRef> {
+        Some(JsonWorkspaceRef {
+            index: self
+                .workspace_groups
+                .iter()
+                .filter_map(|wg| wg.workspaces.iter().position(|i| i == workspace))
+                .next()?,
+            group_index: self
+                .workspace_groups
+                .iter()
+                .position(|wg| wg.workspaces.contains(workspace))?,
+            workspace: self
+                .handle_map
+                .workspace_handle
+                .get(workspace)
+                .and_then(|nh| nh.name.as_deref())
+                .unwrap_or("not found")
+                .to_string(),
+        })
+    }
+    fn json_output(&self, index: usize) -> Option<JsonOutput> {
+        let id = self.outputs.get(index)?;
+        let handle = self.handle_map.output.get(id)?;
+        let (make, model) = self
+            .handle_map
+            .output_model
+            .get(id)
+            .cloned()
+            .unwrap_or_default();
+        Some(JsonOutput {
+            index,
+            name: handle.name.clone().unwrap_or_default(),
+            make,
+            model,
+        })
+    }
+    fn json_app(&self, index: usize) -> Option<JsonApp> {
+        self.apps
+            .get(index)
+            .map(|app| JsonApp::new(self, index, app))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct App {
+    handle: zcosmic_toplevel_handle_v1::ZcosmicToplevelHandleV1,
+    title: Option<String>,
+    app_id: Option<String>,
+    outputs: Vec<ObjectId>,
+    workspaces: Vec<ObjectId>,
+    state: Vec<State>,
+    /// Set once the compositor sent the initial `done` for this toplevel.
+    ready: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JsonApp {
+    pub index: usize,
+    pub id: u32,
+    pub app_id: String,
+    pub title: String,
+    pub state: Vec<State>,
+    pub outputs: Vec<JsonOutputRef>,
+    pub workspaces: Vec<JsonWorkspaceRef>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JsonOutputRef {
+    pub index: usize,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JsonWorkspaceRef {
+    pub group_index: usize,
+    pub index: usize,
+    pub workspace: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JsonWorkspace {
+    pub index: usize,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JsonWorkspaceGroup {
+    pub index: usize,
+    pub workspaces: Vec<JsonWorkspace>,
+    pub outputs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JsonOutput {
+    pub index: usize,
+    pub name: String,
+    #[serde(default)]
+    pub make: String,
+    #[serde(default)]
+    pub model: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JsonSeat {
+    pub index: usize,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JsonInfo {
+    pub apps: Vec<JsonApp>,
+    pub workspace_groups: Vec<JsonWorkspaceGroup>,
+    pub outputs: Vec<JsonOutput>,
+    pub seats: Vec<JsonSeat>,
+}
+
+impl JsonApp {
+    fn new(state: &AppState, index: usize, app: &App) -> Self {
+        let outputs = app
+            .outputs
+            .iter()
+            .filter_map(|o| {
+                state.handle_map.output.get(o).and_then(|h| {
+                    JsonOutputRef {
+                        index: state.outputs.iter().position(|oid| oid == o)?,
+                        name: h.name.clone()?,
+                    }
+                    .into()
+                })
+            })
+            .collect();
+        let workspaces = app
+            .workspaces
+            .iter()
+            .filter_map(|w| state.json_workspace_ref(w))
+            .collect();
+        JsonApp {
+            index,
+            id: app.handle.id().protocol_id(),
+            app_id: app.app_id.clone().unwrap_or_default(),
+            title: app.title.clone().unwrap_or_default(),
+            state: app.state.clone(),
+            outputs,
+            workspaces,
+        }
+    }
+}
+
+/// A window with only an id and app_id, which tests fill in further with
+/// struct update syntax.
+#[cfg(test)]
+pub(crate) fn test_app(id: u32, app_id: &str) -> JsonApp {
+    JsonApp {
+        index: id as usize,
+        id,
+        app_id: app_id.into(),
+        title: String::new(),
+        state: Vec::new(),
+        outputs: Vec::new(),
+        workspaces: Vec::new(),
+    }
+}
+
+impl From<&AppState> for JsonInfo {
+    fn from(state: &AppState) -> Self {
+        Self {
+            apps: state
+                .apps
+                .iter()
+                .enumerate()
+                .map(|(i, app)| JsonApp::new(state, i, app))
+                .collect(),
+            workspace_groups: state
+                .workspace_groups
+                .iter()
+                .enumerate()
+                .map(|(i, group)| JsonWorkspaceGroup {
+                    index: i,
+                    workspaces: state
+                        .handle_map
+                        .workspace_names(group)
+                        .map(ToOwned::to_owned)
+                        .enumerate()
+                        .map(|(index, name)| JsonWorkspace { index, name })
+                        .collect(),
+                    outputs: group
+                        .outputs
+                        .iter()
+                        .filter_map(|oid| {
+                            state
+                                .handle_map
+                                .output
+                                .get(oid)
+                                .and_then(|h| h.name.clone())
+                        })
+                        .collect(),
+                })
+                .collect(),
+            outputs: (0..state.outputs.len())
+                .filter_map(|index| state.json_output(index))
+                .collect(),
+            seats: state
+                .seats
+                .iter()
+                .enumerate()
+                .filter_map(|(i, sid)| {
+                    state.handle_map.seat.get(sid).map(|h| JsonSeat {
+                        index: i,
+                        name: h.name.clone().unwrap_or_default(),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Maximized = 0,
+    Minimized = 1,
+    Activated = 2,
+    Fullscreen = 3,
+    Sticky = 4,
+}
+
+impl TryFrom<u32> for State {
+    type Error = ();
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(State::Maximized),
+            1 => Ok(State::Minimized),
+            2 => Ok(State::Activated),
+            3 => Ok(State::Fullscreen),
+            4 => Ok(State::Sticky),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            State::Maximized => "maximized",
+            State::Minimized => "minimized",
+            State::Fullscreen => "fullscreen",
+            State::Activated => "activated",
+            State::Sticky => "sticky",
+        })
+    }
+}
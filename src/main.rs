@@ -1,23 +1,27 @@
-use cosmic_protocols::toplevel_info::v1::client::zcosmic_toplevel_handle_v1;
-use cosmic_protocols::toplevel_management::v1::client::zcosmic_toplevel_manager_v1;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use cos_cli::client::{
+    ActivateParams, ActivateWsParams, CloseParams, InfoParams, MoveParams, StateParams,
+};
+use cos_cli::config::{Config, Problem, Severity};
+use cos_cli::confirm::{self, Outcome, WindowOutcome};
+use cos_cli::history::{HistoryParams, HistoryResult};
+use cos_cli::operation::{BatchItem, BatchParams, BatchStatus, OnError};
+use cos_cli::{CosmicClient, JsonInfo, Operation};
+use serde_json::Value;
 use std::error::Error;
 use std::fmt;
-
-use wayland_client::{
-    Connection, EventQueue,
-    backend::ObjectId,
-    protocol::{wl_output, wl_seat},
-};
-use wayland_protocols::ext::workspace::v1::client::{
-    ext_workspace_handle_v1,
-    // ext_workspace_group_handle_v1,
-    ext_workspace_manager_v1,
-};
-
-mod dispatch;
+use std::path::PathBuf;
+
+mod daemon;
+mod dbus;
+mod hooks;
+mod hotplug;
+mod http;
+mod mcp;
+mod remote;
+mod restore;
 mod server;
+mod wait;
+mod watch;
 
 fn init_tracing() {
     tracing_subscriber::fmt()
@@ -26,6 +30,8 @@ fn init_tracing() {
                 .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
         )
         .with_ansi(std::env::var_os("NO_COLOR").is_none())
+        // stdout carries the JSON-RPC messages of 'serve' and 'mcp'
+        .with_writer(std::io::stderr)
         .init();
 }
 
@@ -38,21 +44,75 @@ Commands:
   info                          List active apps, workspaces, and outputs
   move                          Move an application to a specific workspace
   activate                      Activate an application on a specific seat
+  focus                         Same as 'activate', e.g. 'focus --previous'
+  history                       List the open windows, most recently focused first
   ws-activate                   Activate a workspace
   state                         Set state of an application
-  serve                         Start a stdio JSON-RPC server
+  serve                         Start a JSON-RPC server on stdio, a unix socket, HTTP or D-Bus
+  mcp                           Start a Model Context Protocol server on stdio
   close                         Close an application
+  apply [FILE]                  Run a list of operations from a JSON file or stdin at once
+  schema [NAME]                 Print the JSON Schema of 'info' results and notifications
+  rules                         Apply window rules from a TOML file to new windows
+  config check                  Validate the config file against the open windows and outputs
+  watch                         Print changes of windows, workspaces and outputs, and run
+                                commands on them
+  wait-for                      Wait until a window exists, closes, has the focus or a state
+  session save FILE             Save the workspace, output and state of every window
+  session restore FILE          Move the windows saved in FILE back into place
+
+Global options:
+  --via-socket <PATH>           Send the command to a 'serve --socket' server instead of
+                                connecting to the compositor
+  --direct                      Always connect to the compositor, even if a server is
+                                listening on the default socket
+  --json                        Print the result in JSON format
+
+Aliases, workspace labels, defaults and output profiles are read from $COS_CLI_CONFIG
+(default: $XDG_CONFIG_HOME/cos-cli/config.toml). 'serve' and 'rules' apply the profile of
+the connected outputs when outputs are plugged in or removed. They read the file when they
+start and need a restart to pick up changes.
+
+When a server is listening on $COS_CLI_SOCKET (default: $XDG_RUNTIME_DIR/cos-cli.sock)
+commands are sent to it, otherwise cos-cli connects to the compositor directly.
 
 Options for 'move':
-  -a, --app-id <ID>             The Application ID (partial match, case-insensitive)
+  -a, --app-id <ID>             The Application ID (partial match, case-insensitive) or alias
   -i, --index <INDEX>           The Application index from 'info' command
-  -w, --workspace <INDEX>       The index of the target workspace
+  -w, --workspace <INDEX>       The index, label or name of the target workspace
   -g, --workspace-group <INDEX> The workspace group index from 'info' command (optional)
   -o, --output-index <INDEX>    The output index from 'info' command (optional)
   --wait <SECONDS>              Wait for the app to appear (optional, only for --app-id)
+  --confirm                     Wait until the windows are on the workspace
+
+Options for 'activate' and 'focus':
+  -i, --index <INDEX>           The Application index from 'info' command
+  --previous                    The most recently focused window that does not have the focus
+  --mru <N>                     The window at place N of 'history' (0: most recently focused)
+  --cycle                       The window after the focused one, among all windows or:
+    -a, --app-id <ID>           those of this Application ID or alias
+    --same-app                  those with the Application ID of the focused window
+    -w, --workspace <INDEX>     those on this workspace, 'current' for the one of the
+                                focused window
+    --order <ORDER>             id (default, the order windows were opened in) or mru
+                                (kept from the start of the cycle)
+    --reverse                   Step backwards
+    --minimized                 Include minimized windows
+  -s, --seat <INDEX>            The seat index from 'info' command (optional)
+
+  The focus history of --previous, --mru and --order mru is kept by a running
+  'serve --socket': without one cos-cli only knows the window that has the focus now.
+
+Options for 'history':
+  -n, --limit <N>               At most this many windows
+
+Options for 'ws-activate':
+  -w, --workspace <INDEX>       The index, label or name of the workspace
+  -g, --workspace-group <INDEX> The workspace group index from 'info' command (optional)
+  --confirm                     Wait until the workspace is active
 
 Options for 'state':
-  -a, --app-id <ID>             The Application ID (partial match, case-insensitive)
+  -a, --app-id <ID>             The Application ID (partial match, case-insensitive) or alias
   -i, --index <INDEX>           The Application index from 'info' command
   --maximize
   --unmaximize
@@ -62,15 +122,89 @@ Options for 'state':
   --unfullscreen
   --sticky
   --unsticky
+  --confirm                     Wait until the windows have the requested state
 
 Options for 'close':
-  -a, --app-id <ID>             The Application ID (partial match, case-insensitive)
+  -a, --app-id <ID>             The Application ID (partial match, case-insensitive) or alias
   -i, --index <INDEX>           The Application index from 'info' command
+  --wait <SECONDS>              Wait for the app to appear (optional, only for --app-id)
+  --confirm                     Wait until the windows are closed
+
+Options for 'apply':
+  --continue                    Run the remaining operations after one failed. By default
+                                every operation is validated first and nothing is sent if
+                                one fails
+
+  FILE contains a JSON array of {\"method\": ..., \"params\": ...} objects, or the params of
+  the 'batch' JSON-RPC method. Reads stdin when FILE is '-' or missing. Operations cannot
+  use --wait or --confirm.
+
+Confirmation:
+  --timeout <SECONDS>           How long --confirm waits (default: 5)
+
+  With --confirm the outcome is printed per window and cos-cli exits with
+  2 if a window disappeared before reflecting the change and 3 on timeout.
 
 Options for 'info':
-  --json                        Output in JSON format
   --discover-wg-output          Try to find info relation about workspace group and output
 
+Arguments for 'schema':
+  NAME                          info, state_change or openrpc (default: info and
+                                state_change)
+
+Options for 'rules':
+  -c, --config <FILE>           The rules file, reloaded when it changes
+  --existing                    Also apply the rules to the windows already open and the
+                                profile of the connected outputs
+
+Options for 'watch':
+  --filter <SELECTOR>           Only print events about matching windows, e.g. firefox or
+                                app_id=firefox,title=github,workspace=2
+  --events <EVENTS>             Only print these event types, e.g. focus,title,app_added
+  --until <EVENT[:SELECTOR]>    Exit after the first such event, e.g. app_removed:firefox
+  -q, --quiet                   Print nothing, only run the commands
+  --on <EVENT=COMMAND>          Run COMMAND with 'sh -c' on every EVENT, e.g.
+                                app_added='notify-send \"$COS_APP_ID\"' (repeatable, adds
+                                to [hooks.on] of the config)
+  --timeout <SECONDS>           Kill a command after this (default: 10)
+  --interval <MS>               Least time between two runs for the same event (default: 0)
+
+  Prints one line per event, or one JSON object per line with --json. The commands see
+  every event regardless of --filter and --events. The commands of one event run one
+  after another with the event in COS_* environment variables. Events arriving while 32
+  of the same type wait are dropped. 'serve' and 'rules' run [hooks] of the config too.
+
+  Selector keys: app_id, title (partial), id, workspace, workspace_group, output, state.
+  A value without a key is an app_id or alias.
+
+Options for 'wait-for':
+  --match <SELECTOR>            The windows to wait for, as in 'watch --filter'
+  --state <STATE>               exists (default), absent, closed, focused or a window state
+                                such as fullscreen or maximized
+  --timeout <SECONDS>           Give up after this (default: wait forever)
+
+  Prints the window that satisfied the condition as JSON, nothing for absent. 'closed'
+  waits until the windows matching at the start are all closed. cos-cli exits with 3 on
+  timeout.
+
+Options for 'session restore':
+  --launch                      Start the apps of windows that are not open ('command' of
+                                the saved window, default: gtk-launch APP_ID)
+  --wait <SECONDS>              How long to wait for launched apps (default: 10)
+
+  Saved windows are matched to open ones by app_id and the most similar title. cos-cli
+  exits with 1 if a window could not be restored.
+
+Options for 'serve':
+  --debounce <MS>               Merge state changes published within this interval (default: 0)
+  --socket <PATH>               Listen on a unix socket instead of stdio
+  --http <ADDR:PORT>            Serve an HTTP and WebSocket API instead of stdio, e.g.
+                                127.0.0.1:7878 (can be combined with --socket)
+  --token <TOKEN>               Require this token on HTTP requests (default: $COS_CLI_TOKEN)
+  --allow-remote                Allow --http on non-loopback addresses, requires --token
+  --dbus                        Export org.cosmic_cli.WindowManager on the session bus
+                                instead of stdio (can be combined with --socket and --http)
+
 Examples:
   cos-cli info
   cos-cli info --json
@@ -82,11 +216,37 @@ Examples:
   cos-cli move -a terminal -w 2 -g 1
   cos-cli activate -i 0 -s 0
   cos-cli activate -i 0
+  cos-cli focus --previous
+  cos-cli focus --cycle --same-app
+  cos-cli focus --cycle --workspace current --reverse
+  cos-cli history --limit 5
   cos-cli state -i 0 --maximize
   cos-cli state --app-id firefox --sticky --fullscreen
   cos-cli close -i 0
   cos-cli close --app-id firefox
   cos-cli close -a terminal
+  cos-cli close -a terminal --confirm --timeout 10
+  cos-cli state -a firefox --maximize --confirm
+  cos-cli serve --debounce 100
+  cos-cli serve --socket $XDG_RUNTIME_DIR/cos-cli.sock
+  cos-cli serve --http 127.0.0.1:7878 --token secret
+  cos-cli serve --dbus
+  cos-cli --via-socket /tmp/cos-cli.sock move -a firefox -w 1
+  cos-cli --direct info
+  cos-cli schema state_change
+  cos-cli apply layout.json
+  cos-cli apply --continue - < layout.json
+  cos-cli move -a browser -w mail
+  cos-cli config check
+  cos-cli rules --config ~/.config/cos-cli/rules.toml
+  cos-cli watch --events focus,title --filter firefox
+  cos-cli watch --json --until app_removed:app_id=terminal
+  cos-cli watch --on focus_changed='echo \"$COS_PREVIOUS_APP_ID -> $COS_APP_ID\"'
+  cos-cli wait-for --match firefox --state focused --timeout 10
+  cos-cli wait-for --match app_id=terminal --state closed
+  cos-cli wait-for --match workspace=3 --state absent
+  cos-cli session save work.json
+  cos-cli session restore work.json --launch
 ";
 
 struct CliError(String);
@@ -127,475 +287,290 @@ impl From<&str> for CliError {
     }
 }
 
-#[derive(Clone)]
-struct NamedHandle<T> {
-    name: Option<String>,
-    handle: T,
-}
-
-impl<T: Clone> NamedHandle<T> {
-    // fn new(handle: T) -> Self {
-    //     Self { name: None, handle }
-    // }
-    fn named(name: &str, handle: T) -> Self {
-        Self {
-            name: name.to_string().into(),
-            handle,
-        }
-    }
-}
-
-#[derive(Default)]
-struct HandleMap {
-    // workspace_group_handle:
-    //     HashMap<ObjectId, NamedHandle<ext_workspace_group_handle_v1::ExtWorkspaceGroupHandleV1>>,
-    workspace_handle: HashMap<ObjectId, NamedHandle<ext_workspace_handle_v1::ExtWorkspaceHandleV1>>,
-    output: HashMap<ObjectId, NamedHandle<wl_output::WlOutput>>,
-    seat: HashMap<ObjectId, NamedHandle<wl_seat::WlSeat>>,
-}
-
-struct WorkspaceGroup {
-    object_id: ObjectId,
-    workspaces: Vec<ObjectId>,
-    outputs: Vec<ObjectId>,
+#[derive(Debug)]
+struct ServeArgs {
+    debounce: std::time::Duration,
+    socket: Option<PathBuf>,
+    http: Option<http::HttpArgs>,
+    /// Export `org.cosmic_cli.WindowManager` on the session bus
+    dbus: bool,
 }
 
-impl HandleMap {
-    fn workspace_names(&self, group: &WorkspaceGroup) -> impl Iterator<Item = &str> {
-        group
+enum Command {
+    /// Runs an operation against the compositor or a running server
+    Run(Operation),
+    Serve(ServeArgs),
+    /// Serves the Model Context Protocol on stdio
+    Mcp,
+    /// Prints a JSON Schema, all of them when no name is given
+    Schema(Option<String>),
+    /// Applies window rules from a file to new toplevels
+    Rules {
+        config: PathBuf,
+        existing: bool,
+    },
+    /// Validates the config file against the live state
+    ConfigCheck,
+    /// Prints state changes and runs hooks on them
+    Watch(Box<watch::WatchArgs>),
+    /// Blocks until a window condition holds
+    WaitFor(wait::WaitArgs),
+    /// Writes the placement of every window to a file
+    SessionSave(PathBuf),
+    /// Moves the windows saved in a file back into place
+    SessionRestore {
+        file: PathBuf,
+        launch: bool,
+        wait: std::time::Duration,
+    },
+}
+
+fn print_info(info: &JsonInfo) {
+    println!("Apps:");
+    for app in &info.apps {
+        let states = app
+            .state
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let output_names = app
+            .outputs
+            .iter()
+            .map(|o| o.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let workspaces = app
             .workspaces
             .iter()
-            .filter_map(|w| self.workspace_handle.get(w))
-            .filter_map(|nh| nh.name.as_deref())
-    }
-}
-
-#[derive(Default)]
-struct AppState {
-    handle_map: HandleMap,
-    cosmic_toplevel_manager: Option<zcosmic_toplevel_manager_v1::ZcosmicToplevelManagerV1>,
-    workspace_manager: Option<ext_workspace_manager_v1::ExtWorkspaceManagerV1>,
-    available_interfaces: HashMap<String, Vec<(u32, u32)>>,
-    workspace_groups: Vec<WorkspaceGroup>,
-    outputs: Vec<ObjectId>,
-    seats: Vec<ObjectId>,
-    apps: Vec<App>,
-    watch: Option<tokio::sync::watch::Sender<Option<JsonInfo>>>,
-}
-
-impl AppState {
-    fn new() -> Self {
-        Self::default()
+            .map(|w| format!("{}.\"{}\"", w.group_index, w.index))
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!(
+            "\t[{}] {} (title: {}, state: [{}],  workspaces: [{}], outputs: [{}])",
+            app.index, app.app_id, app.title, states, workspaces, output_names,
+        );
     }
-
-    fn entities_count(&self) -> usize {
-        self.outputs.len() + self.seats.len() + self.apps.len() + self.workspace_groups.len()
-    }
-    fn enable_notify(&mut self) -> tokio::sync::watch::Receiver<Option<JsonInfo>> {
-        let (tx, rx) = tokio::sync::watch::channel(None);
-        self.watch = tx.into();
-        rx
-    }
-    fn notify(&self) {
-        if let Some(tx) = &self.watch {
-            let _ = tx.send(JsonInfo::from(self).into());
+    println!("Workspaces:");
+    for group in &info.workspace_groups {
+        let i = group.index;
+        let output_names = group.outputs.join(", ");
+        if output_names.is_empty() {
+            println!("\t[{i}] Group (outputs: undiscovered)");
+        } else {
+            println!("\t[{i}] Group (outputs: {output_names})");
         }
-    }
-}
-
-#[derive(Debug, Clone)]
-struct App {
-    handle: zcosmic_toplevel_handle_v1::ZcosmicToplevelHandleV1,
-    title: Option<String>,
-    app_id: Option<String>,
-    outputs: Vec<ObjectId>,
-    workspaces: Vec<ObjectId>,
-    state: Vec<State>,
-}
-
-#[derive(Serialize)]
-struct JsonApp {
-    index: usize,
-    app_id: String,
-    title: String,
-    state: Vec<State>,
-    outputs: Vec<JsonOutputRef>,
-    workspaces: Vec<JsonWorkspaceRef>,
-}
-
-#[derive(Serialize)]
-struct JsonOutputRef {
-    index: usize,
-    name: String,
-}
-
-#[derive(Serialize)]
-struct JsonWorkspaceRef {
-    group_index: usize,
-    index: usize,
-    workspace: String,
-}
-
-#[derive(Serialize)]
-struct JsonWorkspace {
-    index: usize,
-    name: String,
-}
-
-#[derive(Serialize)]
-struct JsonWorkspaceGroup {
-    index: usize,
-    workspaces: Vec<JsonWorkspace>,
-    outputs: Vec<String>,
-}
-
-#[derive(Serialize)]
-struct JsonOutput {
-    index: usize,
-    name: String,
-}
-
-#[derive(Serialize)]
-struct JsonSeat {
-    index: usize,
-    name: String,
-}
-
-#[derive(Serialize)]
-struct JsonInfo {
-    apps: Vec<JsonApp>,
-    workspace_groups: Vec<JsonWorkspaceGroup>,
-    outputs: Vec<JsonOutput>,
-    seats: Vec<JsonSeat>,
-}
-
-impl From<&AppState> for JsonInfo {
-    fn from(state: &AppState) -> Self {
-        Self {
-            apps: state
-                .apps
-                .iter()
-                .enumerate()
-                .map(|(i, app)| {
-                    let outputs = app
-                        .outputs
-                        .iter()
-                        .filter_map(|o| {
-                            state.handle_map.output.get(o).and_then(|h| {
-                                JsonOutputRef {
-                                    index: state.outputs.iter().position(|oid| oid == o)?,
-                                    name: h.name.clone()?,
-                                }
-                                .into()
-                            })
-                        })
-                        .collect();
-                    let workspaces = app
-                        .workspaces
-                        .iter()
-                        .filter_map(|w| {
-                            Some(JsonWorkspaceRef {
-                                index: state
-                                    .workspace_groups
-                                    .iter()
-                                    .filter_map(|wg| wg.workspaces.iter().position(|i| i == w))
-                                    .next()?,
-                                group_index: state
-                                    .workspace_groups
-                                    .iter()
-                                    .position(|wg| wg.workspaces.contains(w))?,
-                                workspace: state
-                                    .handle_map
-                                    .workspace_handle
-                                    .get(w)
-                                    .and_then(|nh| nh.name.as_deref())
-                                    .unwrap_or("not found")
-                                    .to_string(),
-                            })
-                        })
-                        .collect();
-                    JsonApp {
-                        index: i,
-                        app_id: app.app_id.clone().unwrap_or_default(),
-                        title: app.title.clone().unwrap_or_default(),
-                        state: app.state.clone(),
-                        outputs,
-                        workspaces,
-                    }
-                })
-                .collect(),
-            workspace_groups: state
-                .workspace_groups
-                .iter()
-                .enumerate()
-                .map(|(i, group)| JsonWorkspaceGroup {
-                    index: i,
-                    workspaces: state
-                        .handle_map
-                        .workspace_names(group)
-                        .map(ToOwned::to_owned)
-                        .enumerate()
-                        .map(|(index, name)| JsonWorkspace { index, name })
-                        .collect(),
-                    outputs: group
-                        .outputs
-                        .iter()
-                        .filter_map(|oid| {
-                            state
-                                .handle_map
-                                .output
-                                .get(oid)
-                                .and_then(|h| h.name.clone())
-                        })
-                        .collect(),
-                })
-                .collect(),
-            outputs: state
-                .outputs
-                .iter()
-                .enumerate()
-                .filter_map(|(i, oid)| {
-                    state.handle_map.output.get(oid).map(|h| JsonOutput {
-                        index: i,
-                        name: h.name.clone().unwrap_or_default(),
-                    })
-                })
-                .collect(),
-            seats: state
-                .seats
-                .iter()
-                .enumerate()
-                .filter_map(|(i, sid)| {
-                    state.handle_map.seat.get(sid).map(|h| JsonSeat {
-                        index: i,
-                        name: h.name.clone().unwrap_or_default(),
-                    })
-                })
-                .collect(),
+        for workspace in &group.workspaces {
+            println!("\t\tWorkspace: {}", workspace.index);
         }
     }
-}
-
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum State {
-    Maximized = 0,
-    Minimized = 1,
-    Activated = 2,
-    Fullscreen = 3,
-}
-
-impl TryFrom<u32> for State {
-    type Error = ();
-
-    fn try_from(value: u32) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(State::Maximized),
-            1 => Ok(State::Minimized),
-            2 => Ok(State::Activated),
-            3 => Ok(State::Fullscreen),
-            _ => Err(()),
+    println!("Outputs:");
+    for output in &info.outputs {
+        let model = format!("{} {}", output.make, output.model);
+        if model.trim().is_empty() {
+            println!("\t[{}] Output: {}", output.index, output.name);
+        } else {
+            println!(
+                "\t[{}] Output: {} ({})",
+                output.index,
+                output.name,
+                model.trim()
+            );
         }
     }
-}
 
-impl fmt::Display for State {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(match self {
-            State::Maximized => "maximized",
-            State::Minimized => "minimized",
-            State::Fullscreen => "fullscreen",
-            State::Activated => "activated",
-        })
+    println!("Seats:");
+    for seat in &info.seats {
+        println!("\t[{}] Seat: {}", seat.index, seat.name);
     }
 }
 
-#[derive(Debug)]
-struct MoveArgs {
-    app_id: Option<String>,
-    app_index: Option<usize>,
-    workspace_name: String,
-    workspace_group_index: Option<usize>,
-    output_index: Option<usize>,
-    wait: Option<u64>,
-}
-
-#[derive(Debug)]
-struct ActivateArgs {
-    app_index: usize,
-    seat_index: Option<usize>,
+fn print_history(history: &HistoryResult) {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    for (place, focus) in history.windows.iter().enumerate() {
+        let seconds = now.saturating_sub(focus.focused_at) / 1000;
+        let ago = match seconds {
+            0..60 => format!("{seconds}s"),
+            60..3600 => format!("{}m", seconds / 60),
+            _ => format!("{}h", seconds / 3600),
+        };
+        let focused = match focus.unfocused_at {
+            None => "has the focus".to_string(),
+            Some(_) => format!("focused {ago} ago"),
+        };
+        println!(
+            "{place}: [{}] {}: {} ({focused})",
+            focus.app.index, focus.app.app_id, focus.app.title
+        );
+    }
 }
 
-#[derive(Debug)]
-struct ActivateWsArgs {
-    workspace_index: usize,
-    workspace_group_index: Option<usize>,
-}
+/// Exit code when a window disappeared before reflecting the change.
+const EXIT_FAILED: i32 = 2;
+/// Exit code when the change was not reflected within the timeout.
+const EXIT_TIMEOUT: i32 = 3;
 
-#[derive(Debug)]
-struct StateArgs {
-    app_id: Option<String>,
-    app_index: Option<usize>,
-    wait: Option<u64>,
-    maximize: bool,
-    unmaximize: bool,
-    minimize: bool,
-    unminimize: bool,
-    fullscreen: bool,
-    unfullscreen: bool,
-    sticky: bool,
-    unsticky: bool,
+/// Prints the per-window outcomes of `--confirm` and returns the overall one.
+fn report(outcomes: &[WindowOutcome]) -> Outcome {
+    for outcome in outcomes {
+        println!("{} [{}]: {}", outcome.app_id, outcome.id, outcome.outcome);
+    }
+    confirm::overall(outcomes.iter().map(|o| &o.outcome))
 }
 
-#[derive(Debug)]
-struct CloseArgs {
-    app_id: Option<String>,
-    app_index: Option<usize>,
+/// Exits with the code of `outcome` unless it is confirmed.
+fn exit_unless_confirmed(outcome: Outcome) {
+    let code = match outcome {
+        Outcome::Confirmed => return,
+        Outcome::Failed => EXIT_FAILED,
+        Outcome::Timeout => EXIT_TIMEOUT,
+    };
+    std::process::exit(code);
 }
 
-trait AppFinderArgs {
-    fn app_index(&self) -> Option<usize>;
-    fn app_id(&self) -> Option<&String>;
-    fn wait(&self) -> Option<u64>;
+/// Reads the operations of `cos-cli apply` from `path`, or stdin.
+fn read_batch(path: Option<PathBuf>) -> Result<BatchParams, Box<dyn Error>> {
+    let input = match path {
+        Some(path) if path.as_os_str() != "-" => std::fs::read_to_string(path)?,
+        _ => std::io::read_to_string(std::io::stdin())?,
+    };
+    let params = match serde_json::from_str(&input)? {
+        Value::Array(operations) => serde_json::json!({ "operations": operations }),
+        params => params,
+    };
+    Ok(serde_json::from_value(params)?)
 }
 
-impl AppFinderArgs for MoveArgs {
-    fn app_index(&self) -> Option<usize> {
-        self.app_index
-    }
-
-    fn app_id(&self) -> Option<&String> {
-        self.app_id.as_ref()
+/// Prints one line per batch operation and fails if any of them failed.
+fn report_batch(items: &[BatchItem], json: bool) -> Result<(), Box<dyn Error>> {
+    let mut failed = 0;
+    for (index, item) in items.iter().enumerate() {
+        let status = match (&item.status, &item.error) {
+            (BatchStatus::Error, Some(error)) => {
+                failed += 1;
+                error.message.as_str()
+            }
+            (BatchStatus::Error, None) => {
+                failed += 1;
+                "error"
+            }
+            (BatchStatus::Ok, _) => "ok",
+            (BatchStatus::Skipped, _) => "skipped",
+        };
+        if !json {
+            println!("{index}: {}: {status}", item.method);
+        }
     }
-
-    fn wait(&self) -> Option<u64> {
-        self.wait
+    if failed > 0 {
+        return Err(CliError::new(format!(
+            "{failed} of {} operations failed",
+            items.len()
+        )));
     }
+    Ok(())
 }
 
-impl AppFinderArgs for StateArgs {
-    fn app_index(&self) -> Option<usize> {
-        self.app_index
-    }
-
-    fn app_id(&self) -> Option<&String> {
-        self.app_id.as_ref()
-    }
-
-    fn wait(&self) -> Option<u64> {
-        self.wait
-    }
+/// Connects to the compositor with the config file applied.
+fn connect() -> Result<CosmicClient, Box<dyn Error>> {
+    let config = Config::load()?;
+    Ok(CosmicClient::connect()?.with_config(config))
 }
 
-impl AppFinderArgs for CloseArgs {
-    fn app_index(&self) -> Option<usize> {
-        self.app_index
+/// `cos-cli config check`: fails if the config has errors.
+fn config_check(json: bool) -> Result<(), Box<dyn Error>> {
+    let path = Config::path().unwrap_or_default();
+    let problems = match Config::load() {
+        Ok(config) => config.check(&CosmicClient::connect()?.info()),
+        // A file that does not parse is reported like the other problems
+        Err(e) => vec![Problem {
+            severity: Severity::Error,
+            message: e.to_string(),
+        }],
+    };
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({ "path": path, "problems": problems })
+        );
+    } else {
+        for problem in &problems {
+            let severity = match problem.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            println!("{severity}: {}", problem.message);
+        }
     }
-
-    fn app_id(&self) -> Option<&String> {
-        self.app_id.as_ref()
+    let errors = problems
+        .iter()
+        .filter(|p| p.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(CliError::new(format!(
+            "{errors} errors in {}",
+            path.display()
+        )));
     }
-
-    fn wait(&self) -> Option<u64> {
-        None
+    if !json {
+        println!("{} is valid", path.display());
     }
+    Ok(())
 }
 
-#[derive(Debug)]
-struct InfoArgs {
-    json: bool,
-    discover_wg_output: bool,
-}
-
-enum Command {
-    Info(InfoArgs),
-    Move(MoveArgs),
-    Activate(ActivateArgs),
-    ActivateWs(ActivateWsArgs),
-    State(StateArgs),
-    Serve,
-    Close(CloseArgs),
-}
-
-fn find_apps<T: AppFinderArgs>(
-    state: &mut AppState,
-    event_queue: &mut EventQueue<AppState>,
-    args: &T,
-) -> Result<Vec<App>, Box<dyn Error>> {
-    if let Some(app_index) = args.app_index() {
-        if let Some(app) = state.apps.get(app_index) {
-            Ok(vec![app.clone()])
-        } else {
-            Err(CliError::new(format!("App index not found: {}", app_index)))
-        }
-    } else if let Some(app_id) = args.app_id() {
-        let sleep = std::time::Duration::from_millis(500);
-        let wait_dur = args.wait().map(std::time::Duration::from_secs);
-        let now = std::time::Instant::now();
-        let mut apps;
-        loop {
-            apps = state
-                .apps
-                .iter()
-                .filter(|app| {
-                    app.app_id
-                        .as_ref()
-                        .map(|v| v.to_lowercase().contains(&app_id.to_lowercase()))
-                        .unwrap_or_default()
-                })
-                .cloned()
-                .collect::<Vec<_>>();
-
-            if !apps.is_empty() {
-                break;
-            }
-
-            if let Some(wait) = wait_dur {
-                if now.elapsed() > wait {
-                    break;
+/// Prints the result of `method` the same way whether it ran directly or
+/// on a server, and exits with the confirmation outcome if there is one.
+fn print_result(method: &str, json: bool, result: &Value) -> Result<(), Box<dyn Error>> {
+    if json {
+        println!("{result}");
+    } else {
+        match method {
+            "info" => print_info(&serde_json::from_value(result.clone())?),
+            "move" | "state" | "close" => {
+                for window in result["windows"].as_array().into_iter().flatten() {
+                    let app_id = window["app_id"].as_str().unwrap_or_default();
+                    match method {
+                        "move" => println!(
+                            "Move {app_id} to {}",
+                            result["target"]["workspace_name"]
+                                .as_str()
+                                .unwrap_or_default()
+                        ),
+                        "state" => println!("Set state of {app_id}"),
+                        _ => println!("Close {app_id}"),
+                    }
                 }
-                std::thread::sleep(sleep);
-                event_queue.roundtrip(state)?;
-            } else {
-                break;
             }
+            "activate" => println!("Activated window {}", result["index"]),
+            "ws_activate" => println!("Activated workspace {}", result["workspace"]),
+            "history" => print_history(&serde_json::from_value(result.clone())?),
+            _ => {}
         }
-        if apps.is_empty() {
-            return Err(CliError::new(format!("App id not found: {}", app_id)));
-        }
-        Ok(apps)
-    } else {
-        unreachable!(); // Already handled by arg parsing
     }
-}
 
-fn discover() -> Result<(Connection, EventQueue<AppState>, AppState), Box<dyn std::error::Error>> {
-    let conn = Connection::connect_to_env()?;
-    let mut event_queue = conn.new_event_queue();
-    let qh = event_queue.handle();
-
-    let mut state = AppState::new();
-    let registry = conn.display().get_registry(&qh, ());
-
-    event_queue.roundtrip(&mut state)?;
-    dispatch::bind(&registry, &qh, &mut state);
-    event_queue.roundtrip(&mut state)?;
-    tracing::debug!("Discovered {}", state.entities_count(),);
-
-    let mut entities_count = 0;
-    for i in 0..10 {
-        event_queue.roundtrip(&mut state)?;
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        let new_entities_count = state.entities_count();
-        tracing::debug!("Step {i}. Discovered {new_entities_count} (previous: {entities_count})");
+    if method == "batch" {
+        let items = serde_json::from_value::<Vec<BatchItem>>(result["results"].clone())?;
+        return report_batch(&items, json);
+    }
 
-        if new_entities_count == entities_count {
-            break;
+    if let Some(confirmations) = result.get("confirmations") {
+        let outcomes = serde_json::from_value::<Vec<WindowOutcome>>(confirmations.clone())?;
+        let outcome = if json {
+            confirm::overall(outcomes.iter().map(|o| &o.outcome))
+        } else {
+            report(&outcomes)
+        };
+        exit_unless_confirmed(outcome);
+    } else if let Some(confirmation) = result.get("confirmation") {
+        let outcome = serde_json::from_value::<Outcome>(confirmation.clone())?;
+        if !json {
+            println!("Workspace {}: {outcome}", result["workspace"]);
         }
-        entities_count = new_entities_count;
+        exit_unless_confirmed(outcome);
     }
-
-    Ok((conn, event_queue, state))
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -607,118 +582,156 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
+    let via_socket: Option<PathBuf> = pargs.opt_value_from_str("--via-socket")?;
+    let direct = pargs.contains("--direct");
+    let json = pargs.contains("--json");
     let subcommand = pargs.subcommand()?;
 
     let command = match subcommand.as_deref() {
-        Some("info") => Command::Info(InfoArgs {
-            json: pargs.contains("--json"),
+        Some("info") => Command::Run(Operation::Info(InfoParams {
             discover_wg_output: pargs.contains("--discover-wg-output"),
-        }),
-        Some("move") => {
-            let app_id: Option<String> = pargs.opt_value_from_str(["-a", "--app-id"])?;
-            let app_index: Option<usize> = pargs.opt_value_from_str(["-i", "--index"])?;
-
-            if app_id.is_none() && app_index.is_none() {
-                return Err(CliError::new(
-                    "Either --app-id or --index must be provided for 'move' command.".into(),
-                ));
-            }
-            if app_id.is_some() && app_index.is_some() {
-                return Err(CliError::new(
-                    "Only one of --app-id or --index can be provided for 'move' command.".into(),
-                ));
-            }
-
-            Command::Move(MoveArgs {
-                app_id,
-                app_index,
-                workspace_name: pargs.value_from_str(["-w", "--workspace"])?,
-                workspace_group_index: pargs.opt_value_from_str(["-g", "--workspace-group"])?,
-                output_index: pargs.opt_value_from_str(["-o", "--output-index"])?,
-                wait: pargs.opt_value_from_fn("--wait", |v| v.parse())?,
-            })
-        }
-        Some("activate") => Command::Activate(ActivateArgs {
-            app_index: pargs.value_from_str(["-i", "--index"])?,
-            seat_index: pargs.opt_value_from_str(["-s", "--seat"])?,
-        }),
-        Some("ws-activate") => {
-            let workspace_index: usize = pargs.value_from_str(["-w", "--workspace"])?;
-            let workspace_group_index: Option<usize> =
-                pargs.opt_value_from_str(["-g", "--workspace-group"])?;
-            Command::ActivateWs(ActivateWsArgs {
-                workspace_index,
-                workspace_group_index,
+        })),
+        Some("move") => Command::Run(Operation::Move(MoveParams {
+            app_id: pargs.opt_value_from_str(["-a", "--app-id"])?,
+            index: pargs.opt_value_from_str(["-i", "--index"])?,
+            id: None,
+            workspace: pargs.value_from_str(["-w", "--workspace"])?,
+            workspace_group: pargs.opt_value_from_str(["-g", "--workspace-group"])?,
+            output_index: pargs.opt_value_from_str(["-o", "--output-index"])?,
+            wait: pargs.opt_value_from_str("--wait")?,
+            confirm: pargs.contains("--confirm"),
+            timeout: pargs.opt_value_from_str("--timeout")?,
+        })),
+        Some("activate" | "focus") => Command::Run(Operation::Activate(ActivateParams {
+            index: pargs.opt_value_from_str(["-i", "--index"])?,
+            id: None,
+            previous: pargs.contains("--previous"),
+            mru: pargs.opt_value_from_str("--mru")?,
+            cycle: pargs.contains("--cycle"),
+            app_id: pargs.opt_value_from_str(["-a", "--app-id"])?,
+            same_app: pargs.contains("--same-app"),
+            workspace: pargs.opt_value_from_str(["-w", "--workspace"])?,
+            order: pargs.opt_value_from_str("--order")?.unwrap_or_default(),
+            reverse: pargs.contains("--reverse"),
+            minimized: pargs.contains("--minimized"),
+            seat: pargs.opt_value_from_str(["-s", "--seat"])?,
+        })),
+        Some("history") => Command::Run(Operation::History(HistoryParams {
+            limit: pargs.opt_value_from_str(["-n", "--limit"])?,
+        })),
+        Some("ws-activate") => Command::Run(Operation::WsActivate(ActivateWsParams {
+            workspace: pargs.value_from_str(["-w", "--workspace"])?,
+            workspace_group: pargs.opt_value_from_str(["-g", "--workspace-group"])?,
+            confirm: pargs.contains("--confirm"),
+            timeout: pargs.opt_value_from_str("--timeout")?,
+        })),
+        Some("state") => Command::Run(Operation::State(StateParams {
+            app_id: pargs.opt_value_from_str(["-a", "--app-id"])?,
+            index: pargs.opt_value_from_str(["-i", "--index"])?,
+            id: None,
+            wait: pargs.opt_value_from_str("--wait")?,
+            maximize: pargs.contains("--maximize"),
+            unmaximize: pargs.contains("--unmaximize"),
+            minimize: pargs.contains("--minimize"),
+            unminimize: pargs.contains("--unminimize"),
+            fullscreen: pargs.contains("--fullscreen"),
+            unfullscreen: pargs.contains("--unfullscreen"),
+            sticky: pargs.contains("--sticky"),
+            unsticky: pargs.contains("--unsticky"),
+            confirm: pargs.contains("--confirm"),
+            timeout: pargs.opt_value_from_str("--timeout")?,
+        })),
+        Some("serve") => {
+            let debounce = pargs.opt_value_from_str("--debounce")?.unwrap_or(0);
+            let socket = pargs.opt_value_from_str("--socket")?;
+            let token = pargs
+                .opt_value_from_str("--token")?
+                .or_else(|| std::env::var("COS_CLI_TOKEN").ok());
+            let allow_remote = pargs.contains("--allow-remote");
+            let http = pargs
+                .opt_value_from_str("--http")?
+                .map(|addr| http::HttpArgs {
+                    addr,
+                    token,
+                    allow_remote,
+                });
+            Command::Serve(ServeArgs {
+                debounce: std::time::Duration::from_millis(debounce),
+                socket,
+                http,
+                dbus: pargs.contains("--dbus"),
             })
         }
-        Some("state") => {
-            let app_id: Option<String> = pargs.opt_value_from_str(["-a", "--app-id"])?;
-            let app_index: Option<usize> = pargs.opt_value_from_str(["-i", "--index"])?;
-
-            if app_id.is_none() && app_index.is_none() {
-                return Err(CliError::new(
-                    "Either --app-id or --index must be provided for 'state' command.".into(),
-                ));
-            }
-            if app_id.is_some() && app_index.is_some() {
-                return Err(CliError::new(
-                    "Only one of --app-id or --index can be provided for 'state' command.".into(),
-                ));
-            }
-
-            let args = StateArgs {
-                app_id,
-                app_index,
-                wait: pargs.opt_value_from_fn("--wait", |v| v.parse())?,
-                maximize: pargs.contains("--maximize"),
-                unmaximize: pargs.contains("--unmaximize"),
-                minimize: pargs.contains("--minimize"),
-                unminimize: pargs.contains("--unminimize"),
-                fullscreen: pargs.contains("--fullscreen"),
-                unfullscreen: pargs.contains("--unfullscreen"),
-                sticky: pargs.contains("--sticky"),
-                unsticky: pargs.contains("--unsticky"),
-            };
-            let num_actions = [
-                args.maximize,
-                args.unmaximize,
-                args.minimize,
-                args.unminimize,
-                args.fullscreen,
-                args.unfullscreen,
-                args.sticky,
-                args.unsticky,
-            ]
-            .iter()
-            .filter(|&&x| x)
-            .count();
-
-            if num_actions == 0 {
-                return Err(CliError::new(
-                    "No action specified for 'state' command.".into(),
-                ));
+        Some("close") => Command::Run(Operation::Close(CloseParams {
+            app_id: pargs.opt_value_from_str(["-a", "--app-id"])?,
+            index: pargs.opt_value_from_str(["-i", "--index"])?,
+            id: None,
+            wait: pargs.opt_value_from_str("--wait")?,
+            confirm: pargs.contains("--confirm"),
+            timeout: pargs.opt_value_from_str("--timeout")?,
+        })),
+        Some("mcp") => Command::Mcp,
+        Some("apply") => {
+            let keep_going = pargs.contains("--continue");
+            let mut params = read_batch(pargs.opt_free_from_str()?)?;
+            if keep_going {
+                params.on_error = OnError::Continue;
             }
-
-            Command::State(args)
+            Command::Run(Operation::Batch(params))
         }
-        Some("serve") => Command::Serve,
-        Some("close") => {
-            let app_id: Option<String> = pargs.opt_value_from_str(["-a", "--app-id"])?;
-            let app_index: Option<usize> = pargs.opt_value_from_str(["-i", "--index"])?;
-
-            if app_id.is_none() && app_index.is_none() {
-                return Err(CliError::new(
-                    "Either --app-id or --index must be provided for 'close' command.".into(),
-                ));
+        Some("schema") => Command::Schema(pargs.opt_free_from_str()?),
+        Some("config") => match pargs.opt_free_from_str::<String>()?.as_deref() {
+            Some("check") => Command::ConfigCheck,
+            other => {
+                return Err(CliError::new(format!(
+                    "Unknown config command: {}. Expected: check",
+                    other.unwrap_or_default()
+                )));
+            }
+        },
+        Some("session") => match pargs.opt_free_from_str::<String>()?.as_deref() {
+            Some("save") => Command::SessionSave(pargs.free_from_str()?),
+            Some("restore") => {
+                let launch = pargs.contains("--launch");
+                let wait = pargs.opt_value_from_str("--wait")?.unwrap_or(10);
+                Command::SessionRestore {
+                    file: pargs.free_from_str()?,
+                    launch,
+                    wait: std::time::Duration::from_secs(wait),
+                }
             }
-            if app_id.is_some() && app_index.is_some() {
-                return Err(CliError::new(
-                    "Only one of --app-id or --index can be provided for 'close' command.".into(),
-                ));
+            other => {
+                return Err(CliError::new(format!(
+                    "Unknown session command: {}. Expected: save, restore",
+                    other.unwrap_or_default()
+                )));
             }
-
-            Command::Close(CloseArgs { app_id, app_index })
-        }
+        },
+        Some("watch") => Command::Watch(Box::new(watch::WatchArgs {
+            json,
+            quiet: pargs.contains(["-q", "--quiet"]),
+            filter: pargs.opt_value_from_str("--filter")?,
+            events: pargs
+                .opt_value_from_fn("--events", watch::parse_kinds)?
+                .unwrap_or_default(),
+            until: pargs.opt_value_from_fn("--until", watch::parse_until)?,
+            hooks: pargs.values_from_fn("--on", watch::parse_hook)?,
+            timeout: pargs.opt_value_from_str("--timeout")?,
+            interval: pargs.opt_value_from_str("--interval")?,
+        })),
+        Some("wait-for") => Command::WaitFor(wait::WaitArgs {
+            selector: pargs.value_from_str("--match")?,
+            condition: pargs
+                .opt_value_from_str("--state")?
+                .unwrap_or(wait::Condition::Exists),
+            timeout: pargs
+                .opt_value_from_str("--timeout")?
+                .map(std::time::Duration::from_secs),
+        }),
+        Some("rules") => Command::Rules {
+            config: pargs.value_from_str(["-c", "--config"])?,
+            existing: pargs.contains("--existing"),
+        },
         Some("help") | None => {
             println!("{HELP}");
             return Ok(());
@@ -731,487 +744,97 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    let (conn, mut event_queue, mut state) = discover()?;
-
-    match command {
-        Command::Info(args) => {
-            for app in &state.apps {
-                state
-                    .workspace_groups
-                    .iter_mut()
-                    .filter(|wg| wg.workspaces.iter().any(|w| app.workspaces.contains(w)))
-                    .for_each(|wg| {
-                        for output in &app.outputs {
-                            if !wg.outputs.contains(output) {
-                                wg.outputs.push(output.clone());
-                            }
-                        }
-                    });
-            }
-
-            if args.discover_wg_output {
-                let Some(manager) = state.cosmic_toplevel_manager.clone() else {
-                    return Err(CliError::new(
-                        "Compositor does not support workspace management protocol.".into(),
-                    ));
-                };
-                let Some(last_app) = state.apps.last().cloned() else {
-                    return Err(CliError::new(
-                        "No apps found to discover workspace group outputs".into(),
-                    ));
-                };
-
-                let Some(initial_app_output) = last_app.outputs.first() else {
-                    return Err(CliError::new("App without output".into()));
-                };
-
-                let Some(initial_app_workspace) = last_app.workspaces.first() else {
-                    return Err(CliError::new("App without workspace".into()));
-                };
-
-                let Some(initial_app_group) = state
-                    .workspace_groups
-                    .iter()
-                    .find(|wg| wg.workspaces.contains(initial_app_workspace))
-                    .map(|wg| wg.object_id.to_owned())
-                else {
-                    return Err(CliError::new("Workspace without group.".into()));
-                };
-
-                let move_plan = state
-                    .workspace_groups
-                    .iter()
-                    .enumerate()
-                    .filter_map(|(index, g)| {
-                        if g.object_id != initial_app_group {
-                            Some((index, g.object_id.clone(), g.workspaces.first()?.clone()))
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<_>>();
-
-                // start move app from one workspace group to another
-                let mut was_moved = false;
-                for (group_index, group_id, workspace_id) in move_plan {
-                    let Some(nh) = state
-                        .handle_map
-                        .workspace_handle
-                        .get(&workspace_id)
-                        .cloned()
-                    else {
-                        continue;
-                    };
-
-                    for (output_index, output_id) in state.outputs.clone().into_iter().enumerate() {
-                        if initial_app_output == &output_id {
-                            continue;
-                        }
-
-                        let Some(output) = state.handle_map.output.get(&output_id).cloned() else {
-                            continue;
-                        };
-
-                        tracing::debug!(
-                            "Moving app to workspace group {group_index} workspace '{}' at output: {output_index}",
-                            nh.name.as_deref().unwrap_or_default()
-                        );
-                        manager.move_to_ext_workspace(&last_app.handle, &nh.handle, &output.handle);
-                        conn.flush()?;
-
-                        std::thread::sleep(std::time::Duration::from_millis(300));
-                        event_queue.roundtrip(&mut state)?;
-
-                        // check new state
-                        let Some(new_app_state) =
-                            state.apps.iter().find(|a| a.handle == last_app.handle)
-                        else {
-                            continue;
-                        };
-
-                        // app was change output - associate it with workspace group
-                        if new_app_state.outputs.contains(&output_id) {
-                            was_moved = true;
-                            tracing::debug!(
-                                "App moved. Add output {output_index} to the workspace group"
-                            );
-                            let Some(wg) = state
-                                .workspace_groups
-                                .iter_mut()
-                                .find(|g| g.object_id == group_id)
-                            else {
-                                continue;
-                            };
-                            if !wg.outputs.contains(&output_id) {
-                                wg.outputs.push(output_id.clone())
-                            }
-                        } else {
-                            tracing::debug!("App not changes output");
-                        }
-                    }
-                }
-
-                if was_moved
-                    && let Some(workspace) = state
-                        .handle_map
-                        .workspace_handle
-                        .get(initial_app_workspace)
-                        .map(|nh| &nh.handle)
-                    && let Some(output) = state
-                        .handle_map
-                        .output
-                        .get(initial_app_output)
-                        .map(|nh| &nh.handle)
-                {
-                    tracing::debug!("Discover output by app moving done");
-                    // move app back
-                    manager.move_to_ext_workspace(&last_app.handle, workspace, output);
-                    conn.flush()?;
-
-                    std::thread::sleep(std::time::Duration::from_millis(300));
-                    event_queue.roundtrip(&mut state)?;
-                } else {
-                    tracing::debug!("Failed to discover output by app moving");
-                }
-            }
-
-            if args.json {
-                let json_info = JsonInfo::from(&state);
-                println!("{}", serde_json::to_string(&json_info).unwrap());
-            } else {
-                println!("Apps:");
-                for (i, app) in state.apps.iter().enumerate() {
-                    let states = app
-                        .state
-                        .iter()
-                        .map(|s| s.to_string())
-                        .collect::<Vec<_>>()
-                        .join(", ");
-                    let output_names = app
-                        .outputs
-                        .iter()
-                        .filter_map(|o| {
-                            state
-                                .handle_map
-                                .output
-                                .get(o)
-                                .and_then(|h| h.name.as_deref())
-                        })
-                        .collect::<Vec<_>>()
-                        .join(", ");
-                    let workspaces = app
-                        .workspaces
-                        .iter()
-                        .filter_map(|w| {
-                            state
-                                .workspace_groups
-                                .iter()
-                                .enumerate()
-                                .find_map(|(gi, g)| {
-                                    g.workspaces
-                                        .iter()
-                                        .position(|hw| hw == w)
-                                        .map(|wi| format!("{}.\"{}\"", gi, wi))
-                                })
-                        })
-                        .collect::<Vec<_>>()
-                        .join(", ");
-                    println!(
-                        "\t[{}] {} (title: {}, state: [{}],  workspaces: [{}], outputs: [{}])",
-                        i,
-                        app.app_id.as_deref().unwrap_or_default(),
-                        app.title.as_deref().unwrap_or_default(),
-                        states,
-                        workspaces,
-                        output_names,
-                    );
-                }
-                println!("Workspaces:");
-                for (i, group) in state.workspace_groups.iter().enumerate() {
-                    let output_names = group
-                        .outputs
-                        .iter()
-                        .filter_map(|oid| {
-                            state
-                                .handle_map
-                                .output
-                                .get(oid)
-                                .and_then(|h| h.name.as_deref())
-                        })
-                        .collect::<Vec<_>>()
-                        .join(", ");
-                    if output_names.is_empty() {
-                        println!("\t[{i}] Group (outputs: undiscovered)");
-                    } else {
-                        println!("\t[{i}] Group (outputs: {output_names})");
-                    }
-                    for (j, _) in group.workspaces.iter().enumerate() {
-                        println!("\t\tWorkspace: {j}");
-                    }
-                }
-                println!("Outputs:");
-                for (i, oid) in state.outputs.iter().enumerate() {
-                    let name = state
-                        .handle_map
-                        .output
-                        .get(oid)
-                        .and_then(|h| h.name.as_deref())
-                        .unwrap_or("unknown");
-                    println!("\t[{i}] Output: {name}");
-                }
-
-                println!("Seats:");
-                for (i, sid) in state.seats.iter().enumerate() {
-                    let name = state
-                        .handle_map
-                        .seat
-                        .get(sid)
-                        .and_then(|h| h.name.as_deref())
-                        .unwrap_or("unknown");
-                    println!("\t[{i}] Seat: {name}");
-                }
-            }
-        }
-        Command::Move(args) => {
-            let apps_to_move = find_apps(&mut state, &mut event_queue, &args)?;
-
-            let Some(manager) = &state.cosmic_toplevel_manager else {
-                return Err(CliError::new(
-                    "Compositor does not support workspace management protocol.".into(),
-                ));
-            };
-            println!("Connected to cosmic toplevel manager!");
-
-            let workspace_index = if let Some(group_index) = args.workspace_group_index {
-                let group = state.workspace_groups.get(group_index).ok_or_else(|| {
-                    CliError::new(format!("Workspace group not found: {}", group_index))
-                })?;
-                let idx = args.workspace_name.parse::<usize>().map_err(|_| {
-                    CliError::new(format!("Invalid workspace index: {}", args.workspace_name))
-                })?;
-                if idx >= group.workspaces.len() {
-                    return Err(CliError::new(format!(
-                        "Workspace index {} out of range (group has {} workspaces)",
-                        idx,
-                        group.workspaces.len()
-                    )));
-                }
-                (group_index, idx)
-            } else {
-                let mut found = None;
-                for (gi, group) in state.workspace_groups.iter().enumerate() {
-                    for (wi, _) in group.workspaces.iter().enumerate() {
-                        if format!("{}", wi) == args.workspace_name {
-                            found = Some((gi, wi));
-                            break;
-                        }
-                    }
-                    if found.is_some() {
-                        break;
-                    }
-                }
-                found.ok_or_else(|| {
-                    CliError::new(format!("Workspace not found: {}", args.workspace_name))
-                })?
-            };
-
-            let (group_index, idx) = workspace_index;
-            let workspace_handle = &state.workspace_groups[group_index].workspaces[idx];
-            let Some(workspace) = state.handle_map.workspace_handle.get(workspace_handle) else {
-                return Err(CliError::new(
-                    "Workspace handle not found in handle map".into(),
-                ));
-            };
-
-            let output = if let Some(index) = args.output_index {
-                let oid = state
-                    .outputs
-                    .get(index)
-                    .ok_or_else(|| CliError::new(format!("Output index not found: {}", index)))?;
-                state
-                    .handle_map
-                    .output
-                    .get(oid)
-                    .map(|h| h.handle.clone())
-                    .ok_or_else(|| CliError::new("Output handle not found in handle map".into()))?
-            } else {
-                if state.outputs.is_empty() {
-                    return Err(CliError::new("No outputs found.".to_string()));
-                }
-                let oid = &state.outputs[0];
-                state
-                    .handle_map
-                    .output
-                    .get(oid)
-                    .map(|h| h.handle.clone())
-                    .ok_or_else(|| CliError::new("Output handle not found in handle map".into()))?
-            };
-
-            for app in apps_to_move {
-                println!(
-                    "Move {} to {}",
-                    app.app_id.as_deref().unwrap_or_default(),
-                    args.workspace_name,
-                );
-                manager.move_to_ext_workspace(&app.handle, &workspace.handle, &output);
-            }
-
-            conn.flush()?;
-        }
-        Command::Activate(args) => {
-            let Some(manager) = &state.cosmic_toplevel_manager else {
-                return Err(CliError::new(
-                    "Compositor does not support toplevel management protocol.".into(),
-                ));
-            };
-            let Some(app) = state.apps.get(args.app_index) else {
-                return Err(CliError::new(format!(
-                    "App index not found: {}",
-                    args.app_index
-                )));
-            };
-            let seat = if let Some(seat_index) = args.seat_index {
-                let sid = state.seats.get(seat_index).ok_or_else(|| {
-                    CliError::new(format!("Seat index not found: {}", seat_index))
-                })?;
-                state
-                    .handle_map
-                    .seat
-                    .get(sid)
-                    .map(|h| &h.handle)
-                    .ok_or_else(|| CliError::new("Seat handle not found in handle map".into()))?
-            } else {
-                let sid = state
-                    .seats
-                    .first()
-                    .ok_or_else(|| CliError::new("No seats found.".to_string()))?;
-                state
-                    .handle_map
-                    .seat
-                    .get(sid)
-                    .map(|h| &h.handle)
-                    .ok_or_else(|| CliError::new("Seat handle not found in handle map".into()))?
-            };
-            manager.activate(&app.handle, seat);
-            conn.flush()?;
-        }
-        Command::ActivateWs(args) => {
-            let Some(manager) = &state.workspace_manager else {
-                return Err(CliError::new(
-                    "Compositor does not support workspace management protocol.".into(),
-                ));
-            };
-            let workspace_index = if let Some(group_index) = args.workspace_group_index {
-                let group = state.workspace_groups.get(group_index).ok_or_else(|| {
-                    CliError::new(format!("Workspace group not found: {}", group_index))
-                })?;
-                if args.workspace_index >= group.workspaces.len() {
-                    return Err(CliError::new(format!(
-                        "Workspace index {} out of range (group has {} workspaces)",
-                        args.workspace_index,
-                        group.workspaces.len()
-                    )));
-                }
-                (group_index, args.workspace_index)
-            } else {
-                let mut found = None;
-                for (gi, group) in state.workspace_groups.iter().enumerate() {
-                    for (wi, _) in group.workspaces.iter().enumerate() {
-                        if wi == args.workspace_index {
-                            found = Some((gi, wi));
-                            break;
-                        }
-                    }
-                    if found.is_some() {
-                        break;
-                    }
-                }
-                found.ok_or_else(|| {
-                    CliError::new(format!("Workspace not found: {}", args.workspace_index))
-                })?
-            };
-
-            let (group_index, idx) = workspace_index;
-            let workspace_handle = &state.workspace_groups[group_index].workspaces[idx];
-            let Some(ws) = state.handle_map.workspace_handle.get(workspace_handle) else {
-                return Err(CliError::new(
-                    "Workspace handle not found in handle map".into(),
-                ));
-            };
-
-            ws.handle.activate();
-            manager.commit();
-            conn.flush()?;
-            println!("Activated workspace {}", args.workspace_index);
-        }
-        Command::State(args) => {
-            let apps_to_modify = find_apps(&mut state, &mut event_queue, &args)?;
-
-            let Some(manager) = &state.cosmic_toplevel_manager else {
-                return Err(CliError::new(
-                    "Compositor does not support toplevel management protocol.".into(),
-                ));
-            };
-
-            for app in apps_to_modify {
-                if args.maximize {
-                    manager.set_maximized(&app.handle);
-                }
-                if args.unmaximize {
-                    manager.unset_maximized(&app.handle);
-                }
-                if args.minimize {
-                    manager.set_minimized(&app.handle);
-                }
-                if args.unminimize {
-                    manager.unset_minimized(&app.handle);
-                }
-                if args.fullscreen {
-                    manager.set_fullscreen(&app.handle, None);
-                }
-                if args.unfullscreen {
-                    manager.unset_fullscreen(&app.handle);
-                }
-                if args.sticky {
-                    manager.set_sticky(&app.handle);
-                }
-                if args.unsticky {
-                    manager.unset_sticky(&app.handle);
-                }
-            }
-
-            conn.flush()?;
-        }
-        Command::Serve => {
+    let operation = match command {
+        Command::Run(operation) => operation,
+        Command::Serve(args) => {
+            let client = connect()?;
             let rt = tokio::runtime::Builder::new_current_thread()
                 .enable_all()
                 .build()?;
             rt.block_on(async move {
-                server::run(conn, event_queue, state).await?;
+                server::run(client, args).await?;
                 Ok::<_, Box<dyn std::error::Error>>(())
             })?;
+            return Ok(());
         }
-        Command::Close(args) => {
-            let apps_to_close = find_apps(&mut state, &mut event_queue, &args)?;
-
-            let Some(manager) = &state.cosmic_toplevel_manager else {
-                return Err(CliError::new(
-                    "Compositor does not support toplevel management protocol.".into(),
-                ));
-            };
-
-            for app in &apps_to_close {
-                tracing::debug!(
-                    "Sending close request for: {}",
-                    app.app_id.as_deref().unwrap_or("unknown")
-                );
-                manager.close(&app.handle);
+        Command::Mcp => {
+            let client = connect()?;
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            rt.block_on(mcp::run(client))?;
+            return Ok(());
+        }
+        Command::Rules { config, existing } => {
+            daemon::run(config, existing)?;
+            return Ok(());
+        }
+        Command::ConfigCheck => return config_check(json),
+        Command::Watch(args) => return watch::run(*args),
+        Command::WaitFor(args) => {
+            if !wait::run(args)? {
+                std::process::exit(EXIT_TIMEOUT);
             }
-
-            conn.flush()?;
-            event_queue.roundtrip(&mut state)?;
+            return Ok(());
+        }
+        Command::SessionSave(file) => return restore::save(&file, json),
+        Command::SessionRestore { file, launch, wait } => {
+            return restore::restore(&file, launch, wait, json);
+        }
+        Command::Schema(name) => {
+            let schema = match name.as_deref() {
+                Some(name) => cos_cli::schema::schema(name).ok_or_else(|| {
+                    CliError::new(format!(
+                        "Unknown schema: {name}. Expected one of: {}",
+                        cos_cli::schema::SCHEMAS.join(", ")
+                    ))
+                })?,
+                None => serde_json::json!({
+                    "info": cos_cli::schema::schema("info"),
+                    "state_change": cos_cli::schema::schema("state_change"),
+                }),
+            };
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+            return Ok(());
         }
     };
+    let method = operation.method();
+
+    let server = if let Some(path) = via_socket {
+        Some(remote::connect(&path)?)
+    } else if !direct {
+        // Fall back to the compositor when no server is running or it is
+        // another version of cos-cli
+        cos_cli::default_socket_path()
+            .and_then(|path| std::os::unix::net::UnixStream::connect(path).ok())
+            .filter(remote::same_version)
+    } else {
+        None
+    };
+    let result = if let Some(stream) = server {
+        tracing::debug!("Forwarding command to the server");
+        remote::call(&stream, &operation)?
+    } else {
+        let mut client = connect()?;
+        let output = client.execute(operation)?;
+        // Let the compositor process the requests before disconnecting
+        client.dispatch()?;
+        serde_json::to_value(output)?
+    };
 
-    Ok(())
+    print_result(method, json, &result)
+}
+
+/// The library's `test_app`, which the tests of the binary do not see.
+#[cfg(test)]
+pub(crate) fn test_app(id: u32, app_id: &str) -> cos_cli::JsonApp {
+    cos_cli::JsonApp {
+        index: id as usize,
+        id,
+        app_id: app_id.into(),
+        title: String::new(),
+        state: Vec::new(),
+        outputs: Vec::new(),
+        workspaces: Vec::new(),
+    }
 }
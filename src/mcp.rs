@@ -0,0 +1,301 @@
+//! `cos-cli mcp`: the command set as Model Context Protocol tools over stdio,
+//! backed by the same wayland thread as `serve`.
+
+use futures::FutureExt;
+use jsonrpc_stdio_server::jsonrpc_core::{Error, IoHandler, Params};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::error::Error as StdError;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use cos_cli::events::StateChange;
+use cos_cli::{CosmicClient, Operation};
+
+use crate::server::{self, ServerHandler, invalid_params};
+
+/// Newest protocol revision first. Clients asking for another one get the
+/// newest.
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Operations exposed as tools. `info` is the resource instead.
+const TOOLS: &[&str] = &["move", "activate", "state", "ws_activate", "close"];
+
+const INFO_URI: &str = "cos-cli://info";
+
+#[derive(Deserialize)]
+struct InitializeParams {
+    #[serde(rename = "protocolVersion")]
+    protocol_version: String,
+}
+
+#[derive(Deserialize)]
+struct CallToolParams {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Deserialize)]
+struct ResourceParams {
+    uri: String,
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(params: Params) -> Result<T, Error> {
+    params.parse().map_err(|e| invalid_params(&e.to_string()))
+}
+
+fn check_uri(uri: &str) -> Result<(), Error> {
+    match uri {
+        INFO_URI => Ok(()),
+        uri => Err(invalid_params(&format!("Unknown resource: {uri}"))),
+    }
+}
+
+fn tools() -> Value {
+    let tools = TOOLS
+        .iter()
+        .map(|&name| {
+            json!({
+                "name": name,
+                "description": cos_cli::schema::summary(name),
+                "inputSchema": cos_cli::schema::params(name),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "tools": tools })
+}
+
+/// Runs a tool. Failures of the operation are reported in the result, as
+/// MCP expects, so the model can see and react to them.
+async fn call_tool(handler: Arc<ServerHandler>, params: CallToolParams) -> Result<Value, Error> {
+    if !TOOLS.contains(&params.name.as_str()) {
+        return Err(invalid_params(&format!("Unknown tool: {}", params.name)));
+    }
+    let result = match Operation::parse(&params.name, params.arguments) {
+        Ok(operation) => handler.handle_request(operation).await,
+        Err(e) => Err(server::rpc_error(e)),
+    };
+    Ok(match result {
+        Ok(result) => json!({
+            "content": [{ "type": "text", "text": result.to_string() }],
+            "structuredContent": result,
+            "isError": false,
+        }),
+        Err(e) => json!({
+            "content": [{ "type": "text", "text": e.message }],
+            "isError": true,
+        }),
+    })
+}
+
+fn io_handler(handler: Arc<ServerHandler>, subscribed: Arc<AtomicBool>) -> IoHandler {
+    let mut io = IoHandler::new();
+
+    io.add_sync_method("initialize", |params: Params| {
+        let params: InitializeParams = parse(params)?;
+        let version = PROTOCOL_VERSIONS
+            .iter()
+            .find(|&&v| v == params.protocol_version)
+            .unwrap_or(&PROTOCOL_VERSIONS[0]);
+        Ok(json!({
+            "protocolVersion": version,
+            "capabilities": {
+                "tools": {},
+                "resources": { "subscribe": true },
+            },
+            "serverInfo": {
+                "name": "cos-cli",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        }))
+    });
+    io.add_notification("notifications/initialized", |_: Params| {});
+    io.add_sync_method("ping", |_: Params| Ok(json!({})));
+
+    io.add_sync_method("tools/list", |_: Params| Ok(tools()));
+    io.add_method("tools/call", {
+        let handler = handler.clone();
+        move |params: Params| {
+            let handler = handler.clone();
+            async move { call_tool(handler, parse(params)?).await }.boxed()
+        }
+    });
+
+    io.add_sync_method("resources/list", |_: Params| {
+        Ok(json!({
+            "resources": [{
+                "uri": INFO_URI,
+                "name": "info",
+                "description": cos_cli::schema::summary("info"),
+                "mimeType": "application/json",
+            }],
+        }))
+    });
+    io.add_method("resources/read", move |params: Params| {
+        let handler = handler.clone();
+        async move {
+            let params: ResourceParams = parse(params)?;
+            check_uri(&params.uri)?;
+            let info = handler
+                .handle_request(Operation::Info(Default::default()))
+                .await?;
+            Ok(json!({
+                "contents": [{
+                    "uri": params.uri,
+                    "mimeType": "application/json",
+                    "text": info.to_string(),
+                }],
+            }))
+        }
+        .boxed()
+    });
+    io.add_sync_method("resources/subscribe", {
+        let subscribed = subscribed.clone();
+        move |params: Params| {
+            check_uri(&parse::<ResourceParams>(params)?.uri)?;
+            subscribed.store(true, Ordering::Relaxed);
+            Ok(json!({}))
+        }
+    });
+    io.add_sync_method("resources/unsubscribe", move |params: Params| {
+        check_uri(&parse::<ResourceParams>(params)?.uri)?;
+        subscribed.store(false, Ordering::Relaxed);
+        Ok(json!({}))
+    });
+
+    io
+}
+
+/// Tells a subscribed client that `info` changed. The client reads the
+/// resource again, so changes queued up in the meantime need one
+/// notification only.
+async fn publish_updates(
+    mut events_rx: broadcast::Receiver<Arc<StateChange>>,
+    subscribed: Arc<AtomicBool>,
+) {
+    loop {
+        match events_rx.recv().await {
+            Ok(_) | Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => break,
+        }
+        while let Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_)) = events_rx.try_recv() {}
+        if subscribed.load(Ordering::Relaxed) {
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "notifications/resources/updated",
+                "params": { "uri": INFO_URI },
+            });
+            // Fails once the client closed stdout
+            if let Err(e) = writeln!(std::io::stdout().lock(), "{notification}") {
+                tracing::debug!("Stopped writing updates: {e}");
+                break;
+            }
+        }
+    }
+}
+
+/// Serves MCP on stdio until stdin closes or a stop is requested.
+pub async fn run(client: CosmicClient) -> Result<(), Box<dyn StdError>> {
+    server::run_backend(client, |handler, events_tx| async move {
+        let subscribed = Arc::new(AtomicBool::new(false));
+        let updates =
+            tokio::task::spawn(publish_updates(events_tx.subscribe(), subscribed.clone()));
+        let io = io_handler(handler, subscribed);
+        jsonrpc_stdio_server::ServerBuilder::new(io).build().await;
+        updates.abort();
+        tracing::info!("Stdin closed");
+        Ok(())
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The result of `method`, or the error.
+    fn request(io: &IoHandler, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response = io.handle_request_sync(&request.to_string()).unwrap();
+        let mut response: Value = serde_json::from_str(&response).unwrap();
+        match response.get("error") {
+            Some(error) => json!({ "error": error }),
+            None => response["result"].take(),
+        }
+    }
+
+    fn io() -> (IoHandler, Arc<AtomicBool>) {
+        let subscribed = Arc::new(AtomicBool::new(false));
+        (
+            io_handler(ServerHandler::disconnected(), subscribed.clone()),
+            subscribed,
+        )
+    }
+
+    #[test]
+    fn initialize_negotiates_the_version() {
+        let (io, _) = io();
+        let known = request(
+            &io,
+            "initialize",
+            json!({ "protocolVersion": "2025-03-26" }),
+        );
+        assert_eq!(known["protocolVersion"], "2025-03-26");
+        let unknown = request(
+            &io,
+            "initialize",
+            json!({ "protocolVersion": "2023-01-01" }),
+        );
+        assert_eq!(unknown["protocolVersion"], PROTOCOL_VERSIONS[0]);
+        assert_eq!(unknown["serverInfo"]["name"], "cos-cli");
+        let missing = request(&io, "initialize", json!({}));
+        assert_eq!(missing["error"]["code"], -32602);
+    }
+
+    #[test]
+    fn every_tool_has_a_description_and_schema() {
+        let (io, _) = io();
+        let tools = request(&io, "tools/list", json!({}));
+        let tools = tools["tools"].as_array().unwrap();
+        assert_eq!(tools.len(), TOOLS.len());
+        for tool in tools {
+            assert!(tool["description"].is_string(), "{tool}");
+            assert!(tool["inputSchema"].is_object(), "{tool}");
+        }
+    }
+
+    #[test]
+    fn subscribing_to_info() {
+        let (io, subscribed) = io();
+        request(&io, "resources/subscribe", json!({ "uri": INFO_URI }));
+        assert!(subscribed.load(Ordering::Relaxed));
+        let unknown = request(&io, "resources/subscribe", json!({ "uri": "cos-cli://x" }));
+        assert_eq!(unknown["error"]["code"], -32602);
+        request(&io, "resources/unsubscribe", json!({ "uri": INFO_URI }));
+        assert!(!subscribed.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn failed_tools_report_is_error() {
+        let call = |name: &str, arguments| {
+            call_tool(
+                ServerHandler::disconnected(),
+                CallToolParams {
+                    name: name.into(),
+                    arguments,
+                },
+            )
+        };
+        // The operation fails: without a wayland thread, or on bad arguments
+        let failed = call("close", json!({ "app_id": "kitty" })).await.unwrap();
+        assert_eq!(failed["isError"], true);
+        assert!(failed["content"][0]["text"].is_string());
+        let invalid = call("move", json!({ "workspace": [] })).await.unwrap();
+        assert_eq!(invalid["isError"], true);
+        // An unknown tool is an error of the request
+        assert!(call("info", json!({})).await.is_err());
+    }
+}
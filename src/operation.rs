@@ -0,0 +1,525 @@
+use schemars::JsonSchema;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::client::{
+    ActivateParams, ActivateResult, ActivateWsParams, ActivateWsResult, CloseParams, CloseResult,
+    Confirming, InfoParams, MoveParams, MoveResult, Requests, StateParams, StateResult,
+};
+use crate::confirm::Confirmation;
+use crate::error::ErrorObject;
+use crate::history::{HistoryParams, HistoryResult};
+use crate::{ClientError, CosmicClient, JsonInfo};
+
+/// One command of the cos-cli command set. The CLI and the JSON-RPC server
+/// both run commands through [`CosmicClient::execute`], so they behave the
+/// same. Serialized as `{"method": ..., "params": ...}`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Operation {
+    Info(InfoParams),
+    Move(MoveParams),
+    Activate(ActivateParams),
+    WsActivate(ActivateWsParams),
+    State(StateParams),
+    Close(CloseParams),
+    History(HistoryParams),
+    Batch(BatchParams),
+}
+
+impl Operation {
+    /// JSON-RPC method names of all operations.
+    pub const METHODS: &[&str] = &[
+        "info",
+        "move",
+        "activate",
+        "ws_activate",
+        "state",
+        "close",
+        "history",
+        "batch",
+    ];
+
+    /// Builds the operation for a JSON-RPC `method` call. Missing `params`
+    /// are treated as an empty object.
+    pub fn parse(method: &str, params: serde_json::Value) -> Result<Self, ClientError> {
+        let params = match params {
+            serde_json::Value::Null => serde_json::json!({}),
+            params => params,
+        };
+        serde_json::from_value(serde_json::json!({ "method": method, "params": params }))
+            .map_err(|e| ClientError::InvalidParams(e.to_string()))
+    }
+
+    pub fn method(&self) -> &'static str {
+        match self {
+            Operation::Info(_) => "info",
+            Operation::Move(_) => "move",
+            Operation::Activate(_) => "activate",
+            Operation::WsActivate(_) => "ws_activate",
+            Operation::State(_) => "state",
+            Operation::Close(_) => "close",
+            Operation::History(_) => "history",
+            Operation::Batch(_) => "batch",
+        }
+    }
+
+    /// The `params` object of the JSON-RPC call.
+    pub fn params(&self) -> serde_json::Value {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        value
+            .get_mut("params")
+            .map(serde_json::Value::take)
+            .unwrap_or_default()
+    }
+
+    /// Fails when `timeout` is given without `confirm`, which it applies to.
+    fn check_timeout(&self) -> Result<(), ClientError> {
+        let timeout_alone = match self {
+            Operation::Move(p) => p.timeout.is_some() && !p.confirm,
+            Operation::WsActivate(p) => p.timeout.is_some() && !p.confirm,
+            Operation::State(p) => p.timeout.is_some() && !p.confirm,
+            Operation::Close(p) => p.timeout.is_some() && !p.confirm,
+            Operation::Info(_)
+            | Operation::Activate(_)
+            | Operation::History(_)
+            | Operation::Batch(_) => false,
+        };
+        if timeout_alone {
+            return Err(ClientError::InvalidParams(format!(
+                "'{}' got timeout without confirm",
+                self.method()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether the operation dispatches wayland events while it runs, which
+    /// would break the snapshot a batch runs against.
+    fn dispatches(&self) -> bool {
+        match self {
+            Operation::Info(p) => p.discover_wg_output,
+            Operation::Move(p) => p.wait.is_some() || p.confirm,
+            Operation::Activate(_) => false,
+            Operation::WsActivate(p) => p.confirm,
+            Operation::State(p) => p.wait.is_some() || p.confirm,
+            Operation::Close(p) => p.wait.is_some() || p.confirm,
+            Operation::History(_) => false,
+            Operation::Batch(_) => true,
+        }
+    }
+}
+
+/// What a batch does after an operation failed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OnError {
+    /// Validate every operation first and send nothing if one fails
+    #[default]
+    StopOnError,
+    /// Run the remaining operations
+    Continue,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct BatchParams {
+    /// Operations to run in order, as `{"method": ..., "params": ...}`
+    #[serde(deserialize_with = "deserialize_operations")]
+    pub operations: Vec<Operation>,
+    #[serde(default)]
+    pub on_error: OnError,
+}
+
+/// Parses every operation like a JSON-RPC call, so `params` may be omitted.
+fn deserialize_operations<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Operation>, D::Error> {
+    #[derive(Deserialize)]
+    struct Call {
+        method: String,
+        #[serde(default)]
+        params: serde_json::Value,
+    }
+
+    Vec::<Call>::deserialize(deserializer)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, call)| {
+            Operation::parse(&call.method, call.params)
+                .map_err(|e| D::Error::custom(format!("operation {index}: {e}")))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Ok,
+    Error,
+    Skipped,
+}
+
+/// Outcome of one operation of a batch. `result` is what the method returns
+/// on its own.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BatchItem {
+    pub method: String,
+    pub status: BatchStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorObject>,
+}
+
+/// One item per operation, in the order they were given.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BatchResult {
+    pub results: Vec<BatchItem>,
+}
+
+impl BatchResult {
+    /// Every operation skipped except the one at `failed`.
+    fn failed_validation(operations: &[Operation], failed: usize, item: BatchItem) -> Self {
+        let mut item = Some(item);
+        let results = operations
+            .iter()
+            .enumerate()
+            .map(|(index, operation)| match index == failed {
+                true => item.take().unwrap_or_else(|| BatchItem::skipped(operation)),
+                false => BatchItem::skipped(operation),
+            })
+            .collect();
+        BatchResult { results }
+    }
+
+    /// Runs `operations` in order with `execute`. With
+    /// [`OnError::StopOnError`] the ones after a failure are skipped.
+    fn run(
+        operations: &[Operation],
+        on_error: OnError,
+        mut execute: impl FnMut(&Operation) -> Result<Output, ClientError>,
+    ) -> Self {
+        let mut results = Vec::with_capacity(operations.len());
+        let mut stopped = false;
+        for operation in operations {
+            if stopped {
+                results.push(BatchItem::skipped(operation));
+                continue;
+            }
+            let item = BatchItem::new(operation, execute(operation));
+            // Only a lost compositor connection fails an operation that
+            // passed validation
+            stopped = item.status == BatchStatus::Error && on_error == OnError::StopOnError;
+            results.push(item);
+        }
+        BatchResult { results }
+    }
+}
+
+impl BatchItem {
+    fn new(operation: &Operation, result: Result<Output, ClientError>) -> Self {
+        let (status, result, error) = match result {
+            Ok(output) => (BatchStatus::Ok, serde_json::to_value(output).ok(), None),
+            Err(e) => (BatchStatus::Error, None, Some(ErrorObject::from(&e))),
+        };
+        Self {
+            method: operation.method().to_string(),
+            status,
+            result,
+            error,
+        }
+    }
+
+    fn skipped(operation: &Operation) -> Self {
+        Self {
+            method: operation.method().to_string(),
+            status: BatchStatus::Skipped,
+            result: None,
+            error: None,
+        }
+    }
+}
+
+/// An operation run by [`CosmicClient::start`].
+#[derive(Debug)]
+pub enum Started {
+    Done(Output),
+    /// The requests were sent, the outcome is not known yet
+    Confirming(Confirmation),
+    /// The app to `wait` for did not appear yet, nothing was sent
+    Waiting(Waiting),
+}
+
+/// An operation that waits for its app to appear. The caller dispatches and
+/// polls it until the app is there or the `wait` seconds are over.
+#[derive(Debug)]
+pub struct Waiting {
+    operation: Operation,
+    app_id: String,
+    wait: u64,
+    deadline: Instant,
+}
+
+impl Waiting {
+    /// Runs the operation again. `None` while its app is still missing and
+    /// there is time left.
+    pub fn poll(&self, client: &mut CosmicClient) -> Option<Result<Started, ClientError>> {
+        match client.start(self.operation.clone()) {
+            Ok(Started::Waiting(_)) if Instant::now() < self.deadline => None,
+            Ok(Started::Waiting(waiting)) => Some(Err(waiting.timeout())),
+            started => Some(started),
+        }
+    }
+
+    /// The error when the app did not appear in time.
+    pub fn timeout(self) -> ClientError {
+        ClientError::Timeout {
+            app_id: self.app_id,
+            wait: self.wait,
+        }
+    }
+}
+
+/// Result of an [`Operation`], serialized as the JSON-RPC `result`.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Output {
+    Info(JsonInfo),
+    Move(MoveResult),
+    Activate(ActivateResult),
+    WsActivate(ActivateWsResult),
+    State(StateResult),
+    Close(CloseResult),
+    History(HistoryResult),
+    Batch(BatchResult),
+}
+
+impl CosmicClient {
+    pub fn execute(&mut self, operation: Operation) -> Result<Output, ClientError> {
+        operation.check_timeout()?;
+        Ok(match operation {
+            Operation::Info(params) => {
+                if params.discover_wg_output {
+                    self.discover_workspace_group_outputs()?;
+                }
+                Output::Info(self.info())
+            }
+            Operation::Move(params) => Output::Move(self.move_window(params)?),
+            Operation::Activate(params) => Output::Activate(self.activate(params)?),
+            Operation::WsActivate(params) => Output::WsActivate(self.activate_workspace(params)?),
+            Operation::State(params) => Output::State(self.set_state(params)?),
+            Operation::Close(params) => Output::Close(self.close(params)?),
+            Operation::History(params) => Output::History(self.history(params)),
+            Operation::Batch(params) => Output::Batch(self.batch(params)?),
+        })
+    }
+
+    /// Runs `operation` like [`execute`](Self::execute), but returns as soon
+    /// as the requests are sent when it asked to `confirm`, and at once when
+    /// the app it should `wait` for is missing. The caller then dispatches
+    /// and polls the [`Confirmation`] or [`Waiting`] until it is done, so that
+    /// a server can run other operations meanwhile.
+    pub fn start(&mut self, operation: Operation) -> Result<Started, ClientError> {
+        self.confirming = Confirming::Defer(None);
+        let output = match self.execute(operation.clone()) {
+            Err(ClientError::Timeout { app_id, wait }) => {
+                self.confirming = Confirming::Wait;
+                return Ok(Started::Waiting(Waiting {
+                    operation,
+                    app_id,
+                    wait,
+                    deadline: Instant::now() + Duration::from_secs(wait),
+                }));
+            }
+            output => output,
+        };
+        let deferred = match std::mem::take(&mut self.confirming) {
+            Confirming::Defer(deferred) => deferred,
+            Confirming::Wait => None,
+        };
+        Ok(match deferred {
+            Some((watch, timeout)) => {
+                Started::Confirming(Confirmation::new(output?, watch, timeout))
+            }
+            None => Started::Done(output?),
+        })
+    }
+
+    /// Runs `operations` in order against the current state, without
+    /// dispatching in between, and flushes all their requests at once.
+    ///
+    /// With [`OnError::StopOnError`] every operation is resolved before any
+    /// request is sent, so a batch with an operation that fails validation
+    /// changes nothing. The batch is not atomic beyond that: requests cannot
+    /// be taken back once queued, so when an operation fails while the
+    /// requests are built, which only happens when the compositor connection
+    /// is lost, those of the operations before it are still flushed.
+    pub fn batch(&mut self, params: BatchParams) -> Result<BatchResult, ClientError> {
+        let operations = params.operations;
+        if params.on_error == OnError::StopOnError {
+            self.requests = Requests::Discard;
+            let failed = operations
+                .iter()
+                .enumerate()
+                .find_map(|(index, operation)| {
+                    let result = self.execute_batched(operation.clone());
+                    result
+                        .is_err()
+                        .then(|| (index, BatchItem::new(operation, result)))
+                });
+            self.requests = Requests::Flush;
+
+            if let Some((failed, item)) = failed {
+                return Ok(BatchResult::failed_validation(&operations, failed, item));
+            }
+        }
+
+        self.requests = Requests::Queue;
+        let results = BatchResult::run(&operations, params.on_error, |operation| {
+            self.execute_batched(operation.clone())
+        });
+        self.requests = Requests::Flush;
+        self.flush()?;
+
+        Ok(results)
+    }
+
+    fn execute_batched(&mut self, operation: Operation) -> Result<Output, ClientError> {
+        if operation.dispatches() {
+            return Err(ClientError::InvalidParams(format!(
+                "'{}' cannot run in a batch with wait, confirm, discover_wg_output or nested batches",
+                operation.method()
+            )));
+        }
+        self.execute(operation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn batch(operations: serde_json::Value) -> Result<Operation, ClientError> {
+        Operation::parse("batch", json!({ "operations": operations }))
+    }
+
+    fn statuses(result: &BatchResult) -> Vec<BatchStatus> {
+        result.results.iter().map(|item| item.status).collect()
+    }
+
+    #[test]
+    fn parse_treats_missing_params_as_empty() {
+        let operation = Operation::parse("info", serde_json::Value::Null).unwrap();
+        assert!(matches!(
+            operation,
+            Operation::Info(InfoParams {
+                discover_wg_output: false
+            })
+        ));
+        assert_eq!(operation.method(), "info");
+    }
+
+    #[test]
+    fn parse_rejects_unknown_methods_and_params() {
+        assert!(matches!(
+            Operation::parse("nope", json!({})),
+            Err(ClientError::InvalidParams(_))
+        ));
+        // `workspace` is required
+        assert!(matches!(
+            Operation::parse("move", json!({ "app_id": "firefox" })),
+            Err(ClientError::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn params_round_trip() {
+        let operation = Operation::parse("close", json!({ "app_id": "kitty" })).unwrap();
+        let parsed = Operation::parse(operation.method(), operation.params()).unwrap();
+        assert!(matches!(parsed, Operation::Close(p) if p.app_id.as_deref() == Some("kitty")));
+    }
+
+    #[test]
+    fn batch_parses_operations_and_defaults_to_stop_on_error() {
+        let Operation::Batch(params) = batch(json!([
+            { "method": "info" },
+            { "method": "activate", "params": { "index": 0 } },
+        ]))
+        .unwrap() else {
+            panic!("not a batch");
+        };
+        assert_eq!(params.on_error, OnError::StopOnError);
+        let methods = params
+            .operations
+            .iter()
+            .map(Operation::method)
+            .collect::<Vec<_>>();
+        assert_eq!(methods, ["info", "activate"]);
+    }
+
+    #[test]
+    fn batch_names_the_invalid_operation() {
+        let Err(ClientError::InvalidParams(message)) =
+            batch(json!([{ "method": "info" }, { "method": "nope" }]))
+        else {
+            panic!("invalid operation accepted");
+        };
+        assert!(message.contains("operation 1"), "{message}");
+    }
+
+    #[test]
+    fn failed_validation_skips_the_other_operations() {
+        let operations = [
+            Operation::History(HistoryParams::default()),
+            Operation::History(HistoryParams::default()),
+            Operation::History(HistoryParams::default()),
+        ];
+        let item = BatchItem::new(
+            &operations[1],
+            Err(ClientError::InvalidParams("bad".into())),
+        );
+        let result = BatchResult::failed_validation(&operations, 1, item);
+        assert_eq!(
+            statuses(&result),
+            [
+                BatchStatus::Skipped,
+                BatchStatus::Error,
+                BatchStatus::Skipped
+            ]
+        );
+    }
+
+    #[test]
+    fn run_stops_or_continues_after_an_error() {
+        let operations = [
+            Operation::History(HistoryParams::default()),
+            Operation::History(HistoryParams::default()),
+            Operation::History(HistoryParams::default()),
+        ];
+        let execute = |index: &mut usize| {
+            *index += 1;
+            match *index {
+                2 => Err(ClientError::InvalidParams("bad".into())),
+                _ => Ok(Output::History(HistoryResult {
+                    windows: Vec::new(),
+                })),
+            }
+        };
+
+        let mut index = 0;
+        let result = BatchResult::run(&operations, OnError::StopOnError, |_| execute(&mut index));
+        assert_eq!(
+            statuses(&result),
+            [BatchStatus::Ok, BatchStatus::Error, BatchStatus::Skipped]
+        );
+
+        let mut index = 0;
+        let result = BatchResult::run(&operations, OnError::Continue, |_| execute(&mut index));
+        assert_eq!(
+            statuses(&result),
+            [BatchStatus::Ok, BatchStatus::Error, BatchStatus::Ok]
+        );
+    }
+}
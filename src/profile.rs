@@ -0,0 +1,334 @@
+//! Layout profiles: where workspaces and windows go for a set of connected
+//! outputs. Profiles are part of the configuration file:
+//!
+//! ```toml
+//! [profiles.docked]
+//! outputs = ["eDP-1", { make = "Dell", model = "U2720Q" }]
+//! workspaces = [{ workspace = "Web", output = { model = "U2720Q" } }]
+//! windows = [{ match = { app_id = "firefox" }, output = { model = "U2720Q" } }]
+//!
+//! [profiles.laptop]
+//! outputs = ["eDP-1"]
+//! windows = [{ match = {}, output = "eDP-1" }]
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::client::MoveParams;
+use crate::config::{Config, Target};
+use crate::rules::Match;
+use crate::selector::contains;
+use crate::{ClientError, CosmicClient, JsonOutput};
+
+/// An output by name, or by name, make and model (make and model partial,
+/// case-insensitive).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum OutputMatch {
+    Name(String),
+    Match {
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        make: Option<String>,
+        #[serde(default)]
+        model: Option<String>,
+    },
+}
+
+impl OutputMatch {
+    pub fn matches(&self, output: &JsonOutput) -> bool {
+        match self {
+            OutputMatch::Name(name) => &output.name == name,
+            OutputMatch::Match { name, make, model } => {
+                name.as_ref().is_none_or(|name| &output.name == name)
+                    && make
+                        .as_deref()
+                        .is_none_or(|make| contains(&output.make, make))
+                    && model
+                        .as_deref()
+                        .is_none_or(|model| contains(&output.model, model))
+            }
+        }
+    }
+}
+
+impl fmt::Display for OutputMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputMatch::Name(name) => write!(f, "'{name}'"),
+            OutputMatch::Match { name, make, model } => {
+                let fields = [("name", name), ("make", make), ("model", model)]
+                    .into_iter()
+                    .filter_map(|(key, value)| Some(format!("{key} '{}'", value.as_ref()?)))
+                    .collect::<Vec<_>>();
+                write!(f, "{}", fields.join(" "))
+            }
+        }
+    }
+}
+
+/// Moves a workspace to the workspace group of an output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspacePlacement {
+    /// Index, label from the config or name
+    pub workspace: Target,
+    /// Workspace group `workspace` is in now
+    #[serde(default)]
+    pub workspace_group: Option<usize>,
+    pub output: OutputMatch,
+}
+
+/// Moves the matching windows to an output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WindowPlacement {
+    #[serde(default, rename = "match")]
+    pub matcher: Match,
+    pub output: OutputMatch,
+    /// Workspace on the output (default: its active workspace)
+    #[serde(default)]
+    pub workspace: Option<Target>,
+    /// Workspace group of `workspace` (default: the group of the output)
+    #[serde(default)]
+    pub workspace_group: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Outputs that must be connected for the profile to apply
+    pub outputs: Vec<OutputMatch>,
+    #[serde(default)]
+    pub workspaces: Vec<WorkspacePlacement>,
+    /// The first placement a window matches applies
+    #[serde(default)]
+    pub windows: Vec<WindowPlacement>,
+}
+
+impl Profile {
+    /// Whether every output of the profile is connected.
+    pub fn matches(&self, outputs: &[JsonOutput]) -> bool {
+        !self.outputs.is_empty()
+            && self
+                .outputs
+                .iter()
+                .all(|m| outputs.iter().any(|output| m.matches(output)))
+    }
+}
+
+impl Config {
+    /// The profile for the connected `outputs`: of those whose outputs are
+    /// all connected the one naming the most outputs, the first by name on
+    /// a tie. A laptop profile thus gives way to a docked one.
+    pub fn profile(&self, outputs: &[JsonOutput]) -> Option<(&str, &Profile)> {
+        self.profiles
+            .iter()
+            .filter(|(_, profile)| profile.matches(outputs))
+            .fold(
+                None,
+                |best: Option<(&String, &Profile)>, candidate| match best {
+                    Some(best) if best.1.outputs.len() >= candidate.1.outputs.len() => Some(best),
+                    _ => Some(candidate),
+                },
+            )
+            .map(|(name, profile)| (name.as_str(), profile))
+    }
+}
+
+impl CosmicClient {
+    fn profile_output(&self, output: &OutputMatch) -> Result<JsonOutput, ClientError> {
+        self.outputs()
+            .into_iter()
+            .find(|o| output.matches(o))
+            .ok_or_else(|| ClientError::InvalidParams(format!("No output {output}")))
+    }
+
+    /// The workspace group shown on `output`.
+    fn output_group(&self, output: &JsonOutput) -> Result<usize, ClientError> {
+        self.workspaces()
+            .iter()
+            .find(|g| g.outputs.contains(&output.name))
+            .map(|g| g.index)
+            .ok_or_else(|| {
+                ClientError::InvalidParams(format!(
+                    "No workspace group on output '{}'",
+                    output.name
+                ))
+            })
+    }
+
+    /// Moves the workspaces, then the windows of `profile` to their outputs,
+    /// leaving those already in place. Returns the outcome of every placement
+    /// that had something to do.
+    pub fn apply_profile(&mut self, profile: &Profile) -> Vec<Result<String, ClientError>> {
+        let mut outcomes = Vec::new();
+        for placement in &profile.workspaces {
+            let outcome = self.place_workspace(placement);
+            if !matches!(outcome, Ok(None)) {
+                outcomes.push(outcome.map(Option::unwrap_or_default));
+            }
+        }
+        // Let the workspace groups reflect the assignments
+        if !outcomes.is_empty()
+            && let Err(e) = self.dispatch()
+        {
+            outcomes.push(Err(e));
+            return outcomes;
+        }
+
+        for app in self.windows() {
+            let Some(placement) = profile.windows.iter().find(|p| p.matcher.matches(&app)) else {
+                continue;
+            };
+            let outcome = self.place_window(placement, &app);
+            if !matches!(outcome, Ok(None)) {
+                outcomes.push(outcome.map(Option::unwrap_or_default));
+            }
+        }
+        outcomes
+    }
+
+    fn place_workspace(
+        &mut self,
+        placement: &WorkspacePlacement,
+    ) -> Result<Option<String>, ClientError> {
+        let output = self.profile_output(&placement.output)?;
+        let group = self.output_group(&output)?;
+        let workspace = self.resolve_workspace(&placement.workspace, placement.workspace_group)?;
+        if workspace.0 == group {
+            return Ok(None);
+        }
+        self.assign_workspace(workspace, group)?;
+        Ok(Some(format!(
+            "workspace {} to output '{}': assign",
+            placement.workspace, output.name
+        )))
+    }
+
+    fn place_window(
+        &mut self,
+        placement: &WindowPlacement,
+        app: &crate::JsonApp,
+    ) -> Result<Option<String>, ClientError> {
+        let output = self.profile_output(&placement.output)?;
+        let (group, workspace) = match &placement.workspace {
+            Some(workspace) => {
+                let group = match placement.workspace_group {
+                    Some(group) => group,
+                    None => self.output_group(&output)?,
+                };
+                self.resolve_workspace(workspace, Some(group))?
+            }
+            None => {
+                let group = self.output_group(&output)?;
+                (group, self.active_workspace(group).unwrap_or_default())
+            }
+        };
+        let on_output = app.outputs.iter().any(|o| o.index == output.index);
+        let on_workspace = placement.workspace.is_none()
+            || app
+                .workspaces
+                .iter()
+                .any(|w| w.group_index == group && w.index == workspace);
+        if on_output && on_workspace {
+            return Ok(None);
+        }
+        let result = self.move_window(MoveParams {
+            index: Some(app.index),
+            workspace: workspace.into(),
+            workspace_group: Some(group),
+            output_index: Some(output.index),
+            ..Default::default()
+        })?;
+        Ok(Some(format!(
+            "{} [{}] to output '{}': {}",
+            app.app_id,
+            app.id,
+            output.name,
+            result.requests.join(", ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(name: &str, make: &str, model: &str) -> JsonOutput {
+        JsonOutput {
+            index: 0,
+            name: name.into(),
+            make: make.into(),
+            model: model.into(),
+        }
+    }
+
+    fn outputs() -> Vec<JsonOutput> {
+        vec![
+            output("eDP-1", "BOE", "0x095F"),
+            output("DP-1", "Dell Inc.", "DELL U2720Q"),
+        ]
+    }
+
+    const PROFILES: &str = r#"
+        [profiles.laptop]
+        outputs = ["eDP-1"]
+
+        [profiles.docked]
+        outputs = ["eDP-1", { make = "dell", model = "u2720q" }]
+
+        [profiles.office]
+        outputs = ["eDP-1", "HDMI-1"]
+    "#;
+
+    #[test]
+    fn output_match_by_name_is_exact() {
+        let dell = output("DP-1", "Dell Inc.", "DELL U2720Q");
+        assert!(OutputMatch::Name("DP-1".into()).matches(&dell));
+        assert!(!OutputMatch::Name("DP".into()).matches(&dell));
+        assert!(!OutputMatch::Name("dp-1".into()).matches(&dell));
+    }
+
+    #[test]
+    fn output_match_by_make_and_model_is_partial() {
+        let dell = output("DP-1", "Dell Inc.", "DELL U2720Q");
+        let matcher =
+            |name: Option<&str>, make: Option<&str>, model: Option<&str>| OutputMatch::Match {
+                name: name.map(Into::into),
+                make: make.map(Into::into),
+                model: model.map(Into::into),
+            };
+        assert!(matcher(None, Some("dell"), Some("u2720")).matches(&dell));
+        assert!(matcher(Some("DP-1"), None, None).matches(&dell));
+        assert!(!matcher(Some("DP-2"), Some("dell"), None).matches(&dell));
+        assert!(!matcher(None, None, Some("U2723")).matches(&dell));
+    }
+
+    #[test]
+    fn profile_needs_every_output() {
+        let config: Config = PROFILES.parse().unwrap();
+        assert!(config.profiles["docked"].matches(&outputs()));
+        assert!(!config.profiles["office"].matches(&outputs()));
+        let empty = Profile {
+            outputs: Vec::new(),
+            workspaces: Vec::new(),
+            windows: Vec::new(),
+        };
+        assert!(!empty.matches(&outputs()));
+    }
+
+    #[test]
+    fn profile_with_the_most_outputs_wins() {
+        let config: Config = PROFILES.parse().unwrap();
+        assert_eq!(
+            config.profile(&outputs()).map(|(name, _)| name),
+            Some("docked")
+        );
+        let laptop = &outputs()[..1];
+        assert_eq!(config.profile(laptop).map(|(name, _)| name), Some("laptop"));
+        assert!(config.profile(&[]).is_none());
+    }
+}
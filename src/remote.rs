@@ -0,0 +1,73 @@
+use serde_json::{Value, json};
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use cos_cli::Operation;
+
+use crate::CliError;
+
+pub fn connect(path: &Path) -> Result<UnixStream, Box<dyn Error>> {
+    UnixStream::connect(path).map_err(|e| {
+        CliError::new(format!(
+            "Failed to connect to server at {}: {e}",
+            path.display()
+        ))
+        .into()
+    })
+}
+
+/// Runs `operation` on a `serve --socket` server and returns its result.
+pub fn call(stream: &UnixStream, operation: &Operation) -> Result<Value, Box<dyn Error>> {
+    request(stream, operation.method(), operation.params())
+}
+
+/// Whether the server is the same version of cos-cli, so that it accepts
+/// the same params and returns the same results.
+pub fn same_version(stream: &UnixStream) -> bool {
+    match request(stream, "version", Value::Null) {
+        Ok(result) if result["version"] == env!("CARGO_PKG_VERSION") => true,
+        Ok(result) => {
+            tracing::debug!("Server is cos-cli {}", result["version"]);
+            false
+        }
+        Err(e) => {
+            tracing::debug!("Failed to get the server version: {e}");
+            false
+        }
+    }
+}
+
+fn request(mut stream: &UnixStream, method: &str, params: Value) -> Result<Value, Box<dyn Error>> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+        "id": 1,
+    });
+    writeln!(stream, "{request}")?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(CliError::new("Server closed the connection".into()));
+        }
+        let response: Value = serde_json::from_str(&line)?;
+        // Skip notifications
+        if response.get("id") != Some(&json!(1)) {
+            continue;
+        }
+        if let Some(error) = response.get("error") {
+            return Err(CliError::new(
+                error["message"]
+                    .as_str()
+                    .unwrap_or("Unknown server error")
+                    .to_string(),
+            ));
+        }
+        return Ok(response["result"].clone());
+    }
+}
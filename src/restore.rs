@@ -0,0 +1,201 @@
+//! `cos-cli session save` and `session restore`.
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cos_cli::CosmicClient;
+use cos_cli::session::{Session, SessionWindow};
+
+use crate::CliError;
+
+/// How often the windows are checked while waiting for launched apps.
+const POLL: Duration = Duration::from_millis(200);
+
+/// Writes the placement of every window to `path`, or stdout for `-`.
+pub fn save(path: &Path, json: bool) -> Result<(), Box<dyn Error>> {
+    let client = crate::connect()?;
+    let session = client.session();
+    let content = serde_json::to_string_pretty(&session)?;
+    if path.as_os_str() == "-" {
+        println!("{content}");
+        return Ok(());
+    }
+    std::fs::write(path, content + "\n")
+        .map_err(|e| CliError::new(format!("Failed to write {}: {e}", path.display())))?;
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({ "path": path, "windows": session.windows.len() })
+        );
+    } else {
+        println!(
+            "Saved {} windows to {}",
+            session.windows.len(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct Restored {
+    id: u32,
+    app_id: String,
+    title: String,
+    /// Empty when the window was already in place
+    requests: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Failed {
+    window: SessionWindow,
+    error: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    restored: Vec<Restored>,
+    failed: Vec<Failed>,
+    /// Saved windows no open window matched
+    missing: Vec<SessionWindow>,
+    /// Commands started for missing windows
+    launched: Vec<Vec<String>>,
+}
+
+impl Report {
+    fn print(&self) {
+        for window in &self.restored {
+            let requests = if window.requests.is_empty() {
+                "already in place".to_string()
+            } else {
+                window.requests.join(", ")
+            };
+            println!(
+                "Restored {} [{}] '{}': {requests}",
+                window.app_id, window.id, window.title
+            );
+        }
+        for command in &self.launched {
+            println!("Launched {}", command.join(" "));
+        }
+        for failed in &self.failed {
+            println!(
+                "Failed {} '{}': {}",
+                failed.window.app_id, failed.window.title, failed.error
+            );
+        }
+        for window in &self.missing {
+            println!("Not found: {} '{}'", window.app_id, window.title);
+        }
+    }
+}
+
+/// Restores the saved windows that match an open one and returns the others.
+fn restore_matching(
+    client: &mut CosmicClient,
+    windows: Vec<SessionWindow>,
+    taken: &mut HashSet<u32>,
+    report: &mut Report,
+) -> Vec<SessionWindow> {
+    let matches = client.match_session(&windows, taken);
+    let mut missing = Vec::new();
+    for (saved, app) in windows.into_iter().zip(matches) {
+        let Some(app) = app else {
+            missing.push(saved);
+            continue;
+        };
+        taken.insert(app.id);
+        match client.restore_window(&saved, &app) {
+            Ok(requests) => report.restored.push(Restored {
+                id: app.id,
+                app_id: app.app_id,
+                title: app.title,
+                requests,
+            }),
+            Err(e) => report.failed.push(Failed {
+                window: saved,
+                error: e.to_string(),
+            }),
+        }
+    }
+    missing
+}
+
+/// The command that starts the app of `window`.
+fn launch_command(window: &SessionWindow) -> Vec<String> {
+    window
+        .command
+        .clone()
+        .unwrap_or_else(|| vec!["gtk-launch".to_string(), window.app_id.clone()])
+}
+
+/// Moves the windows saved in `path` back into place. With `launch` the
+/// apps of missing windows are started and their windows restored as they
+/// appear within `wait`. Fails if any window could not be restored.
+pub fn restore(
+    path: &Path,
+    launch: bool,
+    wait: Duration,
+    json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| CliError::new(format!("Failed to read {}: {e}", path.display())))?;
+    let session: Session = serde_json::from_str(&content)
+        .map_err(|e| CliError::new(format!("Invalid session in {}: {e}", path.display())))?;
+    let total = session.windows.len();
+
+    let mut client = crate::connect()?;
+    let mut report = Report::default();
+    let mut taken = HashSet::new();
+    let mut missing = restore_matching(&mut client, session.windows, &mut taken, &mut report);
+
+    if launch && !missing.is_empty() {
+        // Once per app, most restore their other windows themselves
+        let mut launched = HashSet::new();
+        for command in missing.iter().map(launch_command) {
+            if !launched.insert(command.clone()) {
+                continue;
+            }
+            let Some((program, args)) = command.split_first() else {
+                continue;
+            };
+            let spawned = Command::new(program)
+                .args(args)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .spawn();
+            match spawned {
+                Ok(_) => report.launched.push(command),
+                Err(e) => tracing::warn!("Failed to launch {}: {e}", command.join(" ")),
+            }
+        }
+
+        let deadline = Instant::now() + wait;
+        while !missing.is_empty() && Instant::now() < deadline {
+            thread::sleep(POLL);
+            client.dispatch()?;
+            missing = restore_matching(&mut client, missing, &mut taken, &mut report);
+        }
+    }
+    // Let the compositor process the requests before disconnecting
+    client.dispatch()?;
+    report.missing = missing;
+
+    if json {
+        println!("{}", serde_json::to_string(&report)?);
+    } else {
+        report.print();
+    }
+    let unrestored = report.failed.len() + report.missing.len();
+    if unrestored > 0 {
+        return Err(CliError::new(format!(
+            "{unrestored} of {total} windows could not be restored"
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,267 @@
+//! Declarative window rules: what to do with a toplevel when it appears.
+//!
+//! Rules are read from TOML:
+//!
+//! ```toml
+//! mode = "first"           # or "all"
+//!
+//! [[rules]]
+//! match = { app_id = "firefox" }
+//! workspace = "Web"        # name or index
+//! output = "DP-1"          # name or index
+//!
+//! [[rules]]
+//! match = { app_id = "mpv", state = ["fullscreen"] }
+//! sticky = true
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::client::{CloseParams, MoveParams, StateParams};
+use crate::config::Target;
+use crate::selector::Selector;
+use crate::{ClientError, CosmicClient, JsonApp, State};
+
+/// Which rules apply to a window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    /// Only the first matching rule
+    #[default]
+    First,
+    /// Every matching rule, in order
+    All,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rules {
+    #[serde(default)]
+    pub mode: MatchMode,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+/// Selects windows. Every given condition must hold, no condition matches
+/// every window.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Match {
+    /// Application ID (partial match, case-insensitive)
+    #[serde(default)]
+    pub app_id: Option<String>,
+    /// Title (partial match, case-insensitive)
+    #[serde(default)]
+    pub title: Option<String>,
+    /// States the window has when it appears
+    #[serde(default)]
+    pub state: Vec<State>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Shown in the log
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default, rename = "match")]
+    pub matcher: Match,
+    /// Workspace to move the window to: index, label from the config or name
+    #[serde(default)]
+    pub workspace: Option<Target>,
+    /// Workspace group of `workspace`
+    #[serde(default)]
+    pub workspace_group: Option<usize>,
+    /// Output to move the window to, together with `workspace`
+    #[serde(default)]
+    pub output: Option<Target>,
+    #[serde(default)]
+    pub maximize: bool,
+    #[serde(default)]
+    pub minimize: bool,
+    #[serde(default)]
+    pub fullscreen: bool,
+    #[serde(default)]
+    pub sticky: bool,
+    #[serde(default)]
+    pub close: bool,
+}
+
+impl FromStr for Rules {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rules: Rules =
+            toml::from_str(s).map_err(|e| ClientError::InvalidParams(e.to_string()))?;
+        for (index, rule) in rules.rules.iter().enumerate() {
+            if rule.workspace.is_none() && (rule.output.is_some() || rule.workspace_group.is_some())
+            {
+                return Err(ClientError::InvalidParams(format!(
+                    "Rule {}: output and workspace_group need a workspace",
+                    rule.label(index)
+                )));
+            }
+        }
+        Ok(rules)
+    }
+}
+
+impl Rules {
+    /// The rules that apply to `app`, with their index.
+    pub fn matching<'a>(&'a self, app: &'a JsonApp) -> impl Iterator<Item = (usize, &'a Rule)> {
+        let limit = match self.mode {
+            MatchMode::First => 1,
+            MatchMode::All => usize::MAX,
+        };
+        self.rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.matcher.matches(app))
+            .take(limit)
+    }
+}
+
+impl Match {
+    /// The selector with the same conditions.
+    pub fn selector(&self) -> Selector {
+        Selector {
+            app_id: self.app_id.clone(),
+            title: self.title.clone(),
+            state: self.state.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn matches(&self, app: &JsonApp) -> bool {
+        self.selector().matches(app)
+    }
+}
+
+impl Rule {
+    /// The name of the rule, or its position in the file.
+    pub fn label(&self, index: usize) -> String {
+        match &self.name {
+            Some(name) => format!("'{name}'"),
+            None => format!("#{index}"),
+        }
+    }
+
+    fn changes_state(&self) -> bool {
+        self.maximize || self.minimize || self.fullscreen || self.sticky
+    }
+}
+
+impl CosmicClient {
+    /// Applies the actions of `rule` to the window `app`: moves it, then sets
+    /// its states, then closes it. Returns the protocol requests sent.
+    pub fn apply_rule(&mut self, rule: &Rule, app: &JsonApp) -> Result<Vec<String>, ClientError> {
+        // Indices shift when windows close, the protocol id does not
+        let index = self
+            .windows()
+            .iter()
+            .find(|window| window.id == app.id)
+            .map(|window| window.index)
+            .ok_or(ClientError::AppNotFound {
+                app_id: Some(app.app_id.clone()),
+                index: None,
+                id: None,
+            })?;
+
+        let mut requests = Vec::new();
+        if let Some(workspace) = &rule.workspace {
+            let output_index = rule
+                .output
+                .as_ref()
+                .map(|output| self.resolve_output(output))
+                .transpose()?;
+            let result = self.move_window(MoveParams {
+                index: Some(index),
+                workspace: workspace.clone(),
+                workspace_group: rule.workspace_group,
+                output_index,
+                ..Default::default()
+            })?;
+            requests.extend(result.requests);
+        }
+        if rule.changes_state() {
+            let result = self.set_state(StateParams {
+                index: Some(index),
+                maximize: rule.maximize,
+                minimize: rule.minimize,
+                fullscreen: rule.fullscreen,
+                sticky: rule.sticky,
+                ..Default::default()
+            })?;
+            requests.extend(result.requests);
+        }
+        if rule.close {
+            let result = self.close(CloseParams {
+                index: Some(index),
+                ..Default::default()
+            })?;
+            requests.extend(result.requests);
+        }
+        Ok(requests)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(app_id: &str, title: &str, state: Vec<State>) -> JsonApp {
+        JsonApp {
+            title: title.into(),
+            state,
+            ..crate::test_app(1, app_id)
+        }
+    }
+
+    const RULES: &str = r#"
+        [[rules]]
+        name = "browser"
+        match = { app_id = "FIREFOX" }
+        workspace = 2
+
+        [[rules]]
+        match = { title = "video", state = ["fullscreen"] }
+        sticky = true
+
+        [[rules]]
+        maximize = true
+    "#;
+
+    fn matching(rules: &Rules, app: &JsonApp) -> Vec<usize> {
+        rules.matching(app).map(|(index, _)| index).collect()
+    }
+
+    #[test]
+    fn first_mode_takes_the_first_match() {
+        let rules: Rules = RULES.parse().unwrap();
+        assert_eq!(
+            matching(&rules, &app("org.mozilla.firefox", "", vec![])),
+            [0]
+        );
+        // The rule without conditions matches every window
+        assert_eq!(matching(&rules, &app("kitty", "", vec![])), [2]);
+    }
+
+    #[test]
+    fn all_mode_takes_every_match_in_order() {
+        let rules: Rules = format!("mode = \"all\"\n{RULES}").parse().unwrap();
+        let video = app("firefox", "A Video", vec![State::Fullscreen]);
+        assert_eq!(matching(&rules, &video), [0, 1, 2]);
+        // Every state of the match is required
+        let windowed = app("firefox", "A Video", vec![]);
+        assert_eq!(matching(&rules, &windowed), [0, 2]);
+    }
+
+    #[test]
+    fn output_needs_a_workspace() {
+        let rules = "[[rules]]\noutput = \"DP-1\"".parse::<Rules>();
+        assert!(matches!(rules, Err(ClientError::InvalidParams(_))));
+        let unknown = "[[rules]]\nworkspac = 1".parse::<Rules>();
+        assert!(matches!(unknown, Err(ClientError::InvalidParams(_))));
+    }
+}
@@ -0,0 +1,168 @@
+//! Machine-readable description of the JSON-RPC API, generated from the
+//! param and result types so it cannot drift from the implementation.
+
+use schemars::generate::SchemaSettings;
+use schemars::{JsonSchema, SchemaGenerator};
+use serde_json::{Value, json};
+
+use crate::ClientError;
+use crate::JsonInfo;
+use crate::client::{
+    ActivateParams, ActivateResult, ActivateWsParams, ActivateWsResult, CloseParams, CloseResult,
+    InfoParams, MoveParams, MoveResult, StateParams, StateResult, VersionResult,
+};
+use crate::events::{
+    Notification, SubscribeParams, SubscribeResult, UnsubscribeParams, UnsubscribeResult,
+};
+use crate::history::{HistoryParams, HistoryResult};
+use crate::operation::{BatchParams, BatchResult};
+
+/// Names accepted by [`schema`].
+pub const SCHEMAS: &[&str] = &["info", "state_change", "openrpc"];
+
+/// The OpenRPC document returned by `rpc.discover`.
+pub fn openrpc() -> Value {
+    let mut generator = SchemaSettings::draft2020_12()
+        .with(|s| {
+            s.definitions_path = "/components/schemas".into();
+            s.meta_schema = None;
+        })
+        .into_generator();
+
+    let methods = vec![
+        method::<InfoParams, JsonInfo>(&mut generator, "info"),
+        method::<MoveParams, MoveResult>(&mut generator, "move"),
+        method::<ActivateParams, ActivateResult>(&mut generator, "activate"),
+        method::<ActivateWsParams, ActivateWsResult>(&mut generator, "ws_activate"),
+        method::<StateParams, StateResult>(&mut generator, "state"),
+        method::<CloseParams, CloseResult>(&mut generator, "close"),
+        method::<HistoryParams, HistoryResult>(&mut generator, "history"),
+        method::<BatchParams, BatchResult>(&mut generator, "batch"),
+        method::<SubscribeParams, SubscribeResult>(&mut generator, "subscribe"),
+        method::<UnsubscribeParams, UnsubscribeResult>(&mut generator, "unsubscribe"),
+        method::<(), String>(&mut generator, "ping"),
+        method::<(), VersionResult>(&mut generator, "version"),
+        method::<(), ()>(&mut generator, "shutdown"),
+    ];
+    let notification = generator.subschema_for::<Notification>();
+
+    json!({
+        "openrpc": "1.3.2",
+        "info": {
+            "title": "cos-cli",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "methods": methods,
+        "components": {
+            "schemas": generator.take_definitions(true),
+            "errors": errors(),
+        },
+        "x-notifications": [{
+            "name": "state_change",
+            "params": notification,
+        }],
+    })
+}
+
+/// The JSON Schema named `name`: `info` for the `info` result,
+/// `state_change` for notifications or `openrpc` for the whole API.
+pub fn schema(name: &str) -> Option<Value> {
+    let generator = SchemaSettings::draft2020_12().into_generator();
+    match name {
+        "info" => Some(generator.into_root_schema_for::<JsonInfo>().to_value()),
+        "state_change" => Some(generator.into_root_schema_for::<Notification>().to_value()),
+        "openrpc" => Some(openrpc()),
+        _ => None,
+    }
+}
+
+/// One-line description of a JSON-RPC method.
+pub fn summary(method: &str) -> Option<&'static str> {
+    Some(match method {
+        "info" => "Apps, workspaces, outputs and seats",
+        "move" => "Move windows to a workspace. Exactly one of app_id or index",
+        "activate" => "Focus a window. Exactly one of index, previous or mru",
+        "ws_activate" => "Switch to a workspace",
+        "state" => "Change window states. Exactly one of app_id or index",
+        "close" => "Close windows. Exactly one of app_id or index",
+        "history" => "Open windows, most recently focused first",
+        "batch" => "Run several operations against one snapshot with a single flush",
+        "subscribe" => "Receive state_change notifications for the given events",
+        "unsubscribe" => "Remove one subscription, or all of them",
+        "ping" => "Returns \"pong\"",
+        "version" => "cos-cli version and the negotiated protocol versions",
+        "shutdown" => "Stop the server after answering the pending requests",
+        _ => return None,
+    })
+}
+
+/// The JSON Schema of the params of the operation `method`, as expected by
+/// [`Operation::parse`](crate::Operation::parse).
+pub fn params(method: &str) -> Option<Value> {
+    let generator = SchemaSettings::draft2020_12().into_generator();
+    let schema = match method {
+        "info" => generator.into_root_schema_for::<InfoParams>(),
+        "move" => generator.into_root_schema_for::<MoveParams>(),
+        "activate" => generator.into_root_schema_for::<ActivateParams>(),
+        "ws_activate" => generator.into_root_schema_for::<ActivateWsParams>(),
+        "state" => generator.into_root_schema_for::<StateParams>(),
+        "close" => generator.into_root_schema_for::<CloseParams>(),
+        "history" => generator.into_root_schema_for::<HistoryParams>(),
+        "batch" => generator.into_root_schema_for::<BatchParams>(),
+        _ => return None,
+    };
+    Some(schema.to_value())
+}
+
+/// An OpenRPC method object. Params are passed by name, one content
+/// descriptor per field of `P`.
+fn method<P: JsonSchema, R: JsonSchema>(generator: &mut SchemaGenerator, name: &str) -> Value {
+    let params = P::json_schema(generator);
+    let required = params
+        .get("required")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let params = params
+        .get("properties")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+        .map(|(field, schema)| {
+            let mut descriptor = json!({
+                "name": field,
+                "required": required.contains(&Value::from(field.as_str())),
+                "schema": schema,
+            });
+            if let Some(description) = schema.get("description") {
+                descriptor["description"] = description.clone();
+            }
+            descriptor
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "name": name,
+        "summary": summary(name),
+        "paramStructure": "by-name",
+        "params": params,
+        "result": {
+            "name": format!("{name}_result"),
+            "schema": generator.subschema_for::<R>(),
+        },
+    })
+}
+
+fn errors() -> Value {
+    json!({
+        "app_not_found": { "code": ClientError::APP_NOT_FOUND, "message": "App not found" },
+        "workspace_not_found": { "code": ClientError::WORKSPACE_NOT_FOUND, "message": "Workspace not found" },
+        "output_not_found": { "code": ClientError::OUTPUT_NOT_FOUND, "message": "Output not found" },
+        "seat_not_found": { "code": ClientError::SEAT_NOT_FOUND, "message": "Seat not found" },
+        "capability_missing": { "code": ClientError::CAPABILITY_MISSING, "message": "Protocol not supported by the compositor" },
+        "timeout": { "code": ClientError::TIMEOUT, "message": "App not found in time" },
+        "disconnected": { "code": ClientError::DISCONNECTED, "message": "Compositor connection lost" },
+        "invalid_params": { "code": ClientError::INVALID_PARAMS, "message": "Invalid params" },
+        "internal": { "code": ClientError::INTERNAL, "message": "Internal error" },
+    })
+}
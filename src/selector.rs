@@ -0,0 +1,227 @@
+//! Window selectors of `watch --filter` and `wait-for --match`: conditions
+//! separated by commas that must all hold, e.g. `app_id=firefox,title=github`
+//! or `workspace=3`. A condition without a key is an app_id or alias.
+//!
+//! | Key | Matches |
+//! |---|---|
+//! | `app_id`, `title` | partial, case-insensitive |
+//! | `id` | the protocol id from `info --json` |
+//! | `workspace` | index, label from the config or name |
+//! | `workspace_group` | index |
+//! | `output` | name |
+//! | `state` | a state the window has, repeatable |
+
+use std::str::FromStr;
+
+use crate::config::{Config, Target};
+use crate::{ClientError, JsonApp, State};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selector {
+    pub app_id: Option<String>,
+    pub title: Option<String>,
+    pub id: Option<u32>,
+    pub workspace: Option<Target>,
+    pub workspace_group: Option<usize>,
+    pub output: Option<String>,
+    pub state: Vec<State>,
+}
+
+impl FromStr for Selector {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |message: String| ClientError::InvalidParams(message);
+        let mut selector = Selector::default();
+        for condition in s.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+            let (key, value) = condition.split_once('=').unwrap_or(("app_id", condition));
+            let value = value.trim();
+            match key.trim() {
+                "app_id" => selector.app_id = Some(value.to_string()),
+                "title" => selector.title = Some(value.to_string()),
+                "id" => {
+                    selector.id = Some(
+                        value
+                            .parse()
+                            .map_err(|_| invalid(format!("Invalid id: {value}")))?,
+                    )
+                }
+                "workspace" => selector.workspace = value.parse().ok(),
+                "workspace_group" => {
+                    selector.workspace_group = Some(
+                        value
+                            .parse()
+                            .map_err(|_| invalid(format!("Invalid workspace_group: {value}")))?,
+                    )
+                }
+                "output" => selector.output = Some(value.to_string()),
+                "state" => selector.state.push(
+                    serde_json::from_value(value.into())
+                        .map_err(|_| invalid(format!("Unknown state: {value}")))?,
+                ),
+                key => {
+                    return Err(invalid(format!(
+                        "Unknown selector key: {key}. Expected one of: app_id, title, id, \
+                         workspace, workspace_group, output, state"
+                    )));
+                }
+            }
+        }
+        Ok(selector)
+    }
+}
+
+impl Selector {
+    /// Resolves an alias in `app_id` and a label in `workspace` through
+    /// `config`.
+    pub fn with_config(mut self, config: &Config) -> Self {
+        if let Some(alias) = self.app_id.as_ref().and_then(|a| config.aliases.get(a)) {
+            self.title = self.title.or(alias.title().map(str::to_string));
+            self.app_id = alias.app_id().map(str::to_string);
+        }
+        if let Some(Target::Name(name)) = &self.workspace
+            && let Some(label) = config.workspaces.get(name)
+        {
+            self.workspace_group = self.workspace_group.or(label.workspace_group());
+            self.workspace = Some(Target::Index(label.workspace()));
+        }
+        self
+    }
+
+    pub fn matches(&self, app: &JsonApp) -> bool {
+        self.app_id
+            .as_deref()
+            .is_none_or(|pattern| contains(&app.app_id, pattern))
+            && self
+                .title
+                .as_deref()
+                .is_none_or(|pattern| contains(&app.title, pattern))
+            && self.id.is_none_or(|id| app.id == id)
+            && (self.workspace.is_none() && self.workspace_group.is_none()
+                || app.workspaces.iter().any(|w| {
+                    self.workspace_group
+                        .is_none_or(|group| w.group_index == group)
+                        && self
+                            .workspace
+                            .as_ref()
+                            .is_none_or(|workspace| match workspace {
+                                Target::Index(index) => w.index == *index,
+                                Target::Name(name) => &w.workspace == name,
+                            })
+                }))
+            && self
+                .output
+                .as_ref()
+                .is_none_or(|output| app.outputs.iter().any(|o| &o.name == output))
+            && self.state.iter().all(|state| app.state.contains(state))
+    }
+}
+
+/// The partial, case-insensitive match of selectors, rules and profiles.
+pub(crate) fn contains(value: &str, pattern: &str) -> bool {
+    value.to_lowercase().contains(&pattern.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{JsonOutputRef, JsonWorkspaceRef};
+
+    fn app() -> JsonApp {
+        JsonApp {
+            title: "GitHub - Mozilla Firefox".into(),
+            state: vec![State::Maximized],
+            outputs: vec![JsonOutputRef {
+                index: 0,
+                name: "DP-1".into(),
+            }],
+            workspaces: vec![JsonWorkspaceRef {
+                group_index: 1,
+                index: 2,
+                workspace: "Web".into(),
+            }],
+            ..crate::test_app(7, "org.mozilla.firefox")
+        }
+    }
+
+    #[test]
+    fn parses_conditions() {
+        let selector: Selector = " firefox , title=hub,id=7,workspace=2,state=maximized"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            selector,
+            Selector {
+                app_id: Some("firefox".into()),
+                title: Some("hub".into()),
+                id: Some(7),
+                workspace: Some(Target::Index(2)),
+                state: vec![State::Maximized],
+                ..Default::default()
+            }
+        );
+        assert_eq!("".parse::<Selector>().unwrap(), Selector::default());
+        for invalid in ["id=x", "workspace_group=-1", "state=tiny", "name=firefox"] {
+            assert!(
+                matches!(
+                    invalid.parse::<Selector>(),
+                    Err(ClientError::InvalidParams(_))
+                ),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_every_condition() {
+        let matching = [
+            "",
+            "FireFox",
+            "title=github,id=7",
+            "workspace=2,workspace_group=1",
+            "workspace=Web",
+            "output=DP-1,state=maximized",
+        ];
+        for selector in matching {
+            assert!(
+                selector.parse::<Selector>().unwrap().matches(&app()),
+                "{selector}"
+            );
+        }
+        let other = [
+            "kitty",
+            "firefox,title=gitlab",
+            "id=8",
+            "workspace=2,workspace_group=0",
+            "output=DP",
+            "state=maximized,state=sticky",
+        ];
+        for selector in other {
+            assert!(
+                !selector.parse::<Selector>().unwrap().matches(&app()),
+                "{selector}"
+            );
+        }
+    }
+
+    #[test]
+    fn resolves_aliases_and_labels() {
+        let config: Config = r#"
+            [aliases]
+            term = { app_id = "wezterm", title = "main" }
+
+            [workspaces]
+            code = { workspace = 1, workspace_group = 0 }
+        "#
+        .parse()
+        .unwrap();
+        let selector = "term,workspace=code"
+            .parse::<Selector>()
+            .unwrap()
+            .with_config(&config);
+        assert_eq!(selector.app_id.as_deref(), Some("wezterm"));
+        assert_eq!(selector.title.as_deref(), Some("main"));
+        assert_eq!(selector.workspace, Some(Target::Index(1)));
+        assert_eq!(selector.workspace_group, Some(0));
+    }
+}
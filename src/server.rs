@@ -1,15 +1,36 @@
 use futures::FutureExt;
+use futures::future::LocalBoxFuture;
 use jsonrpc_stdio_server::jsonrpc_core::{Error, ErrorCode, IoHandler, Params};
-use serde::Deserialize;
+use serde::Serialize;
 use std::error::Error as StdError;
-use std::sync::Arc;
+use std::fmt;
+use std::future::Future;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use tokio::sync::mpsc::{Sender, channel};
-use tokio::sync::oneshot;
-
-use crate::{App, AppState, JsonInfo};
-
-use wayland_client::{Connection, EventQueue};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::{Sender, channel, error::TrySendError};
+use tokio::sync::{oneshot, watch};
+
+use cos_cli::client::VersionResult;
+use cos_cli::confirm::Confirmation;
+use cos_cli::events::{
+    Notification, StateChange, SubscribeParams, SubscribeResult, Subscriptions, UnsubscribeParams,
+    UnsubscribeResult,
+};
+use cos_cli::operation::{Started, Waiting};
+use cos_cli::{ClientError, CosmicClient, Operation};
+
+use crate::ServeArgs;
+use crate::hooks::HookRunner;
+use crate::hotplug::Hotplug;
 
 /// Wraps a `JoinHandle` and checks for panics when dropped.
 struct TaskGuard {
@@ -35,117 +56,39 @@ impl Drop for TaskGuard {
     }
 }
 
-// ---------------------------------------------------------------------------
-// Parameter types
-// ---------------------------------------------------------------------------
-
-#[derive(Debug, Deserialize)]
-pub struct MoveParams {
-    #[serde(default)]
-    pub app_id: Option<String>,
-    #[serde(default)]
-    pub index: Option<usize>,
-    pub workspace: usize,
-    #[serde(default)]
-    pub workspace_group: Option<usize>,
-    #[serde(default)]
-    pub output_index: Option<usize>,
-    #[serde(default)]
-    pub wait: Option<u64>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct ActivateParams {
-    pub index: usize,
-    #[serde(default)]
-    pub seat: Option<usize>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct StateParams {
-    #[serde(default)]
-    pub app_id: Option<String>,
-    #[serde(default)]
-    pub index: Option<usize>,
-    #[serde(default)]
-    pub wait: Option<u64>,
-    #[serde(default)]
-    pub maximize: bool,
-    #[serde(default)]
-    pub unmaximize: bool,
-    #[serde(default)]
-    pub minimize: bool,
-    #[serde(default)]
-    pub unminimize: bool,
-    #[serde(default)]
-    pub fullscreen: bool,
-    #[serde(default)]
-    pub unfullscreen: bool,
-    #[serde(default)]
-    pub sticky: bool,
-    #[serde(default)]
-    pub unsticky: bool,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct ActivateWsParams {
-    pub workspace: usize,
-    #[serde(default)]
-    pub workspace_group: Option<usize>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct CloseParams {
-    #[serde(default)]
-    pub app_id: Option<String>,
-    #[serde(default)]
-    pub index: Option<usize>,
-}
-
-pub enum BackendRequestParams {
-    GetInfo,
-    Move(MoveParams),
-    Activate(ActivateParams),
-    ActivateWs(ActivateWsParams),
-    State(StateParams),
-    Close(CloseParams),
-}
-
 pub struct BackendRequest {
     response_tx: oneshot::Sender<BackendResponse>,
-    params: BackendRequestParams,
+    operation: Operation,
 }
 
 impl BackendRequest {
-    fn request(params: BackendRequestParams) -> (oneshot::Receiver<BackendResponse>, Self) {
+    fn request(operation: Operation) -> (oneshot::Receiver<BackendResponse>, Self) {
         let (response_tx, rx) = oneshot::channel();
         (
             rx,
             Self {
-                params,
+                operation,
                 response_tx,
             },
         )
     }
 }
 
-#[derive(serde::Serialize)]
-#[serde(untagged)]
-pub enum BackendResponse {
-    Info(JsonInfo),
-    Ok(String),
-    Err(String),
-}
+pub type BackendResponse = Result<serde_json::Value, ClientError>;
+
+// ---------------------------------------------------------------------------
+// Errors
+// ---------------------------------------------------------------------------
 
-fn internal_error(message: &str) -> Error {
+pub(crate) fn rpc_error(e: ClientError) -> Error {
     Error {
-        code: ErrorCode::InternalError,
-        message: message.to_string(),
-        data: None,
+        code: ErrorCode::from(e.code()),
+        message: e.to_string(),
+        data: e.data(),
     }
 }
 
-fn invalid_params(message: &str) -> Error {
+pub(crate) fn invalid_params(message: &str) -> Error {
     Error {
         code: ErrorCode::InvalidParams,
         message: message.to_string(),
@@ -157,562 +100,610 @@ fn invalid_params(message: &str) -> Error {
 // Synchronous event-loop thread
 // ---------------------------------------------------------------------------
 
+/// How often wayland events are dispatched while confirmations are pending.
+const CONFIRM_POLL: Duration = Duration::from_millis(50);
+
 /// Owned resources for the dedicated wayland event-loop thread.
 struct WaylandThread {
-    event_queue: EventQueue<AppState>,
-    app_state: AppState,
-    connection: Connection,
+    client: CosmicClient,
+    hotplug: Hotplug,
     request_rx: std::sync::mpsc::Receiver<BackendRequest>,
+    shutdown: Arc<watch::Sender<bool>>,
+    /// Operations waiting for the compositor to reflect their `confirm`
+    confirming: Vec<(Confirmation, oneshot::Sender<BackendResponse>)>,
+    /// Operations waiting for their app to appear
+    waiting: Vec<(Waiting, oneshot::Sender<BackendResponse>)>,
 }
 
 impl WaylandThread {
-    fn run(mut self) {
+    /// Runs until shutdown is requested or the compositor connection is lost.
+    /// Requests still queued at that point are answered with an error.
+    fn run(mut self) -> Result<(), ClientError> {
         let sleep = std::time::Duration::from_millis(300);
-        loop {
+        let mut result = Ok(());
+        while !*self.shutdown.borrow() {
             // Process any pending commands first (non-blocking check)
             while let Ok(request) = self.request_rx.try_recv() {
                 self.process(request);
             }
 
-            // Blocking roundtrip – safe here because this is a dedicated OS thread
-            let _ = self.event_queue.roundtrip(&mut self.app_state);
-            let _ = self.connection.flush();
+            // Blocking roundtrip – safe here because this is a dedicated OS
+            // thread. Everything this dispatch cycle changed is published as
+            // one batch.
+            if let Err(e) = self.client.dispatch() {
+                tracing::error!("{e}");
+                result = Err(e);
+                break;
+            }
+            self.hotplug.poll(&mut self.client);
+            self.poll_waiting();
+            self.poll_confirmations();
+
+            // Wake up as soon as a request arrives, but keep dispatching
+            // wayland events at least every `sleep`, more often while
+            // operations wait for the compositor
+            let sleep = match self.confirming.is_empty() && self.waiting.is_empty() {
+                true => sleep,
+                false => CONFIRM_POLL,
+            };
+            match self.request_rx.recv_timeout(sleep) {
+                Ok(request) => self.process(request),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        self.shutdown.send_replace(true);
 
-            thread::sleep(sleep);
+        let reason = match &result {
+            Ok(()) => "Server is shutting down".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let queued = self
+            .request_rx
+            .try_iter()
+            .map(|request| request.response_tx);
+        let waiting = self.waiting.drain(..).map(|(_, response_tx)| response_tx);
+        for response_tx in queued.chain(waiting) {
+            let _ = response_tx.send(Err(ClientError::Disconnected {
+                reason: reason.clone(),
+            }));
+        }
+        // Their requests were sent, so they get the outcomes known so far
+        for (confirmation, response_tx) in self.confirming.drain(..) {
+            let _ = response_tx.send(to_response(Ok(confirmation.finish())));
         }
+        result
     }
 
+    /// Runs the operation, or starts waiting for its app or confirmation
+    /// without blocking the other requests.
     fn process(&mut self, request: BackendRequest) {
-        use BackendRequestParams::*;
-        let response = match request.params {
-            GetInfo => BackendResponse::Info(JsonInfo::from(&self.app_state)),
-            Move(p) => match self.handle_move(p) {
-                Ok(msg) => BackendResponse::Ok(msg),
-                Err(e) => BackendResponse::Err(e.to_string()),
-            },
-            Activate(p) => match self.handle_activate(p) {
-                Ok(msg) => BackendResponse::Ok(msg),
-                Err(e) => BackendResponse::Err(e.to_string()),
-            },
-            ActivateWs(p) => match self.handle_activate_ws(p) {
-                Ok(msg) => BackendResponse::Ok(msg),
-                Err(e) => BackendResponse::Err(e.to_string()),
-            },
-            State(p) => match self.handle_state(p) {
-                Ok(msg) => BackendResponse::Ok(msg),
-                Err(e) => BackendResponse::Err(e.to_string()),
-            },
-            Close(p) => match self.handle_close(p) {
-                Ok(msg) => BackendResponse::Ok(msg),
-                Err(e) => BackendResponse::Err(e.to_string()),
-            },
-        };
-        let _ = request.response_tx.send(response);
+        let started = self.client.start(request.operation);
+        self.answer(started, request.response_tx);
     }
 
-    fn handle_move(&mut self, params: MoveParams) -> Result<String, Box<dyn StdError>> {
-        let apps = self.find_apps(params.app_id.clone(), params.index, params.wait)?;
-
-        let manager = self
-            .app_state
-            .cosmic_toplevel_manager
-            .as_ref()
-            .ok_or_else(|| {
-                "Compositor does not support workspace management protocol.".to_string()
-            })?;
-
-        let workspace_index = if let Some(group_index) = params.workspace_group {
-            let group = self
-                .app_state
-                .workspace_groups
-                .get(group_index)
-                .ok_or_else(|| format!("Workspace group not found: {}", group_index))?;
-            if params.workspace >= group.workspaces.len() {
-                return Err(format!(
-                    "Workspace index {} out of range (group has {} workspaces)",
-                    params.workspace,
-                    group.workspaces.len()
-                )
-                .into());
+    fn answer(
+        &mut self,
+        started: Result<Started, ClientError>,
+        response_tx: oneshot::Sender<BackendResponse>,
+    ) {
+        match started {
+            Ok(Started::Waiting(waiting)) => self.waiting.push((waiting, response_tx)),
+            Ok(Started::Confirming(confirmation)) => {
+                self.confirming.push((confirmation, response_tx));
             }
-            (group_index, params.workspace)
-        } else {
-            let mut found = None;
-            for (gi, group) in self.app_state.workspace_groups.iter().enumerate() {
-                for (wi, _) in group.workspaces.iter().enumerate() {
-                    if wi == params.workspace {
-                        found = Some((gi, wi));
-                        break;
-                    }
-                }
-                if found.is_some() {
-                    break;
-                }
+            Ok(Started::Done(output)) => {
+                let _ = response_tx.send(to_response(Ok(output)));
             }
-            found.ok_or_else(|| format!("Workspace not found: {}", params.workspace))?
-        };
-
-        let (group_index, idx) = workspace_index;
-        let workspace_handle = &self.app_state.workspace_groups[group_index].workspaces[idx];
-        let Some(workspace) = self
-            .app_state
-            .handle_map
-            .workspace_handle
-            .get(workspace_handle)
-        else {
-            return Err("Workspace handle not found in handle map".into());
-        };
-
-        let output = if let Some(index) = params.output_index {
-            let oid = self
-                .app_state
-                .outputs
-                .get(index)
-                .ok_or_else(|| format!("Output index not found: {}", index))?;
-            self.app_state
-                .handle_map
-                .output
-                .get(oid)
-                .map(|h| h.handle.clone())
-                .ok_or("Output handle not found in handle map")?
-        } else {
-            if self.app_state.outputs.is_empty() {
-                return Err("No outputs found.".to_string().into());
+            Err(e) => {
+                let _ = response_tx.send(Err(e));
             }
-            let oid = &self.app_state.outputs[0];
-            self.app_state
-                .handle_map
-                .output
-                .get(oid)
-                .map(|h| h.handle.clone())
-                .ok_or("Output handle not found in handle map")?
-        };
-
-        for app in &apps {
-            manager.move_to_ext_workspace(&app.handle, &workspace.handle, &output);
         }
+    }
 
-        self.connection.flush()?;
+    /// Runs the operations whose app appeared or whose `wait` is over.
+    fn poll_waiting(&mut self) {
+        for (waiting, response_tx) in std::mem::take(&mut self.waiting) {
+            match waiting.poll(&mut self.client) {
+                Some(started) => self.answer(started, response_tx),
+                None => self.waiting.push((waiting, response_tx)),
+            }
+        }
+    }
 
-        let app_desc = params.app_id.clone().unwrap_or_else(|| "app".to_string());
-        Ok(format!(
-            "Moved {} to workspace {}",
-            app_desc, params.workspace
-        ))
+    /// Answers the confirmations the last dispatch settled or timed out.
+    fn poll_confirmations(&mut self) {
+        for (mut confirmation, response_tx) in std::mem::take(&mut self.confirming) {
+            if confirmation.poll(&self.client) {
+                let _ = response_tx.send(to_response(Ok(confirmation.finish())));
+            } else {
+                self.confirming.push((confirmation, response_tx));
+            }
+        }
     }
+}
 
-    fn handle_activate(&mut self, params: ActivateParams) -> Result<String, Box<dyn StdError>> {
-        let manager = self
-            .app_state
-            .cosmic_toplevel_manager
-            .as_ref()
-            .ok_or_else(|| {
-                "Compositor does not support toplevel management protocol.".to_string()
-            })?;
-
-        let Some(app) = self.app_state.apps.get(params.index) else {
-            return Err(format!("App index not found: {}", params.index).into());
-        };
+fn to_response<T: Serialize>(result: Result<T, ClientError>) -> BackendResponse {
+    result.and_then(|value| {
+        serde_json::to_value(value).map_err(|e| ClientError::Internal(e.to_string()))
+    })
+}
 
-        let seat = if let Some(seat_index) = params.seat {
-            let sid = self
-                .app_state
-                .seats
-                .get(seat_index)
-                .ok_or_else(|| format!("Seat index not found: {}", seat_index))?;
-            self.app_state
-                .handle_map
-                .seat
-                .get(sid)
-                .map(|h| &h.handle)
-                .ok_or("Seat handle not found in handle map")?
-        } else {
-            let sid = self
-                .app_state
-                .seats
-                .first()
-                .ok_or_else(|| "No seats found.".to_string())?;
-            self.app_state
-                .handle_map
-                .seat
-                .get(sid)
-                .map(|h| &h.handle)
-                .ok_or("Seat handle not found in handle map")?
-        };
+// ---------------------------------------------------------------------------
+// Public ServerHandler & run()
+// ---------------------------------------------------------------------------
 
-        manager.activate(&app.handle, seat);
-        self.connection.flush()?;
+pub(crate) struct ServerHandler {
+    tx: Sender<BackendRequest>,
+    /// Result of `version`, taken before the client moved to its thread
+    version: serde_json::Value,
+    shutdown: Arc<watch::Sender<bool>>,
+}
 
-        Ok(format!("Activated app at index {}", params.index))
+impl ServerHandler {
+    fn new(
+        tx: Sender<BackendRequest>,
+        version: VersionResult,
+        shutdown: Arc<watch::Sender<bool>>,
+    ) -> Self {
+        Self {
+            tx,
+            version: serde_json::to_value(version).unwrap_or_default(),
+            shutdown,
+        }
     }
 
-    fn handle_activate_ws(
-        &mut self,
-        params: ActivateWsParams,
-    ) -> Result<String, Box<dyn StdError>> {
-        let Some(manager) = &self.app_state.workspace_manager else {
-            return Err("Compositor does not support workspace management protocol.".into());
-        };
-        let workspace_index = if let Some(group_index) = params.workspace_group {
-            let group = self
-                .app_state
-                .workspace_groups
-                .get(group_index)
-                .ok_or_else(|| format!("Workspace group not found: {}", group_index))?;
-            if params.workspace >= group.workspaces.len() {
-                return Err(format!(
-                    "Workspace index {} out of range (group has {} workspaces)",
-                    params.workspace,
-                    group.workspaces.len()
-                )
-                .into());
-            }
-            (group_index, params.workspace)
-        } else {
-            let mut found = None;
-            for (gi, group) in self.app_state.workspace_groups.iter().enumerate() {
-                for (wi, _) in group.workspaces.iter().enumerate() {
-                    if wi == params.workspace {
-                        found = Some((gi, wi));
-                        break;
-                    }
-                }
-                if found.is_some() {
-                    break;
-                }
-            }
-            found.ok_or_else(|| format!("Workspace not found: {}", params.workspace))?
+    /// A handler without a wayland thread, whose operations fail with
+    /// `Disconnected`.
+    #[cfg(test)]
+    pub(crate) fn disconnected() -> Arc<Self> {
+        let version = VersionResult {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            protocols: Vec::new(),
         };
+        Arc::new(Self::new(
+            channel(1).0,
+            version,
+            Arc::new(watch::channel(false).0),
+        ))
+    }
 
-        let (group_index, idx) = workspace_index;
-        let workspace_handle = &self.app_state.workspace_groups[group_index].workspaces[idx];
-        let Some(ws) = self
-            .app_state
-            .handle_map
-            .workspace_handle
-            .get(workspace_handle)
-        else {
-            return Err("Workspace handle not found in handle map".into());
-        };
+    pub(crate) async fn handle_request(
+        self: Arc<Self>,
+        operation: Operation,
+    ) -> Result<serde_json::Value, Error> {
+        self.execute(operation).await.map_err(rpc_error)
+    }
 
-        ws.handle.activate();
-        manager.commit();
-        self.connection.flush()?;
+    /// Result of the `version` method.
+    pub(crate) fn version(&self) -> &serde_json::Value {
+        &self.version
+    }
 
-        Ok(format!("Activated workspace {}", params.workspace))
+    /// Stops the server after the pending requests are answered.
+    pub(crate) fn request_shutdown(&self) {
+        self.shutdown.send_replace(true);
     }
 
-    fn handle_state(&mut self, params: StateParams) -> Result<String, Box<dyn StdError>> {
-        let apps = self.find_apps(params.app_id.clone(), params.index, params.wait)?;
+    /// Runs `operation` on the wayland thread.
+    pub(crate) async fn execute(&self, operation: Operation) -> BackendResponse {
+        let disconnected = |e: &dyn fmt::Display| ClientError::Disconnected {
+            reason: e.to_string(),
+        };
+        let (response_tx, request) = BackendRequest::request(operation);
+        self.tx.send(request).await.map_err(|e| disconnected(&e))?;
+        response_tx.await.map_err(|e| disconnected(&e))?
+    }
+}
 
-        let manager = self
-            .app_state
-            .cosmic_toplevel_manager
-            .as_ref()
-            .ok_or_else(|| {
-                "Compositor does not support toplevel management protocol.".to_string()
-            })?;
+/// Messages queued for one client. A client that lets its queue fill up
+/// misses notifications and is disconnected when a response does not fit.
+pub(crate) const OUTBOUND_QUEUE: usize = 256;
+
+/// Queues `response` for a client, or aborts its `writer`, which closes the
+/// connection, when the client does not read what it was sent.
+pub(crate) fn queue_response(
+    out: &Sender<String>,
+    response: String,
+    writer: &tokio::task::AbortHandle,
+) {
+    if let Err(TrySendError::Full(_)) = out.try_send(response) {
+        tracing::warn!("Client does not read its messages, disconnecting");
+        writer.abort();
+    }
+}
 
-        let mut actions = Vec::new();
-        if params.maximize {
-            actions.push("maximize");
-        }
-        if params.unmaximize {
-            actions.push("unmaximize");
-        }
-        if params.minimize {
-            actions.push("minimize");
-        }
-        if params.unminimize {
-            actions.push("unminimize");
-        }
-        if params.fullscreen {
-            actions.push("fullscreen");
-        }
-        if params.unfullscreen {
-            actions.push("unfullscreen");
+/// Emits state_change JSON-RPC notifications for one client. Changes come
+/// from dispatch.rs calling AppState::notify() — i.e. actual state changes —
+/// and only those matching one of the client's subscriptions are sent.
+pub(crate) async fn publish_notifications(
+    mut events_rx: broadcast::Receiver<Arc<StateChange>>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    debounce: Duration,
+    out: Sender<String>,
+) {
+    let mut seq: u64 = 0;
+    // Set when batches were dropped, until a notification tells the client
+    let mut lagged = false;
+    loop {
+        let mut change = match events_rx.recv().await {
+            Ok(change) => (*change).clone(),
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!("Dropped {skipped} state changes");
+                lagged = true;
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        // Fold in every batch published within the debounce interval
+        let deadline = tokio::time::Instant::now() + debounce;
+        while let Ok(received) = tokio::time::timeout_at(deadline, events_rx.recv()).await {
+            match received {
+                Ok(later) => change.merge(&later),
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Dropped {skipped} state changes");
+                    lagged = true;
+                }
+                Err(RecvError::Closed) => break,
+            }
         }
-        if params.sticky {
-            actions.push("sticky");
+        let (events, subscribed) = {
+            let subscriptions = subscriptions.lock().unwrap();
+            (subscriptions.filter(&change), !subscriptions.is_empty())
+        };
+        // A lag is reported even without matching events so that the client
+        // can resync from `state`
+        lagged &= subscribed;
+        if events.is_empty() && !lagged {
+            continue;
         }
-        if params.unsticky {
-            actions.push("unsticky");
+        let notification = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "state_change",
+            "params": Notification {
+                seq: seq + 1,
+                lagged,
+                events,
+                state: change.state,
+            },
+        });
+        match out.try_send(notification.to_string()) {
+            Ok(()) => (seq, lagged) = (seq + 1, false),
+            // Dropped like the changes the broadcast channel drops
+            Err(TrySendError::Full(_)) => {
+                tracing::warn!("Client does not read its notifications, dropping one");
+                lagged = true;
+            }
+            Err(TrySendError::Closed(_)) => break,
         }
+    }
+}
 
-        let apps_count = apps.len();
-        for app in &apps {
-            if params.maximize {
-                manager.set_maximized(&app.handle);
-            }
-            if params.unmaximize {
-                manager.unset_maximized(&app.handle);
-            }
-            if params.minimize {
-                manager.set_minimized(&app.handle);
-            }
-            if params.unminimize {
-                manager.unset_minimized(&app.handle);
-            }
-            if params.fullscreen {
-                manager.set_fullscreen(&app.handle, None);
-            }
-            if params.unfullscreen {
-                manager.unset_fullscreen(&app.handle);
-            }
-            if params.sticky {
-                manager.set_sticky(&app.handle);
-            }
-            if params.unsticky {
-                manager.unset_sticky(&app.handle);
+/// Builds the JSON-RPC method table for one client.
+pub(crate) fn io_handler(
+    server_handler: Arc<ServerHandler>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+) -> IoHandler {
+    let mut io = IoHandler::new();
+    for &method in Operation::METHODS {
+        let handler = server_handler.clone();
+        io.add_method(method, move |params: Params| {
+            let handler = handler.clone();
+            async move {
+                let params = match params {
+                    Params::None => serde_json::Value::Null,
+                    params => params.parse().map_err(|e| invalid_params(&e.to_string()))?,
+                };
+                let operation = Operation::parse(method, params).map_err(rpc_error)?;
+                handler.handle_request(operation).await
             }
+            .boxed()
+        });
+    }
+
+    io.add_sync_method("subscribe", {
+        let subscriptions = subscriptions.clone();
+        move |params: Params| {
+            let p: SubscribeParams = match params {
+                Params::None => SubscribeParams::default(),
+                params => params.parse().map_err(|e| invalid_params(&e.to_string()))?,
+            };
+            let id = subscriptions.lock().unwrap().subscribe(p);
+            Ok(serde_json::to_value(SubscribeResult { subscription: id }).unwrap_or_default())
         }
+    });
 
-        self.connection.flush()?;
+    io.add_sync_method("unsubscribe", {
+        move |params: Params| {
+            let p: UnsubscribeParams = match params {
+                Params::None => UnsubscribeParams::default(),
+                params => params.parse().map_err(|e| invalid_params(&e.to_string()))?,
+            };
+            let removed = subscriptions.lock().unwrap().unsubscribe(p.subscription);
+            Ok(serde_json::to_value(UnsubscribeResult { removed }).unwrap_or_default())
+        }
+    });
 
-        Ok(format!(
-            "Set state for {} apps: {}",
-            apps_count,
-            actions.join(", ")
-        ))
-    }
+    io.add_sync_method("rpc.discover", |_: Params| Ok(cos_cli::schema::openrpc()));
 
-    fn handle_close(&mut self, params: CloseParams) -> Result<String, Box<dyn StdError>> {
-        let apps = self.find_apps(params.app_id.clone(), params.index, None)?;
+    io.add_sync_method("ping", |_: Params| Ok(serde_json::json!("pong")));
 
-        let manager = self
-            .app_state
-            .cosmic_toplevel_manager
-            .as_ref()
-            .ok_or_else(|| {
-                "Compositor does not support toplevel management protocol.".to_string()
-            })?;
+    io.add_sync_method("version", {
+        let handler = server_handler.clone();
+        move |_: Params| Ok(handler.version().clone())
+    });
 
-        for app in &apps {
-            manager.close(&app.handle);
-        }
+    io.add_sync_method("shutdown", move |_: Params| {
+        tracing::info!("Shutdown requested");
+        server_handler.request_shutdown();
+        Ok(serde_json::Value::Null)
+    });
 
-        self.connection.flush()?;
+    io
+}
 
-        let desc = params.app_id.clone().unwrap_or_else(|| "app".to_string());
-        Ok(format!("Close request sent for {}", desc))
+// ---------------------------------------------------------------------------
+// Unix socket transport
+// ---------------------------------------------------------------------------
+
+/// Removes the socket file when the server stops.
+struct SocketFile<'a>(&'a Path);
+
+impl Drop for SocketFile<'_> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(self.0);
     }
+}
 
-    fn find_apps(
-        &mut self,
-        app_id: Option<String>,
-        app_index: Option<usize>,
-        wait: Option<u64>,
-    ) -> Result<Vec<App>, Box<dyn StdError>> {
-        if let Some(index) = app_index {
-            if let Some(app) = self.app_state.apps.get(index) {
-                Ok(vec![app.clone()])
-            } else {
-                Err(format!("App index not found: {}", index).into())
-            }
-        } else if let Some(id) = app_id {
-            let sleep = std::time::Duration::from_millis(500);
-            let wait_dur = wait.map(std::time::Duration::from_secs);
-            let now = std::time::Instant::now();
-            let mut apps;
-            loop {
-                apps = self
-                    .app_state
-                    .apps
-                    .iter()
-                    .filter(|app| {
-                        app.app_id
-                            .as_ref()
-                            .map(|v| v.to_lowercase().contains(&id.to_lowercase()))
-                            .unwrap_or_default()
-                    })
-                    .cloned()
-                    .collect::<Vec<_>>();
-
-                if !apps.is_empty() {
-                    break;
-                }
+async fn serve_socket(
+    path: &Path,
+    server_handler: Arc<ServerHandler>,
+    events_tx: broadcast::Sender<Arc<StateChange>>,
+    debounce: Duration,
+) -> Result<(), Box<dyn StdError>> {
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(format!("Another server is listening on {}", path.display()).into());
+        }
+        // Left over from a server that did not shut down cleanly
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let _socket_file = SocketFile(path);
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    tracing::info!("Listening on {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tracing::debug!("Client connected");
+        tokio::task::spawn(serve_client(
+            stream,
+            server_handler.clone(),
+            events_tx.subscribe(),
+            debounce,
+        ));
+    }
+}
 
-                if let Some(wait) = wait_dur {
-                    if now.elapsed() > wait {
-                        break;
-                    }
-                    thread::sleep(sleep);
-                    let _ = self.event_queue.roundtrip(&mut self.app_state);
-                } else {
-                    break;
-                }
+/// Serves one socket client until it disconnects. Every client has its own
+/// subscriptions but shares the wayland thread with all other clients.
+async fn serve_client(
+    stream: UnixStream,
+    server_handler: Arc<ServerHandler>,
+    events_rx: broadcast::Receiver<Arc<StateChange>>,
+    debounce: Duration,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let subscriptions = Arc::new(Mutex::new(Subscriptions::default()));
+    let io = Arc::new(io_handler(server_handler, subscriptions.clone()));
+
+    let (out_tx, mut out_rx) = channel::<String>(OUTBOUND_QUEUE);
+    let notifier = tokio::task::spawn(publish_notifications(
+        events_rx,
+        subscriptions,
+        debounce,
+        out_tx.clone(),
+    ));
+    let writer = tokio::task::spawn(async move {
+        while let Some(mut line) = out_rx.recv().await {
+            line.push('\n');
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                break;
             }
+        }
+    })
+    .abort_handle();
 
-            if apps.is_empty() {
-                return Err(format!("App id not found: {}", id).into());
-            }
-            Ok(apps)
-        } else {
-            Err("Either app_id or index must be provided".into())
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if writer.is_finished() {
+            break;
         }
+        let io = io.clone();
+        let out_tx = out_tx.clone();
+        let writer = writer.clone();
+        // Handle requests concurrently so one waiting for its app or
+        // confirmation does not hold up the next
+        tokio::task::spawn(async move {
+            if let Some(response) = io.handle_request(&line).await {
+                queue_response(&out_tx, response, &writer);
+            }
+        });
     }
+    notifier.abort();
+    tracing::debug!("Client disconnected");
 }
 
 // ---------------------------------------------------------------------------
-// Public ServerHandler & run()
+// Entry point
 // ---------------------------------------------------------------------------
 
-struct ServerHandler {
-    tx: Sender<BackendRequest>,
+/// How long to keep serving after shutdown started, so that responses to
+/// the last requests still reach the clients.
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(200);
+
+async fn serve_stdio(
+    server_handler: Arc<ServerHandler>,
+    events_tx: broadcast::Sender<Arc<StateChange>>,
+    debounce: Duration,
+) -> Result<(), Box<dyn StdError>> {
+    let subscriptions = Arc::new(Mutex::new(Subscriptions::default()));
+    let (out_tx, mut out_rx) = channel::<String>(OUTBOUND_QUEUE);
+    let _notify_guard = TaskGuard::new(tokio::task::spawn(publish_notifications(
+        events_tx.subscribe(),
+        subscriptions.clone(),
+        debounce,
+        out_tx,
+    )));
+    let _stdout_guard = TaskGuard::new(tokio::task::spawn(async move {
+        while let Some(line) = out_rx.recv().await {
+            // Fails once the parent closed stdout
+            if let Err(e) = writeln!(std::io::stdout().lock(), "{line}") {
+                tracing::debug!("Stopped writing notifications: {e}");
+                break;
+            }
+        }
+    }));
+
+    let io = io_handler(server_handler, subscriptions);
+    let server = jsonrpc_stdio_server::ServerBuilder::new(io).build();
+    server.await;
+    tracing::info!("Stdin closed");
+    Ok(())
 }
 
-impl ServerHandler {
-    fn new(tx: Sender<BackendRequest>) -> Self {
-        Self { tx }
-    }
+/// Resolves with the reason once the server should stop.
+async fn stop_requested(shutdown: &watch::Sender<bool>) -> Result<&'static str, std::io::Error> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut stopped = shutdown.subscribe();
+    Ok(tokio::select! {
+        _ = stopped.wait_for(|stopped| *stopped) => "shutdown",
+        _ = sigterm.recv() => "SIGTERM",
+        _ = sigint.recv() => "SIGINT",
+    })
+}
 
-    async fn handle_request(
-        self: Arc<Self>,
-        request_params: BackendRequestParams,
-    ) -> Result<serde_json::Value, Error> {
-        let (response_tx, request) = BackendRequest::request(request_params);
-        self.tx
-            .send(request)
-            .await
-            .map_err(|e| internal_error(&e.to_string()))?;
-        let response = response_tx
-            .await
-            .map_err(|e| internal_error(&e.to_string()))?;
-        serde_json::to_value(response).map_err(|e| internal_error(&e.to_string()))
-    }
+/// Serves on any combination of a unix socket, HTTP and D-Bus, and on stdio
+/// when none of them is given, and runs the `[hooks]` of the config. Runs
+/// until stdin closes (stdio mode), `shutdown` is called, a signal arrives or
+/// the compositor connection is lost. Fails in the last case.
+pub async fn run(client: CosmicClient, args: ServeArgs) -> Result<(), Box<dyn StdError>> {
+    let runner = HookRunner::with_config(&client, Vec::new(), None, None);
+    run_backend(client, move |server_handler, events_tx| async move {
+        let _hooks_guard = (!runner.is_empty())
+            .then(|| TaskGuard::new(tokio::task::spawn(run_hooks(runner, events_tx.subscribe()))));
+        let mut transports: Vec<LocalBoxFuture<Result<(), Box<dyn StdError>>>> = Vec::new();
+        if let Some(path) = args.socket.as_deref() {
+            transports.push(
+                serve_socket(
+                    path,
+                    server_handler.clone(),
+                    events_tx.clone(),
+                    args.debounce,
+                )
+                .boxed_local(),
+            );
+        }
+        if let Some(http) = &args.http {
+            transports.push(
+                crate::http::serve(
+                    http,
+                    server_handler.clone(),
+                    events_tx.clone(),
+                    args.debounce,
+                )
+                .boxed_local(),
+            );
+        }
+        if args.dbus {
+            transports
+                .push(crate::dbus::serve(server_handler.clone(), events_tx.clone()).boxed_local());
+        }
+        if transports.is_empty() {
+            return serve_stdio(server_handler, events_tx, args.debounce).await;
+        }
+        futures::future::try_join_all(transports).await.map(|_| ())
+    })
+    .await
 }
 
-pub async fn run(
-    connection: Connection,
-    event_queue: EventQueue<AppState>,
-    mut app_state: AppState,
-) -> Result<(), Box<dyn StdError>> {
-    let mut io = IoHandler::new();
+/// Runs the hooks of every state change until the server stops.
+async fn run_hooks(mut runner: HookRunner, mut events: broadcast::Receiver<Arc<StateChange>>) {
+    loop {
+        match events.recv().await {
+            Ok(change) => change.events.iter().for_each(|event| runner.handle(event)),
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!("Dropped the hooks of {skipped} state changes")
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
 
+/// Starts the wayland thread, runs `serve` on top of it and shuts both down
+/// when `serve` returns or a stop is requested.
+pub(crate) async fn run_backend<S, F>(
+    mut client: CosmicClient,
+    serve: S,
+) -> Result<(), Box<dyn StdError>>
+where
+    S: FnOnce(Arc<ServerHandler>, broadcast::Sender<Arc<StateChange>>) -> F,
+    F: Future<Output = Result<(), Box<dyn StdError>>>,
+{
     let (request_tx, mut request_rx) = channel::<BackendRequest>(32);
     let (sync_tx, sync_rx) = std::sync::mpsc::channel::<BackendRequest>();
+    let shutdown = Arc::new(watch::channel(false).0);
 
     // ------------------------------------------------------------------
     // Dedicated OS thread for the synchronous wayland event loop
     // ------------------------------------------------------------------
-    let mut watch_rx = app_state.enable_notify();
+    let events_tx = client.event_sender();
+    let version = client.version();
     let wayland = WaylandThread {
-        event_queue,
-        app_state,
-        connection,
+        hotplug: Hotplug::new(&client),
+        client,
         request_rx: sync_rx,
+        shutdown: shutdown.clone(),
+        confirming: Vec::new(),
+        waiting: Vec::new(),
     };
-    let _wayland_thread = thread::spawn(move || wayland.run());
-
-    // Task that emits state_change JSON-RPC notifications only when
-    // dispatch.rs calls AppState::notify() — i.e. on actual state changes.
-    let _notify_guard = TaskGuard::new(tokio::task::spawn(async move {
-        while watch_rx.changed().await.is_ok() {
-            if let Some(ref info) = *watch_rx.borrow() {
-                let notification = serde_json::json!({
-                    "jsonrpc": "2.0",
-                    "method": "state_change",
-                    "params": {
-                        "state": info,
-                    }
-                });
-                println!("{}", notification);
-            }
-        }
-    }));
+    let wayland_thread = thread::spawn(move || wayland.run());
 
     // ------------------------------------------------------------------
     // Async bridge: forwards JSON-RPC requests to the wayland thread
     // ------------------------------------------------------------------
     let _bridge_guard = TaskGuard::new(tokio::task::spawn(async move {
         while let Some(request) = request_rx.recv().await {
-            if sync_tx.send(request).is_err() {
+            if let Err(e) = sync_tx.send(request) {
                 tracing::warn!("Wayland thread exited");
-                break;
+                let _ = e.0.response_tx.send(Err(ClientError::Disconnected {
+                    reason: "Server is shutting down".to_string(),
+                }));
             }
         }
     }));
 
-    let server_handler = Arc::new(ServerHandler::new(request_tx));
-
-    io.add_method("info", {
-        let handler = server_handler.clone();
-        move |_params: Params| {
-            handler
-                .clone()
-                .handle_request(BackendRequestParams::GetInfo)
-                .boxed()
-        }
-    });
-
-    io.add_method("move", {
-        let handler = server_handler.clone();
-        move |params: Params| {
-            let handler = handler.clone();
-            async move {
-                let p: MoveParams = params.parse().map_err(|e| invalid_params(&e.to_string()))?;
-                handler.handle_request(BackendRequestParams::Move(p)).await
-            }
-            .boxed()
-        }
-    });
-
-    io.add_method("activate", {
-        let handler = server_handler.clone();
-        move |params: Params| {
-            let handler = handler.clone();
-            async move {
-                let p: ActivateParams =
-                    params.parse().map_err(|e| invalid_params(&e.to_string()))?;
-                handler
-                    .handle_request(BackendRequestParams::Activate(p))
-                    .await
-            }
-            .boxed()
-        }
-    });
-
-    io.add_method("state", {
-        let handler = server_handler.clone();
-        move |params: Params| {
-            let handler = handler.clone();
-            async move {
-                let p: StateParams = params.parse().map_err(|e| invalid_params(&e.to_string()))?;
-                handler.handle_request(BackendRequestParams::State(p)).await
-            }
-            .boxed()
-        }
-    });
-
-    io.add_method("ws_activate", {
-        let handler = server_handler.clone();
-        move |params: Params| {
-            let handler = handler.clone();
-            async move {
-                let p: ActivateWsParams =
-                    params.parse().map_err(|e| invalid_params(&e.to_string()))?;
-                handler
-                    .handle_request(BackendRequestParams::ActivateWs(p))
-                    .await
-            }
-            .boxed()
-        }
-    });
+    let server_handler = Arc::new(ServerHandler::new(request_tx, version, shutdown.clone()));
+    let mut server = std::pin::pin!(serve(server_handler, events_tx));
 
-    io.add_method("close", {
-        let handler = server_handler.clone();
-        move |params: Params| {
-            let handler = handler.clone();
-            async move {
-                let p: CloseParams = params.parse().map_err(|e| invalid_params(&e.to_string()))?;
-                handler.handle_request(BackendRequestParams::Close(p)).await
-            }
-            .boxed()
+    let (served, stopped) = tokio::select! {
+        served = &mut server => (served, false),
+        reason = stop_requested(&shutdown) => {
+            tracing::info!("Stopping: {}", reason?);
+            (Ok(()), true)
         }
-    });
-
-    let server = jsonrpc_stdio_server::ServerBuilder::new(io).build();
-    server.await;
+    };
 
-    Ok(())
+    // The wayland thread finishes the current request and fails the queued ones
+    shutdown.send_replace(true);
+    let wayland = tokio::task::spawn_blocking(move || wayland_thread.join());
+    if stopped {
+        let _ = tokio::time::timeout(SHUTDOWN_GRACE, &mut server).await;
+    }
+    match wayland.await? {
+        Ok(result) => result?,
+        Err(_) => return Err("Wayland thread panicked".into()),
+    }
+    served
 }
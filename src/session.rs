@@ -0,0 +1,295 @@
+//! Window placement snapshots for `cos-cli session save` and `restore`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::client::{MoveParams, StateParams};
+use crate::{ClientError, CosmicClient, JsonApp, State};
+
+/// Placement of one window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionWindow {
+    pub app_id: String,
+    pub title: String,
+    /// Workspace name
+    #[serde(default)]
+    pub workspace: Option<String>,
+    #[serde(default)]
+    pub workspace_index: Option<usize>,
+    #[serde(default)]
+    pub workspace_group: Option<usize>,
+    /// Output name
+    #[serde(default)]
+    pub output: Option<String>,
+    /// Maximized, minimized, fullscreen and sticky
+    #[serde(default)]
+    pub state: Vec<State>,
+    /// Started by `session restore --launch` when no window matches
+    /// (default: `gtk-launch <app_id>`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Session {
+    pub windows: Vec<SessionWindow>,
+}
+
+/// The states a session restores. Focus is not part of the placement.
+const RESTORED_STATES: [State; 4] = [
+    State::Maximized,
+    State::Minimized,
+    State::Fullscreen,
+    State::Sticky,
+];
+
+impl SessionWindow {
+    fn new(app: &JsonApp) -> Self {
+        let workspace = app.workspaces.first();
+        Self {
+            app_id: app.app_id.clone(),
+            title: app.title.clone(),
+            workspace: workspace.map(|w| w.workspace.clone()),
+            workspace_index: workspace.map(|w| w.index),
+            workspace_group: workspace.map(|w| w.group_index),
+            output: app.outputs.first().map(|o| o.name.clone()),
+            state: app
+                .state
+                .iter()
+                .filter(|s| RESTORED_STATES.contains(s))
+                .cloned()
+                .collect(),
+            command: None,
+        }
+    }
+}
+
+/// Similarity of two titles from 0 to 1: the share of words they have in
+/// common, so that "README.md - Editor" still matches "main.rs - Editor"
+/// better than a window of another document set.
+fn similarity(a: &str, b: &str) -> f64 {
+    let words = |s: &str| {
+        s.to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .map(str::to_string)
+            .collect::<HashSet<_>>()
+    };
+    let (a, b) = (words(a), words(b));
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+}
+
+/// [`CosmicClient::match_session`] against the open windows `apps`.
+fn match_windows(
+    windows: &[SessionWindow],
+    apps: &[JsonApp],
+    taken: &HashSet<u32>,
+) -> Vec<Option<JsonApp>> {
+    let mut pairs = Vec::new();
+    for (saved_index, saved) in windows.iter().enumerate() {
+        for (app_index, app) in apps.iter().enumerate() {
+            if !taken.contains(&app.id) && app.app_id.eq_ignore_ascii_case(&saved.app_id) {
+                pairs.push((similarity(&saved.title, &app.title), saved_index, app_index));
+            }
+        }
+    }
+    // Best matches first, ties in saved order
+    pairs.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+
+    let mut matches = vec![None; windows.len()];
+    let mut used = HashSet::new();
+    for (_, saved_index, app_index) in pairs {
+        if matches[saved_index].is_none() && used.insert(app_index) {
+            matches[saved_index] = Some(apps[app_index].clone());
+        }
+    }
+    matches
+}
+
+impl CosmicClient {
+    /// The placement of every window.
+    pub fn session(&self) -> Session {
+        Session {
+            windows: self.windows().iter().map(SessionWindow::new).collect(),
+        }
+    }
+
+    /// Pairs every saved window with an open window of the same app_id
+    /// (case-insensitive) whose title is most similar. Every open window is
+    /// used once; those in `taken` not at all.
+    pub fn match_session(
+        &self,
+        windows: &[SessionWindow],
+        taken: &HashSet<u32>,
+    ) -> Vec<Option<JsonApp>> {
+        match_windows(windows, &self.windows(), taken)
+    }
+
+    /// Moves `app` to the workspace and output of `saved` and sets its
+    /// states, where they differ. Returns the protocol requests sent.
+    pub fn restore_window(
+        &mut self,
+        saved: &SessionWindow,
+        app: &JsonApp,
+    ) -> Result<Vec<String>, ClientError> {
+        let index = self
+            .windows()
+            .iter()
+            .find(|window| window.id == app.id)
+            .map(|window| window.index)
+            .ok_or(ClientError::AppNotFound {
+                app_id: Some(app.app_id.clone()),
+                index: None,
+                id: None,
+            })?;
+
+        let mut requests = Vec::new();
+        if let Some((group, workspace)) = self.saved_workspace(saved) {
+            let output_index = saved
+                .output
+                .as_ref()
+                .and_then(|name| self.outputs().into_iter().find(|o| &o.name == name))
+                .map(|o| o.index);
+            let on_workspace = app
+                .workspaces
+                .iter()
+                .any(|w| w.group_index == group && w.index == workspace);
+            let on_output =
+                output_index.is_none_or(|output| app.outputs.iter().any(|o| o.index == output));
+            if !on_workspace || !on_output {
+                let result = self.move_window(MoveParams {
+                    index: Some(index),
+                    workspace: workspace.into(),
+                    workspace_group: Some(group),
+                    output_index,
+                    ..Default::default()
+                })?;
+                requests.extend(result.requests);
+            }
+        }
+
+        // Set what the saved window has and the open one lacks, unset the reverse
+        let change = |state: State| {
+            let saved = saved.state.contains(&state);
+            let current = app.state.contains(&state);
+            (saved && !current, !saved && current)
+        };
+        let (maximize, unmaximize) = change(State::Maximized);
+        let (minimize, unminimize) = change(State::Minimized);
+        let (fullscreen, unfullscreen) = change(State::Fullscreen);
+        let (sticky, unsticky) = change(State::Sticky);
+        let changes = [
+            maximize,
+            unmaximize,
+            minimize,
+            unminimize,
+            fullscreen,
+            unfullscreen,
+            sticky,
+            unsticky,
+        ];
+        if changes.contains(&true) {
+            let result = self.set_state(StateParams {
+                index: Some(index),
+                maximize,
+                unmaximize,
+                minimize,
+                unminimize,
+                fullscreen,
+                unfullscreen,
+                sticky,
+                unsticky,
+                ..Default::default()
+            })?;
+            requests.extend(result.requests);
+        }
+        Ok(requests)
+    }
+
+    /// The saved workspace as `(group, index)`: by name, preferably in the
+    /// saved group, else by index.
+    fn saved_workspace(&self, saved: &SessionWindow) -> Option<(usize, usize)> {
+        let groups = self.workspaces();
+        let by_name = |name: &str| {
+            let in_group =
+                |g: &&crate::JsonWorkspaceGroup| g.workspaces.iter().any(|w| w.name == name);
+            let group = groups
+                .iter()
+                .filter(in_group)
+                .find(|g| Some(g.index) == saved.workspace_group)
+                .or_else(|| groups.iter().find(in_group))?;
+            let workspace = group.workspaces.iter().find(|w| w.name == name)?;
+            Some((group.index, workspace.index))
+        };
+        saved.workspace.as_deref().and_then(by_name).or_else(|| {
+            let group = groups.get(saved.workspace_group?)?;
+            let workspace = saved
+                .workspace_index
+                .filter(|&i| i < group.workspaces.len())?;
+            Some((group.index, workspace))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(id: u32, app_id: &str, title: &str) -> JsonApp {
+        JsonApp {
+            title: title.into(),
+            ..crate::test_app(id, app_id)
+        }
+    }
+
+    fn saved(app_id: &str, title: &str) -> SessionWindow {
+        SessionWindow::new(&app(0, app_id, title))
+    }
+
+    fn ids(matches: &[Option<JsonApp>]) -> Vec<Option<u32>> {
+        matches
+            .iter()
+            .map(|m| m.as_ref().map(|app| app.id))
+            .collect()
+    }
+
+    #[test]
+    fn similarity_is_the_share_of_common_words() {
+        assert_eq!(similarity("main.rs - Editor", "main.rs - Editor"), 1.0);
+        assert_eq!(similarity("README.md - Editor", "main.rs - Editor"), 0.2);
+        assert_eq!(similarity("Files", "Terminal"), 0.0);
+        assert_eq!(similarity("", ""), 1.0);
+        // Case and punctuation do not matter
+        assert_eq!(similarity("Inbox (3)", "inbox 3"), 1.0);
+    }
+
+    #[test]
+    fn matches_the_most_similar_title() {
+        let windows = [saved("kitty", "~/src - vim"), saved("kitty", "~/notes")];
+        let apps = [app(1, "kitty", "~/notes"), app(2, "Kitty", "~/src - vim")];
+        let matches = match_windows(&windows, &apps, &HashSet::new());
+        assert_eq!(ids(&matches), [Some(2), Some(1)]);
+    }
+
+    #[test]
+    fn uses_every_window_once_and_skips_taken_ones() {
+        let windows = [
+            saved("firefox", "Mail"),
+            saved("firefox", "Mail"),
+            saved("mpv", "video"),
+        ];
+        let apps = [
+            app(1, "firefox", "Mail"),
+            app(2, "firefox", "News"),
+            app(3, "mpv", "video"),
+        ];
+        let matches = match_windows(&windows, &apps, &HashSet::new());
+        assert_eq!(ids(&matches), [Some(1), Some(2), Some(3)]);
+
+        let matches = match_windows(&windows, &apps, &HashSet::from([1, 3]));
+        assert_eq!(ids(&matches), [Some(2), None, None]);
+    }
+}
@@ -0,0 +1,192 @@
+//! `cos-cli wait-for`: blocks until a window condition holds, checking it
+//! again on every state change.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::TryRecvError;
+
+use cos_cli::events::Event;
+use cos_cli::selector::Selector;
+use cos_cli::{JsonApp, State};
+
+use crate::CliError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// A matching window is open
+    Exists,
+    /// No matching window is open
+    Absent,
+    /// The windows matching when waiting started are all closed
+    Closed,
+    /// A matching window has the focus
+    Focused,
+    /// A matching window has the state
+    State(State),
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exists" => Ok(Condition::Exists),
+            "absent" => Ok(Condition::Absent),
+            "closed" => Ok(Condition::Closed),
+            "focused" => Ok(Condition::Focused),
+            state => serde_json::from_value(state.into())
+                .map(Condition::State)
+                .map_err(|_| {
+                    format!(
+                        "Unknown state: {state}. Expected exists, absent, closed, focused or a \
+                         window state"
+                    )
+                }),
+        }
+    }
+}
+
+pub struct WaitArgs {
+    pub selector: Selector,
+    pub condition: Condition,
+    /// Wait forever when not given
+    pub timeout: Option<Duration>,
+}
+
+/// The matching window that satisfies `condition`, `Some(None)` when
+/// `Absent` holds, `None` while the condition does not hold. `Closed` is
+/// tracked by the removal events instead and never holds here.
+fn check(
+    selector: &Selector,
+    condition: &Condition,
+    windows: &[JsonApp],
+) -> Option<Option<JsonApp>> {
+    let mut matching = windows.iter().filter(|app| selector.matches(app));
+    let found = match condition {
+        Condition::Absent => return matching.next().is_none().then_some(None),
+        Condition::Exists => matching.next(),
+        Condition::Focused => matching.find(|app| app.state.contains(&State::Activated)),
+        Condition::State(state) => matching.find(|app| app.state.contains(state)),
+        Condition::Closed => None,
+    };
+    found.cloned().map(Some)
+}
+
+/// Waits until `args.condition` holds and prints the window that satisfied
+/// it as JSON. Returns false on timeout.
+pub fn run(args: WaitArgs) -> Result<bool, Box<dyn Error>> {
+    let mut client = crate::connect()?;
+    let selector = args.selector.with_config(client.config());
+    let deadline = args.timeout.map(|timeout| Instant::now() + timeout);
+    let mut events = client.subscribe();
+
+    // Closed waits for the windows open now, not for ones opened later
+    let mut open = HashSet::new();
+    let mut satisfied = if args.condition == Condition::Closed {
+        open = client
+            .windows()
+            .iter()
+            .filter(|app| selector.matches(app))
+            .map(|app| app.id)
+            .collect::<HashSet<_>>();
+        if open.is_empty() {
+            return Err(CliError::new("No window matches".into()));
+        }
+        None
+    } else {
+        check(&selector, &args.condition, &client.windows())
+    };
+
+    while satisfied.is_none() {
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if remaining.is_some_and(|remaining| remaining.is_zero()) {
+            return Ok(false);
+        }
+        client.dispatch_timeout(remaining)?;
+        let (mut changed, mut lagged) = (false, false);
+        loop {
+            let change = match events.try_recv() {
+                Ok(change) => change,
+                Err(TryRecvError::Lagged(_)) => {
+                    (changed, lagged) = (true, true);
+                    continue;
+                }
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            };
+            changed = true;
+            for event in &change.events {
+                if let Event::AppRemoved { app } = event
+                    && open.remove(&app.id)
+                    && open.is_empty()
+                {
+                    satisfied = Some(Some(app.clone()));
+                }
+            }
+        }
+        if args.condition != Condition::Closed {
+            if changed {
+                satisfied = check(&selector, &args.condition, &client.windows());
+            }
+        } else if lagged && satisfied.is_none() {
+            // The removals may be among the dropped events
+            let windows = client.windows();
+            open.retain(|id| windows.iter().any(|app| app.id == *id));
+            if open.is_empty() {
+                satisfied = Some(None);
+            }
+        }
+    }
+
+    if let Some(Some(app)) = satisfied {
+        println!("{}", serde_json::to_string(&app)?);
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(id: u32, app_id: &str, state: Vec<State>) -> JsonApp {
+        JsonApp {
+            state,
+            ..crate::test_app(id, app_id)
+        }
+    }
+
+    #[test]
+    fn parses_conditions() {
+        assert_eq!("exists".parse(), Ok(Condition::Exists));
+        assert_eq!("absent".parse(), Ok(Condition::Absent));
+        assert_eq!("closed".parse(), Ok(Condition::Closed));
+        assert_eq!("focused".parse(), Ok(Condition::Focused));
+        assert_eq!(
+            "fullscreen".parse(),
+            Ok(Condition::State(State::Fullscreen))
+        );
+        assert!("open".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn checks_the_matching_windows() {
+        let selector: Selector = "firefox".parse().unwrap();
+        let windows = [
+            app(1, "kitty", vec![State::Activated]),
+            app(2, "firefox", vec![]),
+            app(3, "firefox", vec![State::Activated, State::Maximized]),
+        ];
+        let found = |condition| check(&selector, &condition, &windows).map(|app| app.map(|a| a.id));
+        assert_eq!(found(Condition::Exists), Some(Some(2)));
+        assert_eq!(found(Condition::Focused), Some(Some(3)));
+        assert_eq!(found(Condition::State(State::Maximized)), Some(Some(3)));
+        assert_eq!(found(Condition::State(State::Sticky)), None);
+        assert_eq!(found(Condition::Absent), None);
+        assert_eq!(found(Condition::Closed), None);
+        assert!(matches!(
+            check(&selector, &Condition::Absent, &windows[..1]),
+            Some(None)
+        ));
+    }
+}
@@ -0,0 +1,235 @@
+//! `cos-cli watch`: prints state changes and runs hooks on them until
+//! stopped.
+
+use std::error::Error;
+use std::io::{self, Write};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::broadcast::error::TryRecvError;
+
+use cos_cli::events::{Event, EventKind};
+use cos_cli::selector::Selector;
+use cos_cli::{JsonApp, State};
+
+use crate::hooks::HookRunner;
+
+/// How often wayland events are dispatched.
+const POLL: Duration = Duration::from_millis(100);
+
+pub struct WatchArgs {
+    /// Print events as NDJSON
+    pub json: bool,
+    /// Print nothing, only run hooks
+    pub quiet: bool,
+    /// Only print events about matching windows
+    pub filter: Option<Selector>,
+    /// Only print events of these types, all when empty
+    pub events: Vec<EventKind>,
+    /// Exit after this event
+    pub until: Option<Until>,
+    /// `--on EVENT=COMMAND`
+    pub hooks: Vec<(EventKind, String)>,
+    /// Seconds, overrides the config
+    pub timeout: Option<u64>,
+    /// Milliseconds, overrides the config
+    pub interval: Option<u64>,
+}
+
+/// `--until EVENT[:SELECTOR]`: the first event of a type, about a matching
+/// window if a selector is given.
+pub struct Until {
+    kind: EventKind,
+    selector: Option<Selector>,
+}
+
+impl Until {
+    fn matches(&self, event: &Event) -> bool {
+        event.kind() == self.kind
+            && self
+                .selector
+                .as_ref()
+                .is_none_or(|selector| event.app().is_some_and(|app| selector.matches(app)))
+    }
+}
+
+/// Parses an event type, also without its `app_` prefix or `_changed`
+/// suffix: `focus` is `focus_changed`, `workspace` `app_workspace_changed`.
+pub fn parse_kind(kind: &str) -> Result<EventKind, String> {
+    let kind = kind.trim();
+    [
+        kind.to_string(),
+        format!("{kind}_changed"),
+        format!("app_{kind}_changed"),
+    ]
+    .iter()
+    .find_map(|name| name.parse().ok())
+    .ok_or_else(|| format!("Unknown event type: {kind}"))
+}
+
+/// Parses the comma-separated event types of `--events`.
+pub fn parse_kinds(kinds: &str) -> Result<Vec<EventKind>, String> {
+    kinds
+        .split(',')
+        .filter(|kind| !kind.trim().is_empty())
+        .map(parse_kind)
+        .collect()
+}
+
+/// Parses `EVENT[:SELECTOR]` of `--until`.
+pub fn parse_until(until: &str) -> Result<Until, String> {
+    let (kind, selector) = match until.split_once(':') {
+        Some((kind, selector)) => (kind, Some(selector.parse().map_err(|e| format!("{e}"))?)),
+        None => (until, None),
+    };
+    Ok(Until {
+        kind: parse_kind(kind)?,
+        selector,
+    })
+}
+
+/// Parses `EVENT=COMMAND` of `--on`.
+pub fn parse_hook(hook: &str) -> Result<(EventKind, String), String> {
+    let (kind, command) = hook
+        .split_once('=')
+        .ok_or_else(|| format!("Expected EVENT=COMMAND, got '{hook}'"))?;
+    Ok((parse_kind(kind)?, command.to_string()))
+}
+
+/// Prints the state changes selected by `args` and runs the hooks of the
+/// config and `args` on every change. Runs until the `until` event or until
+/// the compositor connection is lost.
+pub fn run(args: WatchArgs) -> Result<(), Box<dyn Error>> {
+    let mut client = crate::connect()?;
+    let filter = args.filter.map(|f| f.with_config(client.config()));
+    let until = args.until.map(|until| Until {
+        selector: until.selector.map(|s| s.with_config(client.config())),
+        ..until
+    });
+
+    let mut runner = HookRunner::with_config(&client, args.hooks, args.timeout, args.interval);
+
+    let mut events = client.subscribe();
+    loop {
+        client.dispatch()?;
+        loop {
+            let change = match events.try_recv() {
+                Ok(change) => change,
+                Err(TryRecvError::Lagged(skipped)) => {
+                    tracing::warn!("Dropped {skipped} state changes");
+                    continue;
+                }
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            };
+            for event in &change.events {
+                // Hooks see every event, the filters are for printing
+                runner.handle(event);
+                let shown = (args.events.is_empty() || args.events.contains(&event.kind()))
+                    && filter
+                        .as_ref()
+                        .is_none_or(|f| event.app().is_some_and(|app| f.matches(app)));
+                if shown && !args.quiet {
+                    let line = if args.json {
+                        serde_json::to_string(event)?
+                    } else {
+                        describe(event)
+                    };
+                    match writeln!(io::stdout().lock(), "{line}") {
+                        // The reader went away, e.g. `watch | head -1`
+                        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                        result => result?,
+                    }
+                }
+                if until.as_ref().is_some_and(|until| until.matches(event)) {
+                    return Ok(());
+                }
+            }
+        }
+        thread::sleep(POLL);
+    }
+}
+
+fn window(app: &JsonApp) -> String {
+    format!("{} [{}] \"{}\"", app.app_id, app.id, app.title)
+}
+
+fn states(states: &[State]) -> String {
+    let states = states.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    format!("[{}]", states.join(", "))
+}
+
+/// One line describing `event`, starting with its type.
+fn describe(event: &Event) -> String {
+    let details = match event {
+        Event::AppAdded { app }
+        | Event::AppRemoved { app }
+        | Event::AppIdChanged { app }
+        | Event::TitleChanged { app }
+        | Event::FocusChanged { app } => window(app),
+        Event::AppStateChanged { app, previous } => format!(
+            "{}: {} -> {}",
+            window(app),
+            states(previous),
+            states(&app.state)
+        ),
+        Event::AppWorkspaceChanged { app } => {
+            let workspaces = app
+                .workspaces
+                .iter()
+                .map(|w| format!("{}.{} \"{}\"", w.group_index, w.index, w.workspace))
+                .collect::<Vec<_>>();
+            format!("{}: {}", window(app), workspaces.join(", "))
+        }
+        Event::AppOutputChanged { app } => {
+            let outputs = app
+                .outputs
+                .iter()
+                .map(|o| o.name.as_str())
+                .collect::<Vec<_>>();
+            format!("{}: {}", window(app), outputs.join(", "))
+        }
+        Event::OutputAdded { output } | Event::OutputRemoved { output } => {
+            let model = format!("{} {}", output.make, output.model);
+            match model.trim() {
+                "" => output.name.clone(),
+                model => format!("{} ({model})", output.name),
+            }
+        }
+        Event::SeatAdded { seat } => seat.name.clone(),
+        Event::WorkspaceActivated { workspace } => format!(
+            "{}.{} \"{}\"",
+            workspace.group_index, workspace.index, workspace.workspace
+        ),
+        Event::WorkspacesChanged => String::new(),
+    };
+    format!("{} {details}", event.kind()).trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_without_prefix_and_suffix() {
+        assert_eq!(parse_kind("app_added"), Ok(EventKind::AppAdded));
+        assert_eq!(parse_kind(" focus "), Ok(EventKind::FocusChanged));
+        assert_eq!(parse_kind("title"), Ok(EventKind::TitleChanged));
+        assert_eq!(parse_kind("workspace"), Ok(EventKind::AppWorkspaceChanged));
+        assert!(parse_kind("focused").is_err());
+        assert_eq!(
+            parse_kinds("state,,output_added"),
+            Ok(vec![EventKind::AppStateChanged, EventKind::OutputAdded])
+        );
+    }
+
+    #[test]
+    fn hooks_and_until() {
+        assert_eq!(
+            parse_hook("app_added=echo a=b"),
+            Ok((EventKind::AppAdded, "echo a=b".to_string()))
+        );
+        assert!(parse_hook("app_added").is_err());
+        let until = parse_until("app_removed:firefox").unwrap();
+        assert_eq!(until.kind, EventKind::AppRemoved);
+        assert_eq!(until.selector.unwrap().app_id.as_deref(), Some("firefox"));
+    }
+}